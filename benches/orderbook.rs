@@ -0,0 +1,217 @@
+#[macro_use]
+extern crate criterion;
+extern crate rsex;
+
+use criterion::{black_box, BatchSize, Criterion};
+use rsex::binance::ladder::LadderBook;
+use rsex::binance::orderbook::LocalOrderBook;
+use rsex::binance::types::{DepthOrderbookEvent, RawOrderbook};
+use rsex::models::{Ask, Bid, Orderbook};
+use rsex::replay::read_recording;
+use serde_json::{json, Value};
+use std::env;
+
+// RSEX_DEPTH_FILE is the directory a Recorder wrote, e.g. with an OrderBookManager on
+// btcusdt@depth@100ms. The depth diffs of the first symbol in it are replayed, starting from
+// its first recorded snapshot or from an empty book without one.
+// RSEX_TICK_SIZE is the symbol's tick size (0.01 by default).
+// Without a recording a seeded btcusdt-like stream is generated instead.
+fn load() -> (Orderbook, Vec<DepthOrderbookEvent>, f64) {
+    let tick_size = env::var("RSEX_TICK_SIZE")
+        .ok()
+        .and_then(|tick_size| tick_size.parse().ok())
+        .unwrap_or(0.01);
+    match env::var("RSEX_DEPTH_FILE") {
+        Ok(path) => {
+            let (snapshot, events) = recorded(&path);
+            (snapshot, events, tick_size)
+        }
+        Err(_) => {
+            let (snapshot, events) = synthetic();
+            (snapshot, events, 0.01)
+        }
+    }
+}
+
+fn recorded(dir: &str) -> (Orderbook, Vec<DepthOrderbookEvent>) {
+    let frames = read_recording(dir, "binance").expect("read RSEX_DEPTH_FILE");
+    let mut symbol: Option<String> = None;
+    let mut snapshot: Option<Orderbook> = None;
+    let mut events = vec![];
+    for frame in &frames {
+        let stream_symbol = frame.stream.split('@').next().unwrap_or("");
+        if symbol.as_deref().map_or(false, |symbol| symbol != stream_symbol) {
+            continue;
+        }
+        if frame.stream.ends_with("@snapshot") {
+            if snapshot.is_none() {
+                let raw: RawOrderbook = serde_json::from_str(&frame.frame).expect("parse snapshot");
+                snapshot = Some(raw.into());
+                symbol = Some(stream_symbol.to_string());
+            }
+            continue;
+        }
+        let mut value: Value = match serde_json::from_str(&frame.frame) {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        if value.get("data").is_some() {
+            value = value["data"].take();
+        }
+        if let Ok(event) = serde_json::from_value::<DepthOrderbookEvent>(value) {
+            symbol = Some(stream_symbol.to_string());
+            events.push(event);
+        }
+    }
+    assert!(!events.is_empty(), "no depthUpdate frames in {}", dir);
+    match snapshot {
+        Some(snapshot) => {
+            // diffs the snapshot already contains
+            events.retain(|event| event.final_update_id > snapshot.timestamp);
+            (snapshot, events)
+        }
+        // every level the recording touches is rebuilt from the diffs
+        None => {
+            let snapshot = Orderbook {
+                timestamp: events[0].first_update_id - 1,
+                bids: vec![],
+                asks: vec![],
+            };
+            (snapshot, events)
+        }
+    }
+}
+
+// xorshift, enough to make the stream repeatable without a rand dependency
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> i64 {
+        (self.next() % n) as i64
+    }
+}
+
+// 1000 levels a side around 50000, then 2000 diffs of 10-30 levels while the mid drifts,
+// levels the mid moves through are removed so the book never crosses
+fn synthetic() -> (Orderbook, Vec<DepthOrderbookEvent>) {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    let price = |tick: i64| format!("{}.{:02}", tick / 100, tick % 100);
+    let mut mid: i64 = 5_000_000;
+    let mut bids = vec![];
+    let mut asks = vec![];
+    let (mut bid, mut ask) = (mid, mid);
+    for _ in 0..1000 {
+        bid -= 1 + rng.below(3);
+        ask += 1 + rng.below(3);
+        bids.push(Bid {
+            price: price(bid).parse().unwrap(),
+            amount: (1 + rng.below(200)) as f64 / 100.0,
+        });
+        asks.push(Ask {
+            price: price(ask).parse().unwrap(),
+            amount: (1 + rng.below(200)) as f64 / 100.0,
+        });
+    }
+    let snapshot = Orderbook {
+        timestamp: 1000,
+        bids,
+        asks,
+    };
+
+    let mut events = vec![];
+    for id in 1001..3001u64 {
+        let mut bids = vec![];
+        let mut asks = vec![];
+        let moved = mid + rng.below(21) - 10;
+        for tick in mid.min(moved)..=mid.max(moved) {
+            if moved > mid {
+                asks.push(json!([price(tick), "0"]));
+            } else {
+                bids.push(json!([price(tick), "0"]));
+            }
+        }
+        mid = moved;
+        for _ in 0..10 + rng.below(20) {
+            let offset = 1 + rng.below(300);
+            let qty = if rng.below(5) == 0 {
+                "0".to_string()
+            } else {
+                ((1 + rng.below(200)) as f64 / 100.0).to_string()
+            };
+            if rng.below(2) == 0 {
+                bids.push(json!([price(mid - offset), qty]));
+            } else {
+                asks.push(json!([price(mid + offset), qty]));
+            }
+        }
+        let event = json!({
+            "e": "depthUpdate",
+            "E": id,
+            "s": "BTCUSDT",
+            "U": id,
+            "u": id,
+            "b": bids,
+            "a": asks,
+        });
+        events.push(serde_json::from_value(event).unwrap());
+    }
+    (snapshot, events)
+}
+
+fn bench_orderbook(c: &mut Criterion) {
+    let (snapshot, events, tick_size) = load();
+    let tree = LocalOrderBook::from_snapshot("BTCUSDT", snapshot.clone());
+    let ladder = LadderBook::from_snapshot("BTCUSDT", tick_size, &snapshot);
+
+    let mut group = c.benchmark_group("apply");
+    group.bench_function("tree", |b| {
+        b.iter_batched(
+            || tree.clone(),
+            |mut book| {
+                for event in &events {
+                    book.apply(event);
+                }
+                book
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("ladder", |b| {
+        b.iter_batched(
+            || ladder.clone(),
+            |mut book| {
+                for event in &events {
+                    book.apply(event);
+                }
+                book
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+
+    let mut tree = tree;
+    let mut ladder = ladder;
+    for event in &events {
+        tree.apply(event);
+        ladder.apply(event);
+    }
+    for amount in &[0.01, 1.0, 50.0] {
+        let mut group = c.benchmark_group(format!("query_{}", amount));
+        group.bench_function("tree/buy", |b| b.iter(|| tree.query_buy_price(black_box(*amount)).ok()));
+        group.bench_function("ladder/buy", |b| b.iter(|| ladder.query_buy_price(black_box(*amount)).ok()));
+        group.bench_function("tree/sell", |b| b.iter(|| tree.query_sell_price(black_box(*amount)).ok()));
+        group.bench_function("ladder/sell", |b| b.iter(|| ladder.query_sell_price(black_box(*amount)).ok()));
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_orderbook);
+criterion_main!(benches);
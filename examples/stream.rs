@@ -1,6 +1,6 @@
 extern crate rsex;
 
-use rsex::binance::spot_ws::{BinanceWs, LocalOrderBook};
+use rsex::binance::orderbook::OrderBookManager;
 use std::{thread};
 // use log::{info, warn};
 use std::time::Duration;
@@ -36,10 +36,12 @@ fn main() {
     // ws.connect(handler);
     // println!("connected");
     // ws.sub_ticker(&symbol);
-    thread::spawn(move || {
-        // BinanceWs::build_local_orderbook("wss://stream.binance.com:9443","https://api.binance.com",vec!["btcusdt"]);//, "ethusdt"
-        BinanceWs::build_local_orderbook("wss://testnet.binance.vision","https://testnet.binance.vision",vec![ String::from("btcusdt"),String::from("ethusdt"),String::from("eosusdt")]);
-    });
+    // let manager = OrderBookManager::new("https://api.binance.com", "wss://stream.binance.com:9443");
+    let manager = OrderBookManager::new("https://testnet.binance.vision", "wss://testnet.binance.vision");
+    for symbol in &["btcusdt", "ethusdt", "eosusdt"] {
+        manager.add_symbol(symbol);
+    }
     thread::sleep(Duration::from_millis(150000));
-    LocalOrderBook::print_order_book("btcusdt");
+    manager.print_order_book("btcusdt");
+    manager.shutdown();
 }
@@ -193,14 +193,7 @@ impl FutureRest for BinanceSwap {
         let resp: Vec<Vec<Value>> = serde_json::from_str(&ret)?;
         let klines = resp
             .iter()
-            .map(|kline| Kline {
-                timestamp: to_i64(&kline[0]) as u64,
-                open: to_f64(&kline[1]),
-                high: to_f64(&kline[2]),
-                low: to_f64(&kline[3]),
-                close: to_f64(&kline[4]),
-                volume: to_f64(&kline[5]),
-            })
+            .map(|kline| KlineSummary::from(kline).into())
             .collect::<Vec<Kline>>();
 
         Ok(klines)
@@ -1,150 +1,276 @@
+use crate::binance::spot_ws::{stream_channel, BinanceWsHandle, Channel, MessageHeader, ResponseEvent, EVENT_CHANNELS};
 use crate::binance::types::*;
 use crate::errors::*;
 use crate::models::*;
 use crate::traits::*;
+use crate::websocket::*;
+use futures_channel::mpsc::UnboundedReceiver;
+use log::{info, warn};
+use serde_json::{json, Value};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 
-use ws::{Handler, Handshake, Message, Result, Sender};
-
-//static WEBSOCKET_URL: &str = "wss://stream.binance.com:9443/ws/btcusdt@depth20";
+//static WEBSOCKET_URL: &str = "wss://fstream.binance.com/ws";
 
 #[derive(Debug)]
 pub enum WsEvent {
+    // connection lifecycle
+    Connected,
+    Disconnected(String),
+    Resubscribed(usize),
+
     // public stream
     OrderbookEvent(Orderbook),
     KlineEvent(Kline),
     TickerEvent(Ticker),
     TradeEvent(Trade),
     ResponseEvent(ResponseEvent),
+    // stream or event type we have no typed struct for, with its payload
+    RawEvent(String, Value),
 
     // private stream
     AccountUpdateEvent(AccountUpdateEvent),
     OrderTradeEvent(OrderTradeEvent),
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
-pub struct ResponseEvent {
-    id: u64,
-    result: Option<String>,
-}
-
 pub struct BinanceWs<'a> {
     host: String,
-    subs: Vec<String>,
-    out: Option<Sender>,
+    state: Arc<Mutex<SubState>>,
+    out: Option<Outbox>,
+    config: ReconnectConfig,
+    ws_config: WsConfig,
+    resubscribe: bool,
 
-    handler: Box<dyn FnMut(WsEvent) -> Result<()> + 'a>,
+    handler: Box<dyn FnMut(WsEvent) -> APIResult<()> + 'a>,
 }
 
 impl<'a> BinanceWs<'a> {
     pub fn new(host: &str) -> Self {
         BinanceWs {
             host: host.into(),
-            subs: vec![],
+            state: Arc::new(Mutex::new(SubState::new())),
             out: None,
+            config: ReconnectConfig::binance(),
+            ws_config: WsConfig::default(),
+            resubscribe: false,
             handler: Box::new(|event| {
-                println!("event: {:?}", event);
+                info!("event: {:?}", event);
                 Ok(())
             }),
         }
     }
 
-    pub fn connect<Callback: Clone>(&mut self, handler: Callback)
+    pub fn set_reconnect_config(&mut self, config: ReconnectConfig) {
+        self.config = config;
+    }
+
+    // tls backend, proxy and ping interval
+    pub fn set_ws_config(&mut self, config: WsConfig) {
+        self.ws_config = config;
+    }
+
+    // the handle is Send, use it from other threads while `connect` blocks
+    pub fn handle(&self) -> BinanceWsHandle {
+        BinanceWsHandle::new(self.state.clone())
+    }
+
+    // blocks until the handle is shut down, see run_supervised
+    pub fn connect<Callback>(&mut self, handler: Callback)
     where
-        Callback: FnMut(WsEvent) -> Result<()> + 'a,
+        Callback: FnMut(WsEvent) -> APIResult<()> + 'a,
     {
-        ws::connect(self.host.clone(), |out| BinanceWs {
-            host: self.host.clone(),
-            subs: self.subs.clone(),
-            out: Some(out),
-            handler: Box::new(handler.clone()),
-        })
-        .unwrap();
+        self.handler = Box::new(handler);
+        self.resubscribe = false;
+        let host = self.host.clone();
+        let (ws_config, config) = (self.ws_config.clone(), self.config.clone());
+        run_supervised(self, &host, ws_config, &config);
+    }
+
+    // runs the connection on its own thread and delivers events over a channel,
+    // the channel closes once the handle is shut down
+    pub fn connect_channel(&self) -> mpsc::Receiver<WsEvent> {
+        spawn_channel(&self.state, |handler| self.spawn(handler))
+    }
+
+    // same as `connect_channel`, as a futures Stream for async consumers
+    pub fn connect_stream(&self) -> UnboundedReceiver<WsEvent> {
+        spawn_stream(&self.state, |handler| self.spawn(handler))
+    }
+
+    fn spawn<Callback>(&self, handler: Callback) -> Option<thread::JoinHandle<()>>
+    where
+        Callback: FnMut(WsEvent) -> APIResult<()> + Send + 'static,
+    {
+        let host = self.host.clone();
+        let state = self.state.clone();
+        let config = self.config.clone();
+        let ws_config = self.ws_config.clone();
+        thread::Builder::new()
+            .name("binance-future-ws".into())
+            .spawn(move || {
+                let mut ws = BinanceWs::new(&host);
+                ws.state = state;
+                ws.config = config;
+                ws.ws_config = ws_config;
+                ws.connect(handler);
+            })
+            .ok()
     }
 
     fn deseralize(&self, s: &str) -> APIResult<WsEvent> {
-        if s.find("result") != None {
-            let resp: ResponseEvent = serde_json::from_str(s)?;
-            return Ok(WsEvent::ResponseEvent(resp));
+        let header: MessageHeader = serde_json::from_str(s)?;
+        if let Some(stream) = header.stream {
+            let mut val: Value = serde_json::from_str(s)?;
+            let data = val["data"].take();
+            return match stream_channel(&stream) {
+                Some(channel) => deseralize_channel(channel, data),
+                None => Ok(WsEvent::RawEvent(stream, data)),
+            };
+        }
+
+        // raw streams carry no stream name, fall back to the event type or the payload shape
+        let channel = match &header.event_type {
+            // every futures depth payload is a depthUpdate, partial books only come from sub_orderbook
+            Some(event_type) if event_type == "depthUpdate" && self.partial_depth_only() => {
+                Some(Channel::PartialDepth)
+            }
+            Some(event_type) => EVENT_CHANNELS.get(event_type.as_str()).copied(),
+            None if header.id.is_some() => {
+                let resp: ResponseEvent = serde_json::from_str(s)?;
+                return Ok(WsEvent::ResponseEvent(resp));
+            }
+            None if header.update_id.is_some() => Some(Channel::BookTicker),
+            None => None,
+        };
+        let val: Value = serde_json::from_str(s)?;
+        match channel {
+            Some(channel) => deseralize_channel(channel, val),
+            None => Ok(WsEvent::RawEvent(header.event_type.unwrap_or_default(), val)),
         }
-        //let val: Value = serde_json::from_str(s)?;
-        if s.find("kline") != None {
-            let resp: KlineEvent = serde_json::from_str(&s)?;
+    }
+
+    // depth payloads are partial books while no diff depth stream is subscribed
+    fn partial_depth_only(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        let channels = state.subs.iter().map(|sub| stream_channel(sub)).collect::<Vec<_>>();
+        channels.contains(&Some(Channel::PartialDepth)) && !channels.contains(&Some(Channel::DepthUpdate))
+    }
+}
+
+fn deseralize_channel(channel: Channel, val: Value) -> APIResult<WsEvent> {
+    match channel {
+        Channel::Kline => {
+            let resp: KlineEvent = serde_json::from_value(val)?;
             Ok(WsEvent::KlineEvent(resp.kline.into()))
-        } else if s.find("lastUpdateId") != None {
-            let resp: RawOrderbook = serde_json::from_str(&s)?;
+        }
+        Channel::PartialDepth => {
+            let resp: DepthOrderbookEvent = serde_json::from_value(val)?;
             Ok(WsEvent::OrderbookEvent(resp.into()))
-        } else if s.find("aggTrade") != None {
-            let resp: TradeEvent = serde_json::from_str(&s)?;
+        }
+        Channel::AggTrade => {
+            let resp: TradeEvent = serde_json::from_value(val)?;
             Ok(WsEvent::TradeEvent(resp.into()))
-        } else if s.find("A") != None && s.find("B") != None {
-            let resp: BookTickerEvent = serde_json::from_str(&s)?;
+        }
+        Channel::BookTicker | Channel::AllBookTickers => {
+            let resp: BookTickerEvent = serde_json::from_value(val)?;
             Ok(WsEvent::TickerEvent(resp.into()))
-        } else {
-            Err(Box::new(ExError::ApiError("msg channel not found".into())))
+        }
+        _ => {
+            let event_type = val["e"].as_str().unwrap_or_default().to_string();
+            Ok(WsEvent::RawEvent(event_type, val))
         }
     }
 }
 
-impl<'a> SpotWs for BinanceWs<'a> {
-    fn sub_kline(&mut self, symbol: &str, period: &str) {
-        self.subs.push(format!(
-            "{{\"method\": \"SUBSCRIBE\", \"params\": [\"{}@kline_{}\"], \"id\": {}}}",
-            symbol.to_string().to_lowercase(),
-            period,
-            self.subs.len() + 1,
-        ));
+impl<'a> FutureWs for BinanceWs<'a> {
+    fn sub_kline(&self, symbol: &str, period: &str) -> Ack<()> {
+        let stream = format!("{}@kline_{}", symbol.to_lowercase(), period);
+        self.handle().subscribe(&[&stream])
+    }
+
+    fn sub_orderbook(&self, symbol: &str) -> Ack<()> {
+        let stream = format!("{}@depth20", symbol.to_lowercase());
+        self.handle().subscribe(&[&stream])
     }
 
-    fn sub_orderbook(&mut self, symbol: &str) {
-        self.subs.push(format!(
-            "{{\"method\": \"SUBSCRIBE\", \"params\": [\"{}@depth20\"], \"id\": {}}}",
-            symbol.to_string().to_lowercase(),
-            self.subs.len() + 1,
-        ));
+    fn sub_trade(&self, symbol: &str) -> Ack<()> {
+        let stream = format!("{}@aggTrade", symbol.to_lowercase());
+        self.handle().subscribe(&[&stream])
     }
 
-    fn sub_trade(&mut self, symbol: &str) {
-        self.subs.push(format!(
-            "{{\"method\": \"SUBSCRIBE\", \"params\": [\"{}@aggTrade\"], \"id\": {}}}",
-            symbol.to_string().to_lowercase(),
-            self.subs.len() + 1,
-        ));
+    fn sub_ticker(&self, symbol: &str) -> Ack<()> {
+        let stream = format!("{}@bookTicker", symbol.to_lowercase());
+        self.handle().subscribe(&[&stream])
     }
 
-    fn sub_ticker(&mut self, symbol: &str) {
-        self.subs.push(format!(
-            "{{\"method\": \"SUBSCRIBE\", \"params\": [\"{}@bookTicker\"], \"id\": {}}}",
-            symbol.to_string().to_lowercase(),
-            self.subs.len() + 1,
-        ));
+    fn sub_order_update(&self, _symbol: &str) -> Ack<()> {
+        // the user data stream is a separate connection on a listen key
+        Ack::failed(ExError::ApiError("order updates need a listen key".into()))
     }
+}
 
-    fn sub_order_update(&mut self, _symbol: &str) {
-        unimplemented!()
+impl<'a> Supervised for BinanceWs<'a> {
+    fn state(&self) -> Arc<Mutex<SubState>> {
+        self.state.clone()
+    }
+
+    fn on_disconnect(&mut self, reason: String) {
+        self.out = None;
+        let _ = (self.handler)(WsEvent::Disconnected(reason));
+        self.resubscribe = true;
     }
 }
 
 impl<'a> Handler for BinanceWs<'a> {
-    fn on_open(&mut self, _shake: Handshake) -> Result<()> {
-        match &self.out {
-            Some(out) => self.subs.iter().for_each(|s| {
-                let _ = out.send(s.as_str());
-            }),
-            None => {
-                println!("self.out is None");
+    fn on_open(&mut self, out: Outbox) -> APIResult<()> {
+        let _ = (self.handler)(WsEvent::Connected);
+        let resubscribed = {
+            let mut state = self.state.lock().unwrap();
+            if state.shutdown {
+                out.close();
+                return Ok(());
             }
+            state.out = Some(out.clone());
+            let deferred = state.take_deferred();
+            if state.subs.is_empty() {
+                deferred
+                    .iter()
+                    .for_each(|(id, _)| { state.resolve(id, Ok(Value::Null)); });
+            } else {
+                // one request for all streams, binance limits incoming messages to 10 per second
+                let id = state.next_id();
+                let msg = json!({"method": "SUBSCRIBE", "params": state.subs, "id": id});
+                deferred
+                    .iter()
+                    .for_each(|(from, _)| state.merge_pending(from, &id.to_string()));
+                out.send(msg.to_string());
+            }
+            for msg in state.take_queued() {
+                out.send(msg);
+            }
+            state.subs.len()
+        };
+        self.out = Some(out);
+        if self.resubscribe {
+            let _ = (self.handler)(WsEvent::Resubscribed(resubscribed));
         }
         Ok(())
     }
 
-    fn on_message(&mut self, msg: Message) -> Result<()> {
-        //println!("{:?}", msg);
+    fn on_message(&mut self, msg: Message) -> APIResult<()> {
         match self.deseralize(&msg.to_string()) {
             Ok(event) => {
+                if let WsEvent::ResponseEvent(resp) = &event {
+                    let ret = match &resp.error {
+                        Some(err) => Err(ExError::ApiError(format!("{}: {}", err.code, err.msg))),
+                        None => Ok(resp.result.clone().unwrap_or(Value::Null)),
+                    };
+                    self.state.lock().unwrap().resolve(&resp.id.to_string(), ret);
+                }
                 let _ = (self.handler)(event);
             }
             Err(err) => {
-                println!("deseralize msg error: {:?}", err);
+                warn!("deseralize msg error: {:?}", err);
             }
         }
         Ok(())
@@ -154,28 +280,115 @@ impl<'a> Handler for BinanceWs<'a> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::utils::get_timestamp;
+
+    static WEBSOCKET_URL: &str = "wss://fstream.binance.com/ws";
 
     #[test]
-    fn test_binancews() {
-        env_logger::init();
+    fn test_deseralize() {
+        let binance = BinanceWs::new(WEBSOCKET_URL);
+        binance.sub_ticker("BTCUSDT");
+        assert_eq!(binance.state.lock().unwrap().subs, vec!["btcusdt@bookTicker"]);
 
-        let handler = |event: WsEvent| {
-            match event {
-                WsEvent::OrderbookEvent(e) => {
-                    println!("orderbook: {:?}", e);
-                }
-                _ => {
-                    println!("event: {:?}", event);
-                }
+        let ticker = r#"{"e":"bookTicker","u":400900217,"E":1568014460893,"T":1568014460891,
+            "s":"BNBUSDT","b":"25.35190000","B":"31.21000000","a":"25.36520000","A":"40.66000000"}"#;
+        match binance.deseralize(ticker).unwrap() {
+            WsEvent::TickerEvent(ticker) => assert_eq!(ticker.bid.price, 25.3519),
+            event => panic!("expect ticker event: {:?}", event),
+        }
+        match binance.deseralize(r#"{"result":null,"id":1}"#).unwrap() {
+            WsEvent::ResponseEvent(resp) => assert_eq!(resp.id, 1),
+            event => panic!("expect response event: {:?}", event),
+        }
+
+        // routed by event type, not by what the payload happens to contain
+        let ticker = r#"{"e":"bookTicker","u":400900217,"E":1568014460893,"T":1568014460891,
+            "s":"RESULTUSDT","b":"1.5","B":"31.21","a":"1.6","A":"40.66"}"#;
+        assert!(matches!(binance.deseralize(ticker).unwrap(), WsEvent::TickerEvent(_)));
+        let mark = r#"{"e":"markPriceUpdate","E":1562305380000,"s":"BTCUSDT","p":"11794.15","r":"0.00038167"}"#;
+        match binance.deseralize(mark).unwrap() {
+            WsEvent::RawEvent(event_type, val) => {
+                assert_eq!((event_type.as_str(), val["p"].as_str()), ("markPriceUpdate", Some("11794.15")))
             }
-            Ok(())
-        };
+            event => panic!("expect raw event: {:?}", event),
+        }
+
+        let depth = r#"{"e":"depthUpdate","E":1571889248277,"T":1571889248276,"s":"BTCUSDT","U":390497796,
+            "u":390497878,"pu":390497794,"b":[["7403.89","0.002"]],"a":[["7405.96","3.340"]]}"#;
+        assert!(matches!(binance.deseralize(depth).unwrap(), WsEvent::RawEvent(..)));
+        binance.sub_orderbook("BTCUSDT");
+        match binance.deseralize(depth).unwrap() {
+            WsEvent::OrderbookEvent(book) => assert_eq!((book.bids[0].price, book.asks[0].amount), (7403.89, 3.34)),
+            event => panic!("expect orderbook event: {:?}", event),
+        }
+        let combined = format!(r#"{{"stream":"btcusdt@depth20","data":{}}}"#, depth);
+        assert!(matches!(binance.deseralize(&combined).unwrap(), WsEvent::OrderbookEvent(_)));
+    }
+
+    #[test]
+    fn test_subscription_ack() {
         let mut binance = BinanceWs::new(WEBSOCKET_URL);
-        //binance.sub_orderbook("btcusdt");
-        binance.sub_ticker("btcusdt");
-        binance.sub_kline("btcusdt", "5m");
-        binance.sub_trade("btcusdt");
-        binance.connect(handler);
+        let handle = binance.handle();
+        let sub = handle.subscribe(&["btcusdt@aggTrade", "ethusdt@aggTrade"]);
+        let unsub = handle.unsubscribe(&["ethusdt@aggTrade"]);
+        let list = handle.list_subscriptions();
+        assert_eq!(handle.subscriptions(), vec!["btcusdt@aggTrade"]);
+
+        // deferred sub/unsub are acked by the resubscription, queued requests go out after it
+        binance.on_open(Outbox::detached()).unwrap();
+        binance.on_message(Message::Text(r#"{"result":null,"id":4}"#.into())).unwrap();
+        assert!(sub.wait().is_ok());
+        assert!(unsub.wait().is_ok());
+        assert!(list.try_get().is_none());
+        let resp = r#"{"result":["btcusdt@aggTrade"],"id":3}"#;
+        binance.on_message(Message::Text(resp.into())).unwrap();
+        assert_eq!(list.wait().unwrap(), vec!["btcusdt@aggTrade"]);
+
+        let ack = handle.subscribe(&["btcusdt@kline_1m"]);
+        let resp = r#"{"error":{"code":2,"msg":"Invalid request"},"id":5}"#;
+        binance.on_message(Message::Text(resp.into())).unwrap();
+        assert!(ack.wait().is_err());
+        assert!(binance.sub_order_update("BTCUSDT").wait().is_err());
+    }
+
+    #[test]
+    fn test_connect_channel_and_stream() {
+        use async_tungstenite::tungstenite::accept;
+        use std::net::TcpListener;
+        use std::time::Duration;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://127.0.0.1:{}/ws", listener.local_addr().unwrap().port());
+        let server = std::thread::spawn(move || {
+            let ticker = r#"{"e":"bookTicker","u":1,"E":1,"T":1,"s":"BTCUSDT","b":"1.5","B":"2","a":"1.6","A":"3"}"#;
+            for _ in 0..2 {
+                let (conn, _) = listener.accept().unwrap();
+                let mut ws = accept(conn).unwrap();
+                ws.write_message(Message::Text(ticker.into())).unwrap();
+                while ws.read_message().is_ok() {}
+            }
+        });
+
+        let binance = BinanceWs::new(&url);
+        let events = binance.connect_channel();
+        let timeout = Duration::from_secs(5);
+        assert!(matches!(events.recv_timeout(timeout), Ok(WsEvent::Connected)));
+        assert!(matches!(events.recv_timeout(timeout), Ok(WsEvent::TickerEvent(_))));
+        binance.handle().shutdown();
+        assert!(matches!(events.recv_timeout(timeout), Ok(WsEvent::Disconnected(_))));
+        assert!(events.recv_timeout(timeout).is_err());
+
+        let binance = BinanceWs::new(&url);
+        let mut events = binance.connect_stream();
+        let mut received = vec![];
+        while received.len() < 2 {
+            match events.try_next() {
+                Ok(Some(event)) => received.push(event),
+                Ok(None) => break,
+                Err(_) => std::thread::sleep(Duration::from_millis(10)),
+            }
+        }
+        assert!(matches!(received[..], [WsEvent::Connected, WsEvent::TickerEvent(_)]));
+        binance.handle().shutdown();
+        server.join().unwrap();
     }
 }
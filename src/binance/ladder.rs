@@ -0,0 +1,419 @@
+use crate::binance::orderbook::fill_price;
+use crate::binance::types::*;
+use crate::errors::*;
+use crate::models::*;
+
+use std::collections::btree_map::{self, BTreeMap};
+use std::ops::Bound;
+
+// ticks held in the dense part of each side, 128KB per side
+const DEFAULT_WINDOW: usize = 1 << 14;
+
+// price <-> integer tick, 0.01 is not exact in f64 but dividing by 100 is
+#[derive(Debug, Clone, Copy)]
+struct TickScale {
+    tick_size: f64,
+    per_unit: f64,
+}
+
+impl TickScale {
+    fn new(tick_size: f64) -> Self {
+        let per_unit = if tick_size < 1.0 { (1.0 / tick_size).round() } else { 0.0 };
+        TickScale { tick_size, per_unit }
+    }
+
+    fn tick(&self, price: f64) -> i64 {
+        if self.per_unit > 0.0 {
+            (price * self.per_unit).round() as i64
+        } else {
+            (price / self.tick_size).round() as i64
+        }
+    }
+
+    fn price(&self, tick: i64) -> f64 {
+        self.fractional_price(tick as f64)
+    }
+
+    // averages are taken in ticks and converted once
+    fn fractional_price(&self, ticks: f64) -> f64 {
+        if self.per_unit > 0.0 {
+            ticks / self.per_unit
+        } else {
+            ticks * self.tick_size
+        }
+    }
+}
+
+// one side of the book, a dense window of ticks kept around the best level,
+// levels further out than the window fall back to a tree
+#[derive(Debug, Clone)]
+struct Side {
+    is_bid: bool,
+    base: i64,
+    dense: Vec<f64>,
+    // only ever holds levels worse than the window, the best level is always inside it
+    far: BTreeMap<i64, f64>,
+    best: Option<i64>,
+}
+
+impl Side {
+    fn new(is_bid: bool, window: usize) -> Self {
+        Side {
+            is_bid,
+            base: 0,
+            dense: vec![0.0; window],
+            far: BTreeMap::new(),
+            best: None,
+        }
+    }
+
+    fn index(&self, tick: i64) -> Option<usize> {
+        let index = tick.checked_sub(self.base)?;
+        if index >= 0 && (index as usize) < self.dense.len() {
+            Some(index as usize)
+        } else {
+            None
+        }
+    }
+
+    fn better(&self, tick: i64, than: i64) -> bool {
+        if self.is_bid {
+            tick > than
+        } else {
+            tick < than
+        }
+    }
+
+    fn set(&mut self, tick: i64, qty: f64) {
+        if qty == 0.0 {
+            self.remove(tick);
+            return;
+        }
+        let improves = match self.best {
+            Some(best) => self.better(tick, best),
+            None => true,
+        };
+        if improves {
+            self.best = Some(tick);
+            if self.index(tick).is_none() {
+                self.recenter(tick);
+            }
+        }
+        match self.index(tick) {
+            Some(index) => self.dense[index] = qty,
+            None => {
+                self.far.insert(tick, qty);
+            }
+        }
+    }
+
+    fn remove(&mut self, tick: i64) {
+        match self.index(tick) {
+            Some(index) => self.dense[index] = 0.0,
+            None => {
+                self.far.remove(&tick);
+            }
+        }
+        if self.best == Some(tick) {
+            self.best = self.levels_from(tick).next().map(|(tick, _)| tick);
+            if let Some(best) = self.best {
+                if self.index(best).is_none() {
+                    self.recenter(best);
+                }
+            }
+        }
+    }
+
+    // moves the window so `center` sits near its front, leaving room for the side to improve
+    fn recenter(&mut self, center: i64) {
+        let window = self.dense.len() as i64;
+        for (index, qty) in self.dense.iter_mut().enumerate() {
+            if *qty != 0.0 {
+                self.far.insert(self.base + index as i64, *qty);
+                *qty = 0.0;
+            }
+        }
+        self.base = if self.is_bid {
+            center - window * 7 / 8
+        } else {
+            center - window / 8
+        };
+        let mut inside = self.far.split_off(&self.base);
+        let mut outside = inside.split_off(&(self.base + window));
+        self.far.append(&mut outside);
+        for (tick, qty) in inside {
+            self.dense[(tick - self.base) as usize] = qty;
+        }
+    }
+
+    fn levels(&self) -> Levels<'_> {
+        match self.best {
+            Some(best) => self.levels_from(best),
+            // an empty side has nothing in the window or the tree, skip the scan past the end
+            None if self.is_bid => self.levels_from(self.base - 1),
+            None => self.levels_from(self.base + self.dense.len() as i64),
+        }
+    }
+
+    fn levels_from(&self, start: i64) -> Levels<'_> {
+        let start = (start - self.base).max(-1).min(self.dense.len() as i64);
+        let (window, base) = if self.is_bid {
+            (&self.dense[..(start + 1) as usize], self.base)
+        } else {
+            (&self.dense[start.max(0) as usize..], self.base + start.max(0))
+        };
+        Levels {
+            side: self,
+            window,
+            base,
+            far: None,
+        }
+    }
+
+    fn far_levels(&self) -> btree_map::Range<'_, i64, f64> {
+        if self.is_bid {
+            self.far.range((Bound::Unbounded, Bound::Excluded(self.base)))
+        } else {
+            self.far.range((Bound::Included(self.base + self.dense.len() as i64), Bound::Unbounded))
+        }
+    }
+
+    fn clear(&mut self) {
+        for qty in self.dense.iter_mut() {
+            *qty = 0.0;
+        }
+        self.far.clear();
+        self.best = None;
+    }
+}
+
+// (tick, qty) from a starting tick towards worse prices, without allocating
+struct Levels<'a> {
+    side: &'a Side,
+    // the part of the window still to walk, `base` is the tick of its first slot
+    window: &'a [f64],
+    base: i64,
+    // only looked up once the window runs out
+    far: Option<btree_map::Range<'a, i64, f64>>,
+}
+
+impl<'a> Iterator for Levels<'a> {
+    type Item = (i64, f64);
+
+    fn next(&mut self) -> Option<(i64, f64)> {
+        if self.side.is_bid {
+            while let Some((&qty, rest)) = self.window.split_last() {
+                self.window = rest;
+                if qty != 0.0 {
+                    return Some((self.base + rest.len() as i64, qty));
+                }
+            }
+        } else {
+            while let Some((&qty, rest)) = self.window.split_first() {
+                self.window = rest;
+                self.base += 1;
+                if qty != 0.0 {
+                    return Some((self.base - 1, qty));
+                }
+            }
+        }
+        let side = self.side;
+        let far = self.far.get_or_insert_with(|| side.far_levels());
+        let level = if side.is_bid { far.next_back() } else { far.next() };
+        level.map(|(tick, qty)| (*tick, *qty))
+    }
+}
+
+// a book keyed by integer ticks of the symbol's tick size, cheaper to update and walk than
+// LocalOrderBook's float tree, it keeps the same diff sequencing but no staleness or events
+#[derive(Debug, Clone)]
+pub struct LadderBook {
+    symbol: String,
+    scale: TickScale,
+    bids: Side,
+    asks: Side,
+    rest_update_id: u64,
+    ws_final_update_id: u64,
+}
+
+impl LadderBook {
+    // tick_size from the symbol's PRICE_FILTER, see Symbol::tick_size
+    pub fn new(symbol: &str, tick_size: f64) -> Self {
+        LadderBook::with_window(symbol, tick_size, DEFAULT_WINDOW)
+    }
+
+    pub fn with_window(symbol: &str, tick_size: f64, window: usize) -> Self {
+        LadderBook {
+            symbol: symbol.to_string(),
+            scale: TickScale::new(tick_size),
+            bids: Side::new(true, window),
+            asks: Side::new(false, window),
+            rest_update_id: 0,
+            ws_final_update_id: 0,
+        }
+    }
+
+    pub fn from_snapshot(symbol: &str, tick_size: f64, orderbook: &Orderbook) -> Self {
+        let mut book = LadderBook::new(symbol, tick_size);
+        book.load_snapshot(orderbook);
+        book
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.rest_update_id != 0
+    }
+
+    pub fn load_snapshot(&mut self, orderbook: &Orderbook) {
+        self.reset();
+        for bid in &orderbook.bids {
+            self.bids.set(self.scale.tick(bid.price), bid.amount);
+        }
+        for ask in &orderbook.asks {
+            self.asks.set(self.scale.tick(ask.price), ask.amount);
+        }
+        self.rest_update_id = orderbook.timestamp;
+    }
+
+    // applies one diff, false on a sequence gap, the book is then empty until the next snapshot
+    pub fn apply(&mut self, depth_event: &DepthOrderbookEvent) -> bool {
+        if depth_event.final_update_id <= self.rest_update_id {
+            return true;
+        }
+        let bridged = if self.ws_final_update_id == 0 {
+            depth_event.first_update_id <= self.rest_update_id + 1
+        } else {
+            self.ws_final_update_id + 1 == depth_event.first_update_id
+        };
+        if !bridged {
+            self.reset();
+            return false;
+        }
+        for bid in &depth_event.bids {
+            self.bids.set(self.scale.tick(bid.price), bid.qty);
+        }
+        for ask in &depth_event.asks {
+            self.asks.set(self.scale.tick(ask.price), ask.qty);
+        }
+        self.ws_final_update_id = depth_event.final_update_id;
+        true
+    }
+
+    fn reset(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+        self.rest_update_id = 0;
+        self.ws_final_update_id = 0;
+    }
+
+    pub fn best_bid(&self) -> Option<Bid> {
+        self.bids.levels().next().map(|(tick, amount)| Bid {
+            price: self.scale.price(tick),
+            amount,
+        })
+    }
+
+    pub fn best_ask(&self) -> Option<Ask> {
+        self.asks.levels().next().map(|(tick, amount)| Ask {
+            price: self.scale.price(tick),
+            amount,
+        })
+    }
+
+    pub fn query_buy_price(&self, amount: f64) -> APIResult<f64> {
+        self.check_ready()?;
+        let levels = self.asks.levels().map(|(tick, qty)| (tick as f64, qty));
+        let ticks = fill_price(levels, amount);
+        ticks.map(|ticks| self.scale.fractional_price(ticks)).ok_or_else(|| {
+            let err = ExError::InsufficientLiquidity(format!("{} asks below {}", self.symbol, amount));
+            err.into()
+        })
+    }
+
+    pub fn query_sell_price(&self, amount: f64) -> APIResult<f64> {
+        self.check_ready()?;
+        let levels = self.bids.levels().map(|(tick, qty)| (tick as f64, qty));
+        let ticks = fill_price(levels, amount);
+        ticks.map(|ticks| self.scale.fractional_price(ticks)).ok_or_else(|| {
+            let err = ExError::InsufficientLiquidity(format!("{} bids below {}", self.symbol, amount));
+            err.into()
+        })
+    }
+
+    fn check_ready(&self) -> APIResult<()> {
+        if self.is_ready() {
+            Ok(())
+        } else {
+            Err(Box::new(ExError::OrderbookNotReady(self.symbol.clone())))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::binance::orderbook::LocalOrderBook;
+
+    fn depth_event(first: u64, last: u64, bids: &str, asks: &str) -> DepthOrderbookEvent {
+        let s = format!(
+            r#"{{"e":"depthUpdate","E":1,"s":"BTCUSDT","U":{},"u":{},"b":{},"a":{}}}"#,
+            first, last, bids, asks
+        );
+        serde_json::from_str(&s).unwrap()
+    }
+
+    #[test]
+    fn test_tick_scale() {
+        let scale = TickScale::new(0.01);
+        assert_eq!(scale.tick(101.23), 10123);
+        assert_eq!(scale.price(10123), 101.23);
+        let scale = TickScale::new(0.00000001);
+        assert_eq!(scale.price(scale.tick(0.00002517)), 0.00002517);
+        let scale = TickScale::new(10.0);
+        assert_eq!(scale.tick(1230.0), 123);
+    }
+
+    #[test]
+    fn test_ladder_matches_tree() {
+        let snapshot: RawOrderbook = serde_json::from_str(
+            r#"{"lastUpdateId":9,"bids":[["99.5","2"],["98","2"],["50","3"]],"asks":[["101","2"],["102.25","2"],["150","3"]]}"#,
+        )
+        .unwrap();
+        let snapshot: Orderbook = snapshot.into();
+        // a window of 64 ticks forces the far levels and recentering through their paths
+        let mut ladder = LadderBook::with_window("btcusdt", 0.25, 64);
+        ladder.load_snapshot(&snapshot);
+        let mut tree = LocalOrderBook::from_snapshot("btcusdt", snapshot);
+
+        let events = vec![
+            depth_event(10, 10, r#"[["99.5","0"]]"#, r#"[["100.5","1"]]"#),
+            depth_event(11, 11, r#"[["98","0"]]"#, "[]"),
+            depth_event(12, 12, r#"[["100","0.5"]]"#, r#"[["100.5","0"],["101","0"]]"#),
+            depth_event(13, 13, "[]", r#"[["102.25","0"]]"#),
+        ];
+        for event in &events {
+            assert!(ladder.apply(event));
+            assert!(tree.apply(event));
+            let (bid, ask) = (ladder.best_bid().unwrap(), ladder.best_ask().unwrap());
+            assert_eq!(bid.price, tree.best_bid().unwrap().price);
+            assert_eq!(ask.price, tree.best_ask().unwrap().price);
+            for amount in &[0.5, 1.0, 2.5, 3.5] {
+                let close = |ladder: APIResult<f64>, tree: APIResult<f64>| match (ladder, tree) {
+                    (Ok(ladder), Ok(tree)) => (ladder - tree).abs() < 1e-9,
+                    (ladder, tree) => ladder.is_err() && tree.is_err(),
+                };
+                assert!(close(ladder.query_buy_price(*amount), tree.query_buy_price(*amount)));
+                assert!(close(ladder.query_sell_price(*amount), tree.query_sell_price(*amount)));
+            }
+        }
+        assert_eq!(ladder.best_ask().unwrap().price, 150.0);
+        assert!(ladder.query_buy_price(3.5).is_err());
+
+        assert!(!ladder.apply(&depth_event(20, 20, "[]", "[]")));
+        assert!(ladder.best_bid().is_none());
+        assert!(ladder.query_sell_price(0.1).is_err());
+    }
+}
@@ -1,4 +1,7 @@
+pub mod ladder;
+pub mod orderbook;
 pub mod spot_rest;
 pub mod spot_ws;
+pub mod future_ws;
 //pub mod future_rest;
 pub mod types;
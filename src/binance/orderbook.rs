@@ -0,0 +1,1157 @@
+use crate::binance::spot_rest::Binance;
+use crate::binance::spot_ws::{diff_depth_stream, BinanceWs, BinanceWsHandle, UpdateSpeed, WsEvent};
+use crate::binance::types::*;
+use crate::errors::*;
+use crate::models::*;
+use crate::recorder::Recorder;
+use crate::replay::RecordedFrame;
+use crate::traits::*;
+use crate::websocket::*;
+
+use log::{info, warn};
+use ordered_float::OrderedFloat;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+type Books = Arc<RwLock<HashMap<String, Arc<RwLock<LocalOrderBook>>>>>;
+// returns false once the listener is gone
+type Listener = Box<dyn FnMut(&BookEvent) -> bool + Send>;
+type Listeners = Arc<Mutex<HashMap<String, Vec<(u64, Listener)>>>>;
+// (price, amount) at the top of one side
+type Level = Option<(f64, f64)>;
+
+// diff stream and rest snapshot settings of an OrderBookManager
+#[derive(Debug, Clone)]
+pub struct DepthConfig {
+    pub speed: UpdateSpeed,
+    // levels of the rest snapshot, up to 5000
+    pub snapshot_limit: u32,
+    // diffs cached before a snapshot is requested, repeated every this many until one arrives
+    pub cache_threshold: usize,
+    // records the depth frames, rest snapshots and disconnects, enough for replay_books
+    pub recorder: Option<Recorder>,
+}
+
+impl Default for DepthConfig {
+    fn default() -> Self {
+        DepthConfig {
+            speed: UpdateSpeed::Ms100,
+            snapshot_limit: 1000,
+            cache_threshold: 30,
+            recorder: None,
+        }
+    }
+}
+
+// keeps a depth diff stream per symbol and rebuilds books from rest snapshots,
+// each manager owns its connection and threads, so testnet and mainnet can run side by side
+pub struct OrderBookManager {
+    books: Books,
+    listeners: Listeners,
+    next_listener: AtomicU64,
+    depth: DepthConfig,
+    stale_after: Mutex<Option<Duration>>,
+    handle: BinanceWsHandle,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl OrderBookManager {
+    // rest_host: https://api.binance.com, ws_host: wss://stream.binance.com:9443
+    pub fn new(rest_host: &str, ws_host: &str) -> Self {
+        OrderBookManager::with_config(rest_host, ws_host, WsConfig::default())
+    }
+
+    pub fn with_config(rest_host: &str, ws_host: &str, config: WsConfig) -> Self {
+        OrderBookManager::with_depth_config(rest_host, ws_host, config, DepthConfig::default())
+    }
+
+    pub fn with_depth_config(rest_host: &str, ws_host: &str, config: WsConfig, depth: DepthConfig) -> Self {
+        let books: Books = Arc::new(RwLock::new(HashMap::new()));
+        let listeners: Listeners = Arc::new(Mutex::new(HashMap::new()));
+        let (snapshot_tx, snapshot_rx) = mpsc::channel::<String>();
+
+        let mut ws = BinanceWs::new(&format!("{}/stream", ws_host));
+        ws.set_ws_config(config);
+        if let Some(recorder) = &depth.recorder {
+            ws.set_recorder(recorder.clone());
+        }
+        let feed_recorder = depth.recorder.clone();
+        let handle = ws.handle();
+        let feed_books = books.clone();
+        let feed_listeners = listeners.clone();
+        let mut changes = vec![];
+        let feed = ws.spawn(move |event| {
+            match event {
+                WsEvent::DepthEvent(symbol, event) => {
+                    if let Some(book) = get_book(&feed_books, &symbol) {
+                        if book.write().unwrap().on_depth_event(event, &mut changes) {
+                            let _ = snapshot_tx.send(symbol.clone());
+                        }
+                        // book lock released, listeners may query it
+                        dispatch(&feed_listeners, &symbol, changes.drain(..));
+                    }
+                }
+                // diffs were missed while disconnected, start over from a new snapshot
+                WsEvent::Disconnected(reason) => {
+                    warn!("depth stream disconnected: {}, reset local orderbooks", reason);
+                    if let Some(recorder) = &feed_recorder {
+                        let _ = recorder.record("binance", DISCONNECTED_STREAM, &reason);
+                    }
+                    let books = feed_books.read().unwrap().clone();
+                    for (symbol, book) in books {
+                        book.write().unwrap().reset();
+                        dispatch(&feed_listeners, &symbol, std::iter::once(BookEvent::Reset(symbol.clone())));
+                    }
+                }
+                _ => {}
+            }
+            Ok(())
+        });
+
+        let rest_host = rest_host.to_string();
+        let snapshot_limit = depth.snapshot_limit;
+        let snapshot_recorder = depth.recorder.clone();
+        let snapshot_books = books.clone();
+        let snapshot = thread::Builder::new()
+            .name("orderbook-snapshot".into())
+            .spawn(move || {
+                let rest = Binance::new(None, None, rest_host);
+                // ends once the feed thread exits and drops its sender
+                for symbol in snapshot_rx {
+                    get_depth_snapshot(&rest, &snapshot_books, &symbol, snapshot_limit, &snapshot_recorder);
+                }
+            })
+            .ok();
+
+        OrderBookManager {
+            books,
+            listeners,
+            next_listener: AtomicU64::new(0),
+            depth,
+            stale_after: Mutex::new(Some(Duration::from_secs(30))),
+            handle,
+            threads: Mutex::new(feed.into_iter().chain(snapshot).collect()),
+        }
+    }
+
+    pub fn add_symbol(&self, symbol: &str) -> Ack<()> {
+        let symbol = symbol.to_lowercase();
+        self.book_entry(&symbol);
+        self.handle.subscribe(&[&diff_depth_stream(&symbol, self.depth.speed)])
+    }
+
+    // like add_symbol, but the book starts from a saved state instead of waiting for a rest snapshot
+    pub fn restore_symbol(&self, state: BookState) -> Ack<()> {
+        let symbol = state.symbol.to_lowercase();
+        self.book_entry(&symbol).write().unwrap().restore(state);
+        self.handle.subscribe(&[&diff_depth_stream(&symbol, self.depth.speed)])
+    }
+
+    fn book_entry(&self, symbol: &str) -> Arc<RwLock<LocalOrderBook>> {
+        self.books
+            .write()
+            .unwrap()
+            .entry(symbol.to_string())
+            .or_insert_with(|| {
+                let stale_after = *self.stale_after.lock().unwrap();
+                let mut book = LocalOrderBook::new(symbol.to_string(), stale_after);
+                book.cache_threshold = self.depth.cache_threshold.max(1);
+                Arc::new(RwLock::new(book))
+            })
+            .clone()
+    }
+
+    // writes every built book to `dir/<symbol>.json`, returns how many were saved
+    pub fn save_books<P: AsRef<Path>>(&self, dir: P) -> APIResult<usize> {
+        fs::create_dir_all(&dir)?;
+        let books = self.books.read().unwrap().clone();
+        let mut saved = 0;
+        for (symbol, book) in books {
+            let state = book.read().unwrap().state();
+            if let Some(state) = state {
+                fs::write(dir.as_ref().join(format!("{}.json", symbol)), serde_json::to_vec(&state)?)?;
+                saved += 1;
+            }
+        }
+        Ok(saved)
+    }
+
+    // restores and subscribes every book saved by save_books, unreadable files are skipped
+    pub fn restore_books<P: AsRef<Path>>(&self, dir: P) -> APIResult<Vec<String>> {
+        let mut symbols = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+                continue;
+            }
+            let state = fs::read(&path)
+                .map_err(|err| err.to_string())
+                .and_then(|content| serde_json::from_slice::<BookState>(&content).map_err(|err| err.to_string()));
+            match state {
+                Ok(state) => {
+                    symbols.push(state.symbol.to_lowercase());
+                    self.restore_symbol(state);
+                }
+                Err(err) => warn!("skip saved orderbook {}: {}", path.display(), err),
+            }
+        }
+        Ok(symbols)
+    }
+
+    pub fn remove_symbol(&self, symbol: &str) -> Option<Ack<()>> {
+        let symbol = symbol.to_lowercase();
+        self.books.write().unwrap().remove(&symbol)?;
+        Some(self.handle.unsubscribe(&[&diff_depth_stream(&symbol, self.depth.speed)]))
+    }
+
+    // `callback` runs on the feed thread after every applied diff of `symbol`,
+    // it must not subscribe or unsubscribe from within
+    pub fn subscribe_book<F>(&self, symbol: &str, mut callback: F) -> u64
+    where
+        F: FnMut(&BookEvent) + Send + 'static,
+    {
+        self.add_listener(symbol, Box::new(move |event| {
+            callback(event);
+            true
+        }))
+    }
+
+    // same as `subscribe_book` over a channel, dropped with the receiver
+    pub fn book_channel(&self, symbol: &str) -> mpsc::Receiver<BookEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.add_listener(symbol, Box::new(move |event| tx.send(event.clone()).is_ok()));
+        rx
+    }
+
+    pub fn unsubscribe_book(&self, id: u64) {
+        for listeners in self.listeners.lock().unwrap().values_mut() {
+            listeners.retain(|(listener_id, _)| *listener_id != id);
+        }
+    }
+
+    fn add_listener(&self, symbol: &str, listener: Listener) -> u64 {
+        let id = self.next_listener.fetch_add(1, Ordering::Relaxed);
+        self.listeners
+            .lock()
+            .unwrap()
+            .entry(symbol.to_lowercase())
+            .or_default()
+            .push((id, listener));
+        id
+    }
+
+    // books without a diff for this long stop answering queries until the stream resumes,
+    // None never marks them stale
+    pub fn set_stale_after(&self, stale_after: Option<Duration>) {
+        *self.stale_after.lock().unwrap() = stale_after;
+        for book in self.books.read().unwrap().values() {
+            book.write().unwrap().stale_after = stale_after;
+        }
+    }
+
+    pub fn health(&self, symbol: &str) -> APIResult<BookHealth> {
+        self.with_book(symbol, |book| book.health())
+    }
+
+    pub fn symbols(&self) -> Vec<String> {
+        self.books.read().unwrap().keys().cloned().collect()
+    }
+
+    // a copy of the book as of now
+    pub fn get_book(&self, symbol: &str) -> Option<LocalOrderBook> {
+        get_book(&self.books, symbol).map(|book| book.read().unwrap().clone())
+    }
+
+    pub fn is_ready(&self, symbol: &str) -> bool {
+        get_book(&self.books, symbol).map_or(false, |book| book.read().unwrap().is_ready())
+    }
+
+    // run a query against the live book without copying it
+    pub fn with_book<R, F>(&self, symbol: &str, query: F) -> APIResult<R>
+    where
+        F: FnOnce(&LocalOrderBook) -> R,
+    {
+        match get_book(&self.books, symbol) {
+            Some(book) => Ok(query(&book.read().unwrap())),
+            None => Err(Box::new(ExError::ApiError(format!("symbol not found: {}", symbol)))),
+        }
+    }
+
+    pub fn query_buy_price(&self, symbol: &str, amount: f64) -> APIResult<f64> {
+        self.with_book(symbol, |book| book.query_buy_price(amount))?
+    }
+
+    pub fn query_sell_price(&self, symbol: &str, amount: f64) -> APIResult<f64> {
+        self.with_book(symbol, |book| book.query_sell_price(amount))?
+    }
+
+    pub fn print_order_book(&self, symbol: &str) {
+        match get_book(&self.books, symbol) {
+            Some(book) => book.read().unwrap().print_order_book(),
+            None => warn!("symbol not found: {}", symbol),
+        }
+    }
+
+    // closes the stream and waits for the feed and snapshot threads to exit
+    pub fn shutdown(&self) {
+        self.handle.shutdown();
+        let threads = std::mem::take(&mut *self.threads.lock().unwrap());
+        for thread in threads {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for OrderBookManager {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl fmt::Debug for OrderBookManager {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OrderBookManager")
+            .field("symbols", &self.symbols())
+            .finish()
+    }
+}
+
+fn dispatch<I>(listeners: &Listeners, symbol: &str, events: I)
+where
+    I: Iterator<Item = BookEvent>,
+{
+    let mut listeners = listeners.lock().unwrap();
+    if let Some(listeners) = listeners.get_mut(symbol) {
+        for event in events {
+            // a listener returning false is dropped
+            let mut i = 0;
+            while i < listeners.len() {
+                if (listeners[i].1)(&event) {
+                    i += 1;
+                } else {
+                    let _ = listeners.remove(i);
+                }
+            }
+        }
+    }
+}
+
+fn get_book(books: &Books, symbol: &str) -> Option<Arc<RwLock<LocalOrderBook>>> {
+    books.read().unwrap().get(&symbol.to_lowercase()).cloned()
+}
+
+fn get_depth_snapshot(rest: &Binance, books: &Books, symbol: &str, limit: u32, recorder: &Option<Recorder>) {
+    info!("get depth snapshot by rest, symbol: {}", symbol);
+    let ret = rest.get_orderbook(&symbol.to_uppercase(), limit);
+    match ret {
+        Ok(orderbook) => {
+            // removed while the request was in flight
+            if let Some(book) = get_book(books, symbol) {
+                let mut local_orderbook = book.write().unwrap();
+                if local_orderbook.rest_update_id == 0 {
+                    // recorded under the book lock, in the order the book saw it
+                    if let Some(recorder) = recorder {
+                        let frame = snapshot_frame(&orderbook);
+                        let _ = recorder.record("binance", &format!("{}{}", symbol, SNAPSHOT_STREAM), &frame);
+                    }
+                    local_orderbook.save_depth_snapshot(orderbook);
+                }
+            }
+        }
+        Err(error) => {
+            warn!("get orderbook error: {}", error);
+        }
+    }
+}
+
+// recorded streams besides the depth frames, <symbol>@snapshot holds the applied rest snapshots
+const SNAPSHOT_STREAM: &str = "@snapshot";
+const DISCONNECTED_STREAM: &str = "disconnected";
+
+// same shape as the rest response
+fn snapshot_frame(orderbook: &Orderbook) -> String {
+    let bids = orderbook.bids.iter().map(|bid| (bid.price, bid.amount)).collect::<Vec<_>>();
+    let asks = orderbook.asks.iter().map(|ask| (ask.price, ask.amount)).collect::<Vec<_>>();
+    json!({"lastUpdateId": orderbook.timestamp, "bids": bids, "asks": asks}).to_string()
+}
+
+// rebuilds the books of an OrderBookManager from what its recorder captured, frames go through
+// the same parsing and book logic as live ones, so the books end up as the live ones were
+pub fn replay_books<'f, I>(frames: I) -> HashMap<String, LocalOrderBook>
+where
+    I: IntoIterator<Item = &'f RecordedFrame>,
+{
+    let ws = BinanceWs::new("replay");
+    let mut books: HashMap<String, LocalOrderBook> = HashMap::new();
+    let mut changes = vec![];
+    for frame in frames {
+        if frame.stream == DISCONNECTED_STREAM {
+            books.values_mut().for_each(|book| book.reset());
+        } else if let Some(symbol) = frame.stream.strip_suffix(SNAPSHOT_STREAM) {
+            match serde_json::from_str::<RawOrderbook>(&frame.frame) {
+                Ok(snapshot) => {
+                    let book = books
+                        .entry(symbol.to_string())
+                        .or_insert_with(|| LocalOrderBook::new(symbol.to_string(), None));
+                    if book.rest_update_id == 0 {
+                        book.save_depth_snapshot(snapshot.into());
+                    }
+                }
+                Err(err) => warn!("skip recorded snapshot of {}: {}", symbol, err),
+            }
+        } else if let Ok(WsEvent::DepthEvent(symbol, event)) = ws.deseralize(&frame.frame) {
+            let book = books
+                .entry(symbol.clone())
+                .or_insert_with(|| LocalOrderBook::new(symbol, None));
+            book.on_depth_event(event, &mut changes);
+            changes.clear();
+        }
+    }
+    books
+}
+
+// walks levels from the top, None when they run out before `amount` is filled
+pub(crate) fn fill_price<I>(levels: I, amount: f64) -> Option<f64>
+where
+    I: Iterator<Item = (f64, f64)>,
+{
+    if amount <= 0.0 {
+        return None;
+    }
+    let mut remaining = amount;
+    let mut cost = 0.0;
+    for (price, qty) in levels {
+        let fill = remaining.min(qty);
+        cost += price * fill;
+        remaining -= fill;
+        if remaining <= 0.0 {
+            return Some(cost / amount);
+        }
+    }
+    None
+}
+
+#[derive(Debug, Clone)]
+pub struct LocalOrderBook {
+    symbol: String,
+    asks: BTreeMap<OrderedFloat<f64>, f64>,
+    bids: BTreeMap<OrderedFloat<f64>, f64>,
+    depth_cache: BTreeMap<u64, DepthOrderbookEvent>,
+    cache_threshold: usize,
+    rest_update_id: u64,
+    ws_final_update_id: u64,
+    ready: bool,
+    last_bbo: Option<(Level, Level)>,
+    last_update: Option<Instant>,
+    last_event_time: u64,
+    stale_after: Option<Duration>,
+    gaps: u64,
+    crossings: u64,
+    resyncs: u64,
+}
+
+// what save_books writes per symbol, levels are (price, amount) from the top
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookState {
+    pub symbol: String,
+    pub last_update_id: u64,
+    pub bids: Vec<(f64, f64)>,
+    pub asks: Vec<(f64, f64)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BookHealth {
+    pub ready: bool,
+    pub stale: bool,
+    pub crossed: bool,
+    pub since_last_event: Option<Duration>,
+    // exchange time of the last diff, ms
+    pub last_event_time: u64,
+    pub gaps: u64,
+    pub crossings: u64,
+    pub resyncs: u64,
+}
+
+impl LocalOrderBook {
+    fn new(symbol: String, stale_after: Option<Duration>) -> LocalOrderBook {
+        LocalOrderBook {
+            symbol,
+            asks: BTreeMap::new(),
+            bids: BTreeMap::new(),
+            depth_cache: BTreeMap::new(),
+            cache_threshold: DepthConfig::default().cache_threshold,
+            rest_update_id: 0,
+            ws_final_update_id: 0,
+            ready: false,
+            last_bbo: None,
+            last_update: None,
+            last_event_time: 0,
+            stale_after,
+            gaps: 0,
+            crossings: 0,
+            resyncs: 0,
+        }
+    }
+
+    // a ready book from a rest snapshot, for replaying diffs outside the manager
+    pub fn from_snapshot(symbol: &str, orderbook: Orderbook) -> Self {
+        let mut book = LocalOrderBook::new(symbol.to_string(), None);
+        book.save_depth_snapshot(orderbook);
+        book.ready = true;
+        book
+    }
+
+    // applies one diff, false on a sequence gap, the book then waits for a new snapshot
+    pub fn apply(&mut self, depth_event: &DepthOrderbookEvent) -> bool {
+        !matches!(self.update(depth_event), DiffResult::Gap)
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.symbol
+    }
+
+    // built, in sequence and not stale
+    pub fn is_ready(&self) -> bool {
+        self.ready && !self.is_stale()
+    }
+
+    pub fn is_stale(&self) -> bool {
+        match (self.last_update, self.stale_after) {
+            (Some(last_update), Some(stale_after)) => last_update.elapsed() > stale_after,
+            _ => false,
+        }
+    }
+
+    pub fn is_crossed(&self) -> bool {
+        match (self.bids.keys().next_back(), self.asks.keys().next()) {
+            (Some(bid), Some(ask)) => bid >= ask,
+            _ => false,
+        }
+    }
+
+    pub fn health(&self) -> BookHealth {
+        BookHealth {
+            ready: self.is_ready(),
+            stale: self.is_stale(),
+            crossed: self.is_crossed(),
+            since_last_event: self.last_update.map(|last_update| last_update.elapsed()),
+            last_event_time: self.last_event_time,
+            gaps: self.gaps,
+            crossings: self.crossings,
+            resyncs: self.resyncs,
+        }
+    }
+
+    pub fn print_order_book(&self) {
+        info!("Local Orderbook:\nasks:\n{:?}\nbids:\n{:?}", &self.asks, &self.bids);
+    }
+
+    pub fn best_bid(&self) -> Option<Bid> {
+        if !self.is_ready() {
+            return None;
+        }
+        self.bids.iter().next_back().map(|(price, amount)| Bid {
+            price: price.into_inner(),
+            amount: *amount,
+        })
+    }
+
+    pub fn best_ask(&self) -> Option<Ask> {
+        if !self.is_ready() {
+            return None;
+        }
+        self.asks.iter().next().map(|(price, amount)| Ask {
+            price: price.into_inner(),
+            amount: *amount,
+        })
+    }
+
+    pub fn spread(&self) -> Option<f64> {
+        Some(self.best_ask()?.price - self.best_bid()?.price)
+    }
+
+    pub fn mid_price(&self) -> Option<f64> {
+        Some((self.best_ask()?.price + self.best_bid()?.price) / 2.0)
+    }
+
+    // mid weighted towards the side with less size at the top
+    pub fn micro_price(&self) -> Option<f64> {
+        let bid = self.best_bid()?;
+        let ask = self.best_ask()?;
+        Some((bid.price * ask.amount + ask.price * bid.amount) / (bid.amount + ask.amount))
+    }
+
+    // (bid amount, ask amount) resting within `bps` basis points of the mid
+    pub fn depth_within_bps(&self, bps: f64) -> Option<(f64, f64)> {
+        if !self.is_ready() {
+            return None;
+        }
+        let mid = self.mid_price()?;
+        let lower = OrderedFloat(mid * (1.0 - bps / 10000.0));
+        let upper = OrderedFloat(mid * (1.0 + bps / 10000.0));
+        let bids = self.bids.range(lower..).map(|(_, amount)| amount).sum();
+        let asks = self.asks.range(..=upper).map(|(_, amount)| amount).sum();
+        Some((bids, asks))
+    }
+
+    // (bid - ask) / (bid + ask) over the top `levels` of each side, in [-1, 1]
+    pub fn imbalance(&self, levels: usize) -> Option<f64> {
+        if !self.is_ready() {
+            return None;
+        }
+        let bids: f64 = self.bids.values().rev().take(levels).sum();
+        let asks: f64 = self.asks.values().take(levels).sum();
+        if bids + asks == 0.0 {
+            return None;
+        }
+        Some((bids - asks) / (bids + asks))
+    }
+
+    pub fn top_levels(&self, levels: usize) -> Option<Orderbook> {
+        if !self.is_ready() {
+            return None;
+        }
+        let bids = self
+            .bids
+            .iter()
+            .rev()
+            .take(levels)
+            .map(|(price, amount)| Bid {
+                price: price.into_inner(),
+                amount: *amount,
+            })
+            .collect::<Vec<Bid>>();
+        let asks = self
+            .asks
+            .iter()
+            .take(levels)
+            .map(|(price, amount)| Ask {
+                price: price.into_inner(),
+                amount: *amount,
+            })
+            .collect::<Vec<Ask>>();
+        Some(Orderbook {
+            timestamp: std::cmp::max(self.rest_update_id, self.ws_final_update_id),
+            bids,
+            asks,
+        })
+    }
+
+    // average fill price for buying `amount` against the asks
+    pub fn query_buy_price(&self, amount: f64) -> APIResult<f64> {
+        self.check_ready()?;
+        fill_price(self.asks.iter().map(|(price, qty)| (price.into_inner(), *qty)), amount).ok_or_else(|| {
+            let err = ExError::InsufficientLiquidity(format!("{} asks below {}", self.symbol, amount));
+            err.into()
+        })
+    }
+
+    // average fill price for selling `amount` into the bids
+    pub fn query_sell_price(&self, amount: f64) -> APIResult<f64> {
+        self.check_ready()?;
+        fill_price(self.bids.iter().rev().map(|(price, qty)| (price.into_inner(), *qty)), amount).ok_or_else(|| {
+            let err = ExError::InsufficientLiquidity(format!("{} bids below {}", self.symbol, amount));
+            err.into()
+        })
+    }
+
+    // base amount bought by spending `quote` against the asks
+    pub fn query_buy_amount(&self, quote: f64) -> APIResult<f64> {
+        self.check_ready()?;
+        let mut budget = quote;
+        let mut amount = 0.0;
+        for (price, qty) in &self.asks {
+            let price = price.into_inner();
+            if price * qty >= budget {
+                return Ok(amount + budget / price);
+            }
+            budget -= price * qty;
+            amount += qty;
+        }
+        Err(Box::new(ExError::InsufficientLiquidity(format!(
+            "{} asks worth less than {}",
+            self.symbol, quote
+        ))))
+    }
+
+    fn check_ready(&self) -> APIResult<()> {
+        if self.is_stale() {
+            Err(Box::new(ExError::OrderbookNotReady(format!("{} is stale", self.symbol))))
+        } else if !self.ready {
+            Err(Box::new(ExError::OrderbookNotReady(self.symbol.clone())))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn reset(&mut self) {
+        self.resyncs += 1;
+        self.last_bbo = None;
+        self.asks.clear();
+        self.bids.clear();
+        self.depth_cache.clear();
+        self.rest_update_id = 0;
+        self.ws_final_update_id = 0;
+        self.ready = false;
+    }
+
+    // None while waiting for a snapshot, cached diffs are not part of the state
+    pub fn state(&self) -> Option<BookState> {
+        if self.rest_update_id == 0 {
+            return None;
+        }
+        Some(BookState {
+            symbol: self.symbol.clone(),
+            last_update_id: std::cmp::max(self.rest_update_id, self.ws_final_update_id),
+            bids: self.bids.iter().rev().map(|(price, amount)| (price.into_inner(), *amount)).collect(),
+            asks: self.asks.iter().map(|(price, amount)| (price.into_inner(), *amount)).collect(),
+        })
+    }
+
+    // seeds the book as if `state` were a rest snapshot, the first live diff then either
+    // continues its sequence or the book is reset and rebuilt from a real snapshot
+    pub fn restore(&mut self, state: BookState) {
+        self.bids = state.bids.into_iter().map(|(price, amount)| (OrderedFloat(price), amount)).collect();
+        self.asks = state.asks.into_iter().map(|(price, amount)| (OrderedFloat(price), amount)).collect();
+        self.rest_update_id = state.last_update_id;
+        self.ws_final_update_id = 0;
+        self.ready = false;
+        self.last_bbo = None;
+    }
+
+    fn save_depth_snapshot(&mut self, orderbook: Orderbook) {
+        for entry in &(orderbook.asks) {
+            self.asks.insert(OrderedFloat(entry.price), entry.amount);
+        }
+        for entry in &(orderbook.bids) {
+            self.bids.insert(OrderedFloat(entry.price), entry.amount);
+        }
+        self.rest_update_id = orderbook.timestamp;
+    }
+
+    fn update(&mut self, depth_event: &DepthOrderbookEvent) -> DiffResult {
+        if depth_event.final_update_id <= self.rest_update_id {
+            return DiffResult::Stale;
+        }
+        // the first diff after a snapshot has to straddle it, later ones follow each other
+        let bridged = if self.ws_final_update_id == 0 {
+            depth_event.first_update_id <= self.rest_update_id + 1
+        } else {
+            self.ws_final_update_id + 1 == depth_event.first_update_id
+        };
+        if !bridged {
+            info!("package lost, rebuild symbol {} local orderbook", &(self.symbol));
+            self.gaps += 1;
+            self.reset();
+            self.depth_cache.insert(depth_event.final_update_id, depth_event.clone());
+            return DiffResult::Gap;
+        }
+        for entry in &(depth_event.asks) {
+            if entry.qty == 0.0 {
+                self.asks.remove(&OrderedFloat(entry.price));
+            } else {
+                self.asks.insert(OrderedFloat(entry.price), entry.qty);
+            }
+        }
+        for entry in &(depth_event.bids) {
+            if entry.qty == 0.0 {
+                self.bids.remove(&OrderedFloat(entry.price));
+            } else {
+                self.bids.insert(OrderedFloat(entry.price), entry.qty);
+            }
+        }
+        self.ws_final_update_id = depth_event.final_update_id;
+        DiffResult::Applied
+    }
+
+    // returns true when a rest snapshot should be requested, what changed is pushed to `changes`
+    fn on_depth_event(&mut self, depth_event: DepthOrderbookEvent, changes: &mut Vec<BookEvent>) -> bool {
+        self.last_update = Some(Instant::now());
+        self.last_event_time = depth_event.event_time;
+        if self.rest_update_id == 0 {
+            self.depth_cache.insert(depth_event.final_update_id, depth_event);
+            let cached = self.depth_cache.len();
+            return cached > self.cache_threshold && cached % self.cache_threshold == 0;
+        }
+        if !self.ready {
+            info!("rest depth snapshot is done, update symbol {} local orderbook", &(self.symbol));
+            let depth_cache = std::mem::take(&mut self.depth_cache);
+            for (_, cached) in depth_cache {
+                if let DiffResult::Gap = self.update(&cached) {
+                    return true;
+                }
+            }
+            self.ready = true;
+            info!("build local orderbook finished, symbol: {}", &(self.symbol));
+        }
+        match self.update(&depth_event) {
+            DiffResult::Gap => {
+                changes.push(BookEvent::Reset(self.symbol.clone()));
+                return true;
+            }
+            DiffResult::Applied => changes.push(BookEvent::Update(BookUpdate::new(&self.symbol, depth_event))),
+            DiffResult::Stale => {}
+        }
+        if self.is_crossed() {
+            warn!("symbol {} local orderbook crossed, rebuild", &(self.symbol));
+            self.crossings += 1;
+            self.reset();
+            changes.push(BookEvent::Reset(self.symbol.clone()));
+            return true;
+        }
+        let bid = self.best_bid();
+        let ask = self.best_ask();
+        let top = (
+            bid.as_ref().map(|bid| (bid.price, bid.amount)),
+            ask.as_ref().map(|ask| (ask.price, ask.amount)),
+        );
+        if self.last_bbo != Some(top) {
+            self.last_bbo = Some(top);
+            changes.push(BookEvent::Bbo(self.symbol.clone(), bid, ask));
+        }
+        false
+    }
+}
+
+enum DiffResult {
+    Stale,
+    Applied,
+    Gap,
+}
+
+#[derive(Debug, Clone)]
+pub enum BookEvent {
+    // levels changed by one diff
+    Update(BookUpdate),
+    // best bid or ask moved, only sent when price or size at the top changed
+    Bbo(String, Option<Bid>, Option<Ask>),
+    // the book lost its sequence and is rebuilding, it is not ready until the next Bbo
+    Reset(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct BookUpdate {
+    pub symbol: String,
+    pub first_update_id: u64,
+    pub final_update_id: u64,
+    // amount 0 means the level was removed
+    pub bids: Vec<Bid>,
+    pub asks: Vec<Ask>,
+}
+
+impl BookUpdate {
+    fn new(symbol: &str, event: DepthOrderbookEvent) -> Self {
+        BookUpdate {
+            symbol: symbol.to_string(),
+            first_update_id: event.first_update_id,
+            final_update_id: event.final_update_id,
+            bids: event
+                .bids
+                .iter()
+                .map(|bid| Bid {
+                    price: bid.price,
+                    amount: bid.qty,
+                })
+                .collect(),
+            asks: event
+                .asks
+                .iter()
+                .map(|ask| Ask {
+                    price: ask.price,
+                    amount: ask.qty,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn depth_event(first: u64, last: u64, bids: &str, asks: &str) -> DepthOrderbookEvent {
+        let s = format!(
+            r#"{{"e":"depthUpdate","E":1,"s":"BTCUSDT","U":{},"u":{},"b":{},"a":{}}}"#,
+            first, last, bids, asks
+        );
+        serde_json::from_str(&s).unwrap()
+    }
+
+    #[test]
+    fn test_local_orderbook_build() {
+        let mut book = LocalOrderBook::new("btcusdt".into(), None);
+        let mut changes = vec![];
+        assert!(!book.on_depth_event(depth_event(1, 10, r#"[["99","1"]]"#, r#"[["101","1"]]"#), &mut changes));
+        assert!(book.query_buy_price(1.0).is_err());
+
+        let snapshot: RawOrderbook = serde_json::from_str(
+            r#"{"lastUpdateId":9,"bids":[["99","2"],["98","2"]],"asks":[["101","2"],["102","2"]]}"#,
+        )
+        .unwrap();
+        book.save_depth_snapshot(snapshot.into());
+        assert!(!book.on_depth_event(depth_event(11, 12, r#"[["99","0"]]"#, r#"[["101","0.5"]]"#), &mut changes));
+        assert!(book.is_ready());
+        assert_eq!(book.query_buy_price(1.0).unwrap(), 101.5);
+        assert_eq!(book.query_sell_price(1.0).unwrap(), 98.0);
+
+        // a gap drops the book until a new snapshot arrives
+        assert!(book.on_depth_event(depth_event(20, 21, "[]", "[]"), &mut changes));
+        assert!(!book.is_ready());
+    }
+
+    #[test]
+    fn test_book_queries() {
+        let mut book = LocalOrderBook::new("btcusdt".into(), None);
+        assert_eq!(book.depth_within_bps(150.0), None);
+        assert!(book.top_levels(1).is_none());
+        let snapshot: RawOrderbook = serde_json::from_str(
+            r#"{"lastUpdateId":9,"bids":[["99","1"],["98","3"]],"asks":[["101","3"],["103","2"]]}"#,
+        )
+        .unwrap();
+        book.save_depth_snapshot(snapshot.into());
+        book.on_depth_event(depth_event(10, 10, "[]", "[]"), &mut vec![]);
+
+        assert_eq!(book.best_bid().unwrap().price, 99.0);
+        assert_eq!(book.best_ask().unwrap().price, 101.0);
+        assert_eq!(book.spread(), Some(2.0));
+        assert_eq!(book.mid_price(), Some(100.0));
+        assert_eq!(book.micro_price(), Some((99.0 * 3.0 + 101.0) / 4.0));
+        // 150 bps around 100 covers 98.5..=101.5
+        assert_eq!(book.depth_within_bps(150.0), Some((1.0, 3.0)));
+        assert_eq!(book.imbalance(1), Some(-0.5));
+
+        let top = book.top_levels(1).unwrap();
+        assert_eq!((top.bids.len(), top.asks.len()), (1, 1));
+        assert_eq!(top.timestamp, 10);
+
+        assert_eq!(book.query_buy_price(4.0).unwrap(), (101.0 * 3.0 + 103.0) / 4.0);
+        assert_eq!(book.query_buy_amount(303.0 + 51.5).unwrap(), 3.5);
+        match book.query_sell_price(5.0) {
+            Err(err) => assert!(matches!(
+                err.downcast_ref::<ExError>(),
+                Some(ExError::InsufficientLiquidity(_))
+            )),
+            Ok(price) => panic!("unexpected price: {}", price),
+        }
+        assert!(book.query_buy_amount(1000.0).is_err());
+    }
+
+    #[test]
+    fn test_book_events() {
+        let mut book = LocalOrderBook::new("btcusdt".into(), None);
+        let snapshot: RawOrderbook =
+            serde_json::from_str(r#"{"lastUpdateId":9,"bids":[["99","1"]],"asks":[["101","1"]]}"#).unwrap();
+        book.save_depth_snapshot(snapshot.into());
+
+        let mut changes = vec![];
+        book.on_depth_event(depth_event(10, 10, r#"[["98","1"]]"#, "[]"), &mut changes);
+        match &changes[..] {
+            [BookEvent::Update(update), BookEvent::Bbo(symbol, Some(bid), Some(ask))] => {
+                assert_eq!(update.bids[0].price, 98.0);
+                assert_eq!(symbol, "btcusdt");
+                assert_eq!((bid.price, ask.price), (99.0, 101.0));
+            }
+            changes => panic!("unexpected changes: {:?}", changes),
+        }
+
+        // below the top, no bbo event
+        changes.clear();
+        book.on_depth_event(depth_event(11, 11, r#"[["97","1"]]"#, "[]"), &mut changes);
+        assert!(matches!(&changes[..], [BookEvent::Update(_)]));
+
+        changes.clear();
+        book.on_depth_event(depth_event(12, 12, r#"[["99","0"]]"#, "[]"), &mut changes);
+        match &changes[..] {
+            [BookEvent::Update(_), BookEvent::Bbo(_, Some(bid), _)] => assert_eq!(bid.price, 98.0),
+            changes => panic!("unexpected changes: {:?}", changes),
+        }
+
+        changes.clear();
+        book.on_depth_event(depth_event(20, 20, "[]", "[]"), &mut changes);
+        assert!(matches!(&changes[..], [BookEvent::Reset(_)]));
+    }
+
+    #[test]
+    fn test_book_health() {
+        let mut book = LocalOrderBook::new("btcusdt".into(), Some(Duration::from_secs(5)));
+        let snapshot: RawOrderbook =
+            serde_json::from_str(r#"{"lastUpdateId":9,"bids":[["99","1"]],"asks":[["101","1"]]}"#).unwrap();
+        book.save_depth_snapshot(snapshot.into());
+
+        let mut changes = vec![];
+        assert!(!book.on_depth_event(depth_event(10, 10, "[]", "[]"), &mut changes));
+        let health = book.health();
+        assert!(health.ready && !health.stale && !health.crossed);
+        assert_eq!((health.gaps, health.crossings, health.resyncs), (0, 0, 0));
+
+        // no diff for longer than stale_after
+        book.last_update = Instant::now().checked_sub(Duration::from_secs(10));
+        assert!(book.is_stale() && !book.is_ready());
+        assert!(book.best_bid().is_none());
+        assert!(book.query_buy_price(0.5).is_err());
+
+        // a fresh diff brings it back
+        book.on_depth_event(depth_event(11, 11, "[]", "[]"), &mut changes);
+        assert!(book.is_ready());
+        assert_eq!(book.query_buy_price(0.5).unwrap(), 101.0);
+
+        // bid through the ask, rebuild
+        changes.clear();
+        assert!(book.on_depth_event(depth_event(12, 12, r#"[["102","1"]]"#, "[]"), &mut changes));
+        assert!(matches!(&changes[..], [BookEvent::Update(_), BookEvent::Reset(_)]));
+        let health = book.health();
+        assert!(!health.ready);
+        assert_eq!((health.gaps, health.crossings, health.resyncs), (0, 1, 1));
+
+        let snapshot: RawOrderbook =
+            serde_json::from_str(r#"{"lastUpdateId":20,"bids":[["99","1"]],"asks":[["101","1"]]}"#).unwrap();
+        book.save_depth_snapshot(snapshot.into());
+        book.on_depth_event(depth_event(21, 21, "[]", "[]"), &mut changes);
+        assert!(book.on_depth_event(depth_event(30, 30, "[]", "[]"), &mut changes));
+        let health = book.health();
+        assert_eq!((health.gaps, health.crossings, health.resyncs), (1, 1, 2));
+    }
+
+    #[test]
+    fn test_book_restore() {
+        let mut book = LocalOrderBook::new("btcusdt".into(), None);
+        assert!(book.state().is_none());
+        let snapshot: RawOrderbook =
+            serde_json::from_str(r#"{"lastUpdateId":9,"bids":[["99","1"],["98","1"]],"asks":[["101","1"]]}"#).unwrap();
+        book.save_depth_snapshot(snapshot.into());
+        book.on_depth_event(depth_event(10, 12, r#"[["99","2"]]"#, "[]"), &mut vec![]);
+        let state = book.state().unwrap();
+        assert_eq!(state.last_update_id, 12);
+        assert_eq!(state.bids, vec![(99.0, 2.0), (98.0, 1.0)]);
+        let state: BookState = serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+
+        // the first diff straddles the saved id, no snapshot needed
+        let mut restored = LocalOrderBook::new("btcusdt".into(), None);
+        restored.restore(state.clone());
+        assert!(!restored.is_ready());
+        assert!(!restored.on_depth_event(depth_event(11, 14, "[]", r#"[["101","0.5"]]"#), &mut vec![]));
+        assert!(restored.is_ready());
+        assert_eq!(restored.query_sell_price(2.0).unwrap(), 99.0);
+        assert_eq!(restored.best_ask().unwrap().amount, 0.5);
+
+        // diffs moved on while it was down, rebuild from rest
+        let mut restored = LocalOrderBook::new("btcusdt".into(), None);
+        restored.restore(state);
+        assert!(restored.on_depth_event(depth_event(20, 21, "[]", "[]"), &mut vec![]));
+        assert!(!restored.is_ready());
+        assert!(restored.state().is_none());
+    }
+
+    #[test]
+    fn test_replay_books() {
+        let dir = std::env::temp_dir().join(format!("rsex-replay-books-{}", std::process::id()));
+        let recorder = Recorder::new(crate::recorder::RecorderConfig::new(&dir));
+        let mut live = LocalOrderBook::new("btcusdt".into(), None);
+        let diff = |first: u64, last: u64, bids: &str, asks: &str| {
+            format!(
+                r#"{{"stream":"btcusdt@depth@100ms","data":{{"e":"depthUpdate","E":1,"s":"BTCUSDT","U":{},"u":{},"b":{},"a":{}}}}}"#,
+                first, last, bids, asks
+            )
+        };
+        let feed = |live: &mut LocalOrderBook, frame: String| {
+            recorder.record("binance", "btcusdt@depth@100ms", &frame).unwrap();
+            if let Ok(WsEvent::DepthEvent(_, event)) = BinanceWs::new("live").deseralize(&frame) {
+                live.on_depth_event(event, &mut vec![]);
+            }
+        };
+        let snapshot = |live: &mut LocalOrderBook, snapshot: &str| {
+            let snapshot: Orderbook = serde_json::from_str::<RawOrderbook>(snapshot).unwrap().into();
+            recorder.record("binance", "btcusdt@snapshot", &snapshot_frame(&snapshot)).unwrap();
+            live.save_depth_snapshot(snapshot);
+        };
+
+        feed(&mut live, diff(8, 10, r#"[["99","1"]]"#, "[]"));
+        snapshot(&mut live, r#"{"lastUpdateId":9,"bids":[["99","2"],["98.5","2"]],"asks":[["101","2"]]}"#);
+        feed(&mut live, diff(11, 12, r#"[["98.5","0"]]"#, r#"[["100.25","0.1"]]"#));
+        recorder.record("binance", DISCONNECTED_STREAM, "heartbeat timeout").unwrap();
+        live.reset();
+        feed(&mut live, diff(30, 31, r#"[["97","1"]]"#, "[]"));
+        snapshot(&mut live, r#"{"lastUpdateId":30,"bids":[["97","3"]],"asks":[["102","1"]]}"#);
+        feed(&mut live, diff(32, 32, "[]", r#"[["102","0.5"]]"#));
+        recorder.close();
+
+        let frames = crate::replay::read_recording(&dir, "binance").unwrap();
+        let books = replay_books(&frames);
+        let replayed = books["btcusdt"].state().unwrap();
+        let live = live.state().unwrap();
+        assert_eq!((replayed.last_update_id, &replayed.bids, &replayed.asks), (32, &live.bids, &live.asks));
+        assert_eq!(live.asks, vec![(102.0, 0.5)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_manager_listeners() {
+        let manager = OrderBookManager::new("http://127.0.0.1:1", "ws://127.0.0.1:1");
+        let events = manager.book_channel("BTCUSDT");
+        let counter = Arc::new(AtomicU64::new(0));
+        let count = counter.clone();
+        let id = manager.subscribe_book("btcusdt", move |_| {
+            count.fetch_add(1, Ordering::Relaxed);
+        });
+
+        dispatch(&manager.listeners, "btcusdt", vec![BookEvent::Reset("btcusdt".into())].into_iter());
+        dispatch(&manager.listeners, "ethusdt", vec![BookEvent::Reset("ethusdt".into())].into_iter());
+        assert!(matches!(events.try_recv(), Ok(BookEvent::Reset(_))));
+        assert!(events.try_recv().is_err());
+        assert_eq!(counter.load(Ordering::Relaxed), 1);
+
+        manager.unsubscribe_book(id);
+        drop(events);
+        dispatch(&manager.listeners, "btcusdt", vec![BookEvent::Reset("btcusdt".into())].into_iter());
+        assert_eq!(counter.load(Ordering::Relaxed), 1);
+        assert!(manager.listeners.lock().unwrap()["btcusdt"].is_empty());
+    }
+
+    #[test]
+    fn test_manager_symbols() {
+        let manager = OrderBookManager::new("http://127.0.0.1:1", "ws://127.0.0.1:1");
+        manager.add_symbol("BTCUSDT");
+        manager.add_symbol("ethusdt");
+        let mut symbols = manager.symbols();
+        symbols.sort();
+        assert_eq!(symbols, vec!["btcusdt", "ethusdt"]);
+        assert!(!manager.is_ready("BTCUSDT"));
+        assert!(manager.query_buy_price("BTCUSDT", 1.0).is_err());
+        assert!(manager.query_sell_price("bnbusdt", 1.0).is_err());
+        assert!(manager.remove_symbol("ETHUSDT").is_some());
+        assert!(manager.remove_symbol("ethusdt").is_none());
+        assert!(manager.get_book("ethusdt").is_none());
+        manager.shutdown();
+    }
+
+    #[test]
+    fn test_manager_save_restore() {
+        let dir = std::env::temp_dir().join(format!("rsex-books-{}", std::process::id()));
+        let manager = OrderBookManager::new("http://127.0.0.1:1", "ws://127.0.0.1:1");
+        manager.add_symbol("ethusdt");
+        manager.restore_symbol(BookState {
+            symbol: "BTCUSDT".into(),
+            last_update_id: 9,
+            bids: vec![(99.0, 1.0)],
+            asks: vec![(101.0, 1.0)],
+        });
+        // ethusdt has no snapshot yet
+        assert_eq!(manager.save_books(&dir).unwrap(), 1);
+        manager.shutdown();
+
+        let manager = OrderBookManager::new("http://127.0.0.1:1", "ws://127.0.0.1:1");
+        fs::write(dir.join("broken.json"), "{").unwrap();
+        assert_eq!(manager.restore_books(&dir).unwrap(), vec!["btcusdt"]);
+        let state = manager.get_book("btcusdt").unwrap().state().unwrap();
+        assert_eq!((state.last_update_id, state.asks), (9, vec![(101.0, 1.0)]));
+        manager.shutdown();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -12,8 +12,8 @@ use reqwest::StatusCode;
 use ring::{digest, hmac};
 use serde_json::Value;
 use std::collections::{BTreeMap, HashMap};
-use std::thread;
-use crate::binance::spot_ws::{BinanceWs, LocalOrderBook};
+use std::sync::Arc;
+use crate::binance::orderbook::OrderBookManager;
 use log::{warn};
 
 
@@ -22,6 +22,12 @@ lazy_static! {
         let mut map = HashMap::new();
         map.insert("get_orderbook", "/api/v3/depth");
         map.insert("get_ticker", "/api/v3/ticker/bookTicker");
+        map.insert("get_price", "/api/v3/ticker/price");
+        map.insert("get_24h_stats", "/api/v3/ticker/24hr");
+        map.insert("get_avg_price", "/api/v3/avgPrice");
+        map.insert("get_trades", "/api/v3/trades");
+        map.insert("get_historical_trades", "/api/v3/historicalTrades");
+        map.insert("get_agg_trades", "/api/v3/aggTrades");
         map.insert("get_kline", "/api/v3/klines");
         map.insert("get_balance", "/api/v3/account");
         map.insert("create_order", "/api/v3/order");
@@ -35,6 +41,12 @@ lazy_static! {
         let mut map = HashMap::new();
         map.insert("get_orderbook", "/api/v3/depth");
         map.insert("get_ticker", "/api/v3/ticker/bookTicker");
+        map.insert("get_price", "/api/v3/ticker/price");
+        map.insert("get_24h_stats", "/api/v3/ticker/24hr");
+        map.insert("get_avg_price", "/api/v3/avgPrice");
+        map.insert("get_trades", "/api/v3/trades");
+        map.insert("get_historical_trades", "/api/v3/historicalTrades");
+        map.insert("get_agg_trades", "/api/v3/aggTrades");
         map.insert("get_kline", "/api/v3/klines");
         map.insert("get_balance", "/sapi/v1/margin/account");
         map.insert("create_order", "/sapi/v1/margin/order");
@@ -42,6 +54,14 @@ lazy_static! {
         map.insert("cancel_all", "/sapi/v1/margin/openOrders"); // maybe not exist
         map.insert("get_order", "/sapi/v1/margin/order");
         map.insert("get_open_orders", "/sapi/v1/margin/openOrders");
+        // borrows on post, lists loans on get, as does repay
+        map.insert("margin_loan", "/sapi/v1/margin/loan");
+        map.insert("margin_repay", "/sapi/v1/margin/repay");
+        map.insert("max_borrowable", "/sapi/v1/margin/maxBorrowable");
+        map.insert("get_interest_history", "/sapi/v1/margin/interestHistory");
+        map.insert("get_isolated_account", "/sapi/v1/margin/isolated/account");
+        map.insert("get_isolated_pair", "/sapi/v1/margin/isolated/pair");
+        map.insert("get_isolated_pairs", "/sapi/v1/margin/isolated/allPairs");
         map
     };
 }
@@ -52,29 +72,65 @@ pub struct Binance {
     secret_key: String,
     host: String,
     is_margin: bool,
+    // orders go to the isolated margin account of their symbol
+    is_isolated: bool,
+    side_effect: Option<bn_types::SideEffectType>,
+    withdraw_whitelist: WithdrawWhitelist,
+    orderbooks: Option<Arc<OrderBookManager>>,
 }
 
 impl Binance {
-    pub fn init_local_orderbook(rest_host: String, steams_ws_url:String, symbols:Vec<String>){
-        thread::spawn(move || {
-            BinanceWs::build_local_orderbook(&steams_ws_url[..],&rest_host[..], symbols);
-        });
+    // starts local orderbooks for `symbols`, query_buy_price/query_sell_price read from them
+    pub fn init_local_orderbook(&mut self, steams_ws_url: &str, symbols: Vec<String>) -> Arc<OrderBookManager> {
+        let manager = Arc::new(OrderBookManager::new(&self.host, steams_ws_url));
+        for symbol in symbols {
+            manager.add_symbol(&symbol);
+        }
+        self.orderbooks = Some(manager.clone());
+        manager
+    }
+
+    // share one manager between several clients
+    pub fn set_orderbook_manager(&mut self, manager: Arc<OrderBookManager>) {
+        self.orderbooks = Some(manager);
     }
+
     pub fn new(api_key: Option<String>, secret_key: Option<String>, rest_host: String) -> Self {
         Binance {
             api_key: api_key.unwrap_or_else(|| "".into()),
             secret_key: secret_key.unwrap_or_else(|| "".into()),
             host:rest_host,
             is_margin: false,
+            is_isolated: false,
+            side_effect: None,
+            withdraw_whitelist: WithdrawWhitelist::default(),
+            orderbooks: None,
         }
     }
 
     pub fn set_margin(&mut self) {
         self.is_margin = true;
+        self.is_isolated = false;
+    }
+
+    pub fn set_isolated_margin(&mut self) {
+        self.is_margin = true;
+        self.is_isolated = true;
     }
 
     pub fn set_spot(&mut self) {
         self.is_margin = false;
+        self.is_isolated = false;
+    }
+
+    // sent with every margin order from now on, None leaves it to binance (NO_SIDE_EFFECT)
+    pub fn set_side_effect(&mut self, side_effect: Option<bn_types::SideEffectType>) {
+        self.side_effect = side_effect;
+    }
+
+    // `tag` is the memo of networks that need one, see WithdrawWhitelist for `network`
+    pub fn allow_withdraw_address(&mut self, asset: &str, network: Option<&str>, address: &str, tag: Option<&str>) {
+        self.withdraw_whitelist.allow(asset, network, address, tag);
     }
 
     pub fn get(&self, endpoint: &str, request: &str) -> APIResult<String> {
@@ -87,6 +143,20 @@ impl Binance {
         self.handler(response)
     }
 
+    // market data that only needs the api key
+    pub fn get_with_key(&self, endpoint: &str, request: &str) -> APIResult<String> {
+        let mut url: String = format!("{}{}", self.host, endpoint);
+        if !request.is_empty() {
+            url.push_str(format!("?{}", request).as_str());
+        }
+        let client = reqwest::blocking::Client::new();
+        let resp = client
+            .get(url.as_str())
+            .headers(self.build_headers(false)?)
+            .send()?;
+        self.handler(resp)
+    }
+
     pub fn post(&self, endpoint: &str) -> APIResult<String> {
         let url: String = format!("{}{}", self.host, endpoint);
         let client = reqwest::blocking::Client::new();
@@ -247,25 +317,151 @@ impl Binance {
         Ok(resp)
     }
 
+    // without a symbol binance answers for every market
+    pub fn get_book_tickers_raw(&self) -> APIResult<Vec<bn_types::RawTicker>> {
+        let ret = self.get(self.uri("get_ticker"), "")?;
+        let bn_types::BookTickers::AllBookTickers(tickers) = serde_json::from_str(&ret)?;
+        Ok(tickers)
+    }
+
+    pub fn get_prices_raw(&self) -> APIResult<Vec<bn_types::SymbolPrice>> {
+        let ret = self.get(self.uri("get_price"), "")?;
+        let bn_types::Prices::AllPrices(prices) = serde_json::from_str(&ret)?;
+        Ok(prices)
+    }
+
+    pub fn get_24h_stats_raw(&self, symbol: &str) -> APIResult<bn_types::PriceStats> {
+        let params = format!("symbol={}", symbol.to_ascii_uppercase());
+        let ret = self.get(self.uri("get_24h_stats"), &params)?;
+        let resp: bn_types::PriceStats = serde_json::from_str(&ret)?;
+        Ok(resp)
+    }
+
+    pub fn get_all_24h_stats_raw(&self) -> APIResult<Vec<bn_types::PriceStats>> {
+        let ret = self.get(self.uri("get_24h_stats"), "")?;
+        let resp: Vec<bn_types::PriceStats> = serde_json::from_str(&ret)?;
+        Ok(resp)
+    }
+
+    pub fn get_avg_price_raw(&self, symbol: &str) -> APIResult<bn_types::AveragePrice> {
+        let params = format!("symbol={}", symbol.to_ascii_uppercase());
+        let ret = self.get(self.uri("get_avg_price"), &params)?;
+        let resp: bn_types::AveragePrice = serde_json::from_str(&ret)?;
+        Ok(resp)
+    }
+
+    // oldest first, at most 1000
+    pub fn get_recent_trades_raw(&self, symbol: &str, limit: u16) -> APIResult<Vec<Trade>> {
+        let symbol = symbol.to_ascii_uppercase();
+        let params = format!("symbol={}&limit={}", symbol, limit);
+        let ret = self.get(self.uri("get_trades"), &params)?;
+        let resp: Vec<bn_types::RawTrade> = serde_json::from_str(&ret)?;
+        Ok(with_symbol(resp, &symbol))
+    }
+
+    // `limit` trades from trade id `from_id` on, needs the api key
+    pub fn get_historical_trades(&self, symbol: &str, from_id: u64, limit: u16) -> APIResult<Vec<Trade>> {
+        let symbol = symbol.to_ascii_uppercase();
+        let params = format!("symbol={}&fromId={}&limit={}", symbol, from_id, limit);
+        let ret = self.get_with_key(self.uri("get_historical_trades"), &params)?;
+        let resp: Vec<bn_types::RawTrade> = serde_json::from_str(&ret)?;
+        Ok(with_symbol(resp, &symbol))
+    }
+
+    // every aggregate trade in [start, end] (ms), binance serves at most an hour per time
+    // query, so each hour is paged on by aggregate id
+    pub fn get_agg_trades(&self, symbol: &str, start: u64, end: u64) -> APIResult<Vec<Trade>> {
+        const PAGE: usize = 1000;
+        const WINDOW: u64 = 3600 * 1000;
+        let symbol = symbol.to_ascii_uppercase();
+        let mut trades = BTreeMap::new();
+        let mut from = start;
+        while from <= end {
+            let until = (from + WINDOW - 1).min(end);
+            let mut params = format!("symbol={}&startTime={}&endTime={}&limit={}", symbol, from, until, PAGE);
+            loop {
+                let ret = self.get(self.uri("get_agg_trades"), &params)?;
+                let page: Vec<bn_types::RawAggTrade> = serde_json::from_str(&ret)?;
+                let next = match page.last() {
+                    Some(last) if page.len() == PAGE && last.time <= until => Some(last.id + 1),
+                    _ => None,
+                };
+                for trade in with_symbol(page, &symbol) {
+                    if trade.timestamp >= from && trade.timestamp <= until {
+                        trades.insert(trade.id, trade);
+                    }
+                }
+                match next {
+                    Some(id) => params = format!("symbol={}&fromId={}&limit={}", symbol, id, PAGE),
+                    None => break,
+                }
+            }
+            from = until + 1;
+        }
+        Ok(trades.into_values().collect())
+    }
+
+    fn uri(&self, name: &str) -> &'static str {
+        if self.is_margin {
+            MARGIN_URI.get(name).unwrap()
+        } else {
+            SPOT_URI.get(name).unwrap()
+        }
+    }
+
     pub fn get_kline_raw(&self, symbol: &str, period: &str, limit: u16) -> APIResult<Vec<Kline>> {
+        let params = format!("symbol={}&interval={}&limit={}", symbol, period, limit);
+        self.get_klines(&params)
+    }
+
+    // pages forward from `start` with the largest page binance serves
+    pub fn get_kline_range_raw(
+        &self,
+        symbol: &str,
+        interval: KlineInterval,
+        start: u64,
+        end: u64,
+    ) -> APIResult<Vec<Kline>> {
+        const PAGE: usize = 1000;
+        let mut klines = BTreeMap::new();
+        let mut cursor = start;
+        while cursor <= end {
+            let params = format!(
+                "symbol={}&interval={}&startTime={}&endTime={}&limit={}",
+                symbol.to_ascii_uppercase(),
+                bn_types::kline_interval(interval),
+                cursor,
+                end,
+                PAGE
+            );
+            let page = self.get_klines(&params)?;
+            let full = page.len() == PAGE;
+            let last = match page.last() {
+                Some(kline) => kline.timestamp,
+                None => break,
+            };
+            for kline in page {
+                klines.insert(kline.timestamp, kline);
+            }
+            if !full || last < cursor {
+                break;
+            }
+            cursor = last + 1;
+        }
+        Ok(klines.into_values().collect())
+    }
+
+    fn get_klines(&self, params: &str) -> APIResult<Vec<Kline>> {
         let uri = if self.is_margin {
             MARGIN_URI.get("get_kline").unwrap()
         } else {
             SPOT_URI.get("get_kline").unwrap()
         };
-        let params = format!("symbol={}&interval={}&limit={}", symbol, period, limit);
-        let ret = self.get(uri, &params)?;
+        let ret = self.get(uri, params)?;
         let resp: Vec<Vec<Value>> = serde_json::from_str(&ret)?;
         let klines = resp
             .iter()
-            .map(|kline| Kline {
-                timestamp: to_i64(&kline[0]) as u64,
-                open: to_f64(&kline[1]),
-                high: to_f64(&kline[2]),
-                low: to_f64(&kline[3]),
-                close: to_f64(&kline[4]),
-                volume: to_f64(&kline[5]),
-            })
+            .map(|kline| bn_types::KlineSummary::from(kline).into())
             .collect::<Vec<Kline>>();
 
         Ok(klines)
@@ -374,6 +570,7 @@ impl Binance {
         params.insert("timeInForce".into(), "GTC".into());
         params.insert("quantity".into(), amount.to_string());
         params.insert("price".into(), price.to_string());
+        self.add_order_params(&mut params);
         let req = self.build_signed_request(params)?;
         let ret = self.post_signed(uri, &req)?;
         let resp: bn_types::OrderResult = serde_json::from_str(&ret)?;
@@ -383,13 +580,14 @@ impl Binance {
 
     pub fn create_order_with_param(
         &self,
-        params: BTreeMap<String, String>
+        mut params: BTreeMap<String, String>
     ) -> APIResult<String> {
         let uri = if self.is_margin {
             MARGIN_URI.get("create_order").unwrap()
         } else {
             SPOT_URI.get("create_order").unwrap()
         };
+        self.add_order_params(&mut params);
         let req = self.build_signed_request(params)?;
         let ret = self.post_signed(uri, &req)?;
         let resp: bn_types::OrderResult = serde_json::from_str(&ret)?;
@@ -438,6 +636,7 @@ impl Binance {
         if client_order_id.len()>0{
             params.insert("newClientOrderId".into(), client_order_id.to_string());
         }
+        self.add_order_params(&mut params);
         let req = self.build_signed_request(params)?;
         let ret = self.post_signed(uri, &req)?;
         let resp: bn_types::OrderResult = serde_json::from_str(&ret)?;
@@ -454,6 +653,7 @@ impl Binance {
         let mut params: BTreeMap<String, String> = BTreeMap::new();
         params.insert("symbol".into(), symbol.into());
         params.insert("orderId".into(), id.into());
+        self.add_margin_params(&mut params);
         let req = self.build_signed_request(params)?;
         let _ret = self.delete_signed(uri, &req)?;
         Ok(true)
@@ -467,6 +667,7 @@ impl Binance {
         };
         let mut params: BTreeMap<String, String> = BTreeMap::new();
         params.insert("symbol".into(), symbol.into());
+        self.add_margin_params(&mut params);
         let req = self.build_signed_request(params)?;
         let _ret = self.delete_signed(uri, &req)?;
         Ok(true)
@@ -481,6 +682,7 @@ impl Binance {
         let mut params: BTreeMap<String, String> = BTreeMap::new();
         params.insert("symbol".into(), symbol.into());
         params.insert("orderId".into(), id.into());
+        self.add_margin_params(&mut params);
         let req = self.build_signed_request(params)?;
         let ret = self.get_signed(uri, &req)?;
         let resp: bn_types::RawOrder = serde_json::from_str(&ret)?;
@@ -497,6 +699,7 @@ impl Binance {
         let mut params: BTreeMap<String, String> = BTreeMap::new();
         params.insert("symbol".into(), symbol.into());
         params.insert("origClientOrderId".into(), client_order_id.into());
+        self.add_margin_params(&mut params);
         let req = self.build_signed_request(params)?;
         let ret = self.get_signed(uri, &req)?;
         let resp: bn_types::RawOrder = serde_json::from_str(&ret)?;
@@ -512,6 +715,7 @@ impl Binance {
         };
         let mut params: BTreeMap<String, String> = BTreeMap::new();
         params.insert("symbol".into(), symbol.into());
+        self.add_margin_params(&mut params);
         let req = self.build_signed_request(params)?;
         let ret = self.get_signed(uri, &req)?;
         let resp: Vec<bn_types::RawOrder> = serde_json::from_str(&ret)?;
@@ -519,6 +723,250 @@ impl Binance {
         Ok(resp)
     }
 
+    // isIsolated on every request to a margin order endpoint of an isolated account
+    fn add_margin_params(&self, params: &mut BTreeMap<String, String>) {
+        if self.is_margin && self.is_isolated {
+            params.insert("isIsolated".into(), "TRUE".into());
+        }
+    }
+
+    fn add_order_params(&self, params: &mut BTreeMap<String, String>) {
+        self.add_margin_params(params);
+        if let (true, Some(side_effect)) = (self.is_margin, self.side_effect) {
+            params.insert("sideEffectType".into(), side_effect.as_str().into());
+        }
+    }
+
+    pub fn margin_borrow_raw(
+        &self,
+        asset: &str,
+        amount: f64,
+        isolated_symbol: Option<&str>,
+    ) -> APIResult<bn_types::MarginTransaction> {
+        self.margin_transfer("margin_loan", asset, amount, isolated_symbol)
+    }
+
+    pub fn margin_repay_raw(
+        &self,
+        asset: &str,
+        amount: f64,
+        isolated_symbol: Option<&str>,
+    ) -> APIResult<bn_types::MarginTransaction> {
+        self.margin_transfer("margin_repay", asset, amount, isolated_symbol)
+    }
+
+    fn margin_transfer(
+        &self,
+        name: &str,
+        asset: &str,
+        amount: f64,
+        isolated_symbol: Option<&str>,
+    ) -> APIResult<bn_types::MarginTransaction> {
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        params.insert("asset".into(), asset.to_ascii_uppercase());
+        params.insert("amount".into(), amount.to_string());
+        if let Some(symbol) = isolated_symbol {
+            params.insert("isIsolated".into(), "TRUE".into());
+            params.insert("symbol".into(), symbol.to_ascii_uppercase());
+        }
+        let req = self.build_signed_request(params)?;
+        let ret = self.post_signed(MARGIN_URI.get(name).unwrap(), &req)?;
+        let resp: bn_types::MarginTransaction = serde_json::from_str(&ret)?;
+        Ok(resp)
+    }
+
+    pub fn max_borrowable_raw(&self, asset: &str, isolated_symbol: Option<&str>) -> APIResult<bn_types::MaxBorrowable> {
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        params.insert("asset".into(), asset.to_ascii_uppercase());
+        if let Some(symbol) = isolated_symbol {
+            params.insert("isolatedSymbol".into(), symbol.to_ascii_uppercase());
+        }
+        let req = self.build_signed_request(params)?;
+        let ret = self.get_signed(MARGIN_URI.get("max_borrowable").unwrap(), &req)?;
+        let resp: bn_types::MaxBorrowable = serde_json::from_str(&ret)?;
+        Ok(resp)
+    }
+
+    pub fn get_margin_account_raw(&self) -> APIResult<bn_types::MarginAccountInfo> {
+        let req = self.build_signed_request(BTreeMap::new())?;
+        let ret = self.get_signed(MARGIN_URI.get("get_balance").unwrap(), &req)?;
+        let resp: bn_types::MarginAccountInfo = serde_json::from_str(&ret)?;
+        Ok(resp)
+    }
+
+    // every isolated pair of the account without `symbols`, binance takes at most 5
+    pub fn get_isolated_account_raw(&self, symbols: &[&str]) -> APIResult<bn_types::IsolatedAccountInfo> {
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        if !symbols.is_empty() {
+            params.insert("symbols".into(), symbols.join(",").to_ascii_uppercase());
+        }
+        let req = self.build_signed_request(params)?;
+        let ret = self.get_signed(MARGIN_URI.get("get_isolated_account").unwrap(), &req)?;
+        let resp: bn_types::IsolatedAccountInfo = serde_json::from_str(&ret)?;
+        Ok(resp)
+    }
+
+    pub fn get_isolated_pair(&self, symbol: &str) -> APIResult<bn_types::IsolatedSymbol> {
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        params.insert("symbol".into(), symbol.to_ascii_uppercase());
+        let req = self.build_signed_request(params)?;
+        let ret = self.get_signed(MARGIN_URI.get("get_isolated_pair").unwrap(), &req)?;
+        let resp: bn_types::IsolatedSymbol = serde_json::from_str(&ret)?;
+        Ok(resp)
+    }
+
+    pub fn get_isolated_pairs(&self) -> APIResult<Vec<bn_types::IsolatedSymbol>> {
+        let req = self.build_signed_request(BTreeMap::new())?;
+        let ret = self.get_signed(MARGIN_URI.get("get_isolated_pairs").unwrap(), &req)?;
+        let resp: Vec<bn_types::IsolatedSymbol> = serde_json::from_str(&ret)?;
+        Ok(resp)
+    }
+
+    // binance defaults to the last 7 days and serves at most 30 days before `end`
+    pub fn get_loan_history_raw(
+        &self,
+        asset: &str,
+        isolated_symbol: Option<&str>,
+        start: Option<u64>,
+        end: Option<u64>,
+    ) -> APIResult<Vec<bn_types::RawMarginLoan>> {
+        self.get_margin_records("margin_loan", asset, isolated_symbol, start, end)
+    }
+
+    pub fn get_repay_history_raw(
+        &self,
+        asset: &str,
+        isolated_symbol: Option<&str>,
+        start: Option<u64>,
+        end: Option<u64>,
+    ) -> APIResult<Vec<bn_types::RawMarginRepay>> {
+        self.get_margin_records("margin_repay", asset, isolated_symbol, start, end)
+    }
+
+    pub fn get_interest_history_raw(
+        &self,
+        asset: &str,
+        isolated_symbol: Option<&str>,
+        start: Option<u64>,
+        end: Option<u64>,
+    ) -> APIResult<Vec<bn_types::RawMarginInterest>> {
+        self.get_margin_records("get_interest_history", asset, isolated_symbol, start, end)
+    }
+
+    // every page of a record endpoint, newest first
+    fn get_margin_records<T: serde::de::DeserializeOwned>(
+        &self,
+        name: &str,
+        asset: &str,
+        isolated_symbol: Option<&str>,
+        start: Option<u64>,
+        end: Option<u64>,
+    ) -> APIResult<Vec<T>> {
+        const PAGE: usize = 100;
+        let mut records = vec![];
+        for current in 1.. {
+            let mut params: BTreeMap<String, String> = BTreeMap::new();
+            params.insert("asset".into(), asset.to_ascii_uppercase());
+            if let Some(symbol) = isolated_symbol {
+                params.insert("isolatedSymbol".into(), symbol.to_ascii_uppercase());
+            }
+            if let Some(start) = start {
+                params.insert("startTime".into(), start.to_string());
+            }
+            if let Some(end) = end {
+                params.insert("endTime".into(), end.to_string());
+            }
+            params.insert("current".into(), current.to_string());
+            params.insert("size".into(), PAGE.to_string());
+            let req = self.build_signed_request(params)?;
+            let ret = self.get_signed(MARGIN_URI.get(name).unwrap(), &req)?;
+            let page: bn_types::MarginRecords<T> = serde_json::from_str(&ret)?;
+            let full = page.rows.len() == PAGE;
+            records.extend(page.rows);
+            if !full || records.len() as u64 >= page.total {
+                break;
+            }
+        }
+        Ok(records)
+    }
+
+    // every coin of the wallet with its networks
+    pub fn get_coins(&self) -> APIResult<Vec<bn_types::CoinInfo>> {
+        let req = self.build_signed_request(BTreeMap::new())?;
+        let ret = self.get_signed("/sapi/v1/capital/config/getall", &req)?;
+        let resp: Vec<bn_types::CoinInfo> = serde_json::from_str(&ret)?;
+        Ok(resp)
+    }
+
+    pub fn get_deposit_address_raw(&self, coin: &str, network: Option<&str>) -> APIResult<bn_types::RawDepositAddress> {
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        params.insert("coin".into(), coin.to_ascii_uppercase());
+        if let Some(network) = network {
+            params.insert("network".into(), network.into());
+        }
+        let req = self.build_signed_request(params)?;
+        let ret = self.get_signed("/sapi/v1/capital/deposit/address", &req)?;
+        let resp: bn_types::RawDepositAddress = serde_json::from_str(&ret)?;
+        Ok(resp)
+    }
+
+    // binance defaults to the last 90 days
+    pub fn get_deposit_history_raw(&self, coin: &str) -> APIResult<Vec<bn_types::RawDeposit>> {
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        params.insert("coin".into(), coin.to_ascii_uppercase());
+        let req = self.build_signed_request(params)?;
+        let ret = self.get_signed("/sapi/v1/capital/deposit/hisrec", &req)?;
+        let resp: Vec<bn_types::RawDeposit> = serde_json::from_str(&ret)?;
+        Ok(resp)
+    }
+
+    pub fn get_withdraw_history_raw(&self, coin: &str) -> APIResult<Vec<bn_types::RawWithdraw>> {
+        self.query_withdraws(coin, None)
+    }
+
+    // looks the withdrawal up by id instead of scanning the default history window
+    pub fn get_withdraw_raw(&self, coin: &str, id: &str) -> APIResult<Option<bn_types::RawWithdraw>> {
+        let resp = self.query_withdraws(coin, Some(id))?;
+        Ok(resp.into_iter().find(|withdraw| withdraw.id == id))
+    }
+
+    fn query_withdraws(&self, coin: &str, id: Option<&str>) -> APIResult<Vec<bn_types::RawWithdraw>> {
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        params.insert("coin".into(), coin.to_ascii_uppercase());
+        if let Some(id) = id {
+            params.insert("idList".into(), id.into());
+        }
+        let req = self.build_signed_request(params)?;
+        let ret = self.get_signed("/sapi/v1/capital/withdraw/history", &req)?;
+        let resp: Vec<bn_types::RawWithdraw> = serde_json::from_str(&ret)?;
+        Ok(resp)
+    }
+
+    // no whitelist check, only reachable through Wallet::withdraw
+    fn withdraw_raw(
+        &self,
+        coin: &str,
+        network: Option<&str>,
+        address: &str,
+        tag: Option<&str>,
+        amount: f64,
+    ) -> APIResult<bn_types::WithdrawResult> {
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        params.insert("coin".into(), coin.to_ascii_uppercase());
+        params.insert("address".into(), address.into());
+        params.insert("amount".into(), amount.to_string());
+        if let Some(network) = network {
+            params.insert("network".into(), network.into());
+        }
+        if let Some(tag) = tag {
+            params.insert("addressTag".into(), tag.into());
+        }
+        let req = self.build_signed_request(params)?;
+        let ret = self.post_signed("/sapi/v1/capital/withdraw/apply", &req)?;
+        let resp: bn_types::WithdrawResult = serde_json::from_str(&ret)?;
+        Ok(resp)
+    }
+
     pub fn get_history_orders_raw(&self, symbol: &str) -> APIResult<Vec<bn_types::RawOrder>> {
         let uri = "/api/v3/allOrders";
         let mut params: BTreeMap<String, String> = BTreeMap::new();
@@ -536,6 +984,15 @@ impl Binance {
     }
 }
 
+fn with_symbol<T: Into<Trade>>(raw: Vec<T>, symbol: &str) -> Vec<Trade> {
+    raw.into_iter()
+        .map(|trade| Trade {
+            symbol: symbol.to_string(),
+            ..trade.into()
+        })
+        .collect()
+}
+
 impl SpotRest for Binance {
     fn get_symbols(&self) -> APIResult<Vec<SymbolInfo>> {
         let raw = self.get_symbols_raw()?;
@@ -552,10 +1009,46 @@ impl SpotRest for Binance {
         Ok(raw.into())
     }
 
+    fn get_all_book_tickers(&self) -> APIResult<HashMap<String, Ticker>> {
+        let tickers = self.get_book_tickers_raw()?;
+        Ok(tickers
+            .into_iter()
+            .map(|ticker| (ticker.symbol.clone(), ticker.into()))
+            .collect())
+    }
+
+    fn get_all_prices(&self) -> APIResult<HashMap<String, f64>> {
+        let prices = self.get_prices_raw()?;
+        Ok(prices.into_iter().map(|price| (price.symbol, price.price)).collect())
+    }
+
+    fn get_24h_stats(&self, symbol: &str) -> APIResult<DayStats> {
+        let raw = self.get_24h_stats_raw(symbol)?;
+        Ok(raw.into())
+    }
+
+    fn get_all_24h_stats(&self) -> APIResult<Vec<DayStats>> {
+        let raw = self.get_all_24h_stats_raw()?;
+        Ok(raw.into_iter().map(|stats| stats.into()).collect())
+    }
+
+    fn get_avg_price(&self, symbol: &str) -> APIResult<f64> {
+        let raw = self.get_avg_price_raw(symbol)?;
+        Ok(raw.price)
+    }
+
+    fn get_recent_trades(&self, symbol: &str, limit: u16) -> APIResult<Vec<Trade>> {
+        self.get_recent_trades_raw(symbol, limit)
+    }
+
     fn get_kline(&self, symbol: &str, period: &str, limit: u16) -> APIResult<Vec<Kline>> {
         self.get_kline_raw(symbol, period, limit)
     }
 
+    fn get_kline_range(&self, symbol: &str, interval: KlineInterval, start: u64, end: u64) -> APIResult<Vec<Kline>> {
+        self.get_kline_range_raw(symbol, interval, start, end)
+    }
+
     fn get_balance(&self, asset: &str) -> APIResult<Balance> {
         self.get_balance_raw(asset)
     }
@@ -635,9 +1128,13 @@ impl SpotRest for Binance {
 
     fn query_buy_price(&self, symbol: &str, amount: f64) -> (f64, bool) {
         loop {
-            let (price, from_ws) = LocalOrderBook::query_buy_price(symbol, amount);
-            if from_ws{
-                return (price, from_ws)
+            if let Some(books) = &self.orderbooks {
+                match books.query_buy_price(symbol, amount) {
+                    Ok(price) => return (price, true),
+                    // the trait has no error channel, keep the old sentinel for a thin book
+                    Err(err) if is_insufficient_liquidity(err.as_ref()) => return (999999999999.0, true),
+                    Err(_) => {}
+                }
             }
             match self.get_ticker(symbol){
                 Ok(result) => {
@@ -652,9 +1149,12 @@ impl SpotRest for Binance {
 
     fn query_sell_price(&self, symbol: &str, amount: f64) -> (f64, bool) {
         loop {
-            let (price, from_ws) = LocalOrderBook::query_sell_price(symbol, amount);
-            if from_ws{
-                return (price, from_ws)
+            if let Some(books) = &self.orderbooks {
+                match books.query_sell_price(symbol, amount) {
+                    Ok(price) => return (price, true),
+                    Err(err) if is_insufficient_liquidity(err.as_ref()) => return (0.0, true),
+                    Err(_) => {}
+                }
             }
             match self.get_ticker(symbol){
                 Ok(result) => {
@@ -668,6 +1168,116 @@ impl SpotRest for Binance {
     }
 }
 
+impl MarginRest for Binance {
+    fn margin_borrow(&self, asset: &str, amount: f64, isolated_symbol: Option<&str>) -> APIResult<String> {
+        let raw = self.margin_borrow_raw(asset, amount, isolated_symbol)?;
+        Ok(raw.tran_id.to_string())
+    }
+
+    fn margin_repay(&self, asset: &str, amount: f64, isolated_symbol: Option<&str>) -> APIResult<String> {
+        let raw = self.margin_repay_raw(asset, amount, isolated_symbol)?;
+        Ok(raw.tran_id.to_string())
+    }
+
+    fn max_borrowable(&self, asset: &str, isolated_symbol: Option<&str>) -> APIResult<f64> {
+        let raw = self.max_borrowable_raw(asset, isolated_symbol)?;
+        Ok(raw.amount)
+    }
+
+    fn get_margin_account(&self, isolated_symbol: Option<&str>) -> APIResult<MarginAccount> {
+        let symbol = match isolated_symbol {
+            Some(symbol) => symbol,
+            None => return Ok(self.get_margin_account_raw()?.into()),
+        };
+        let raw = self.get_isolated_account_raw(&[symbol])?;
+        match raw.assets.into_iter().next() {
+            Some(pair) => Ok(pair.into()),
+            None => Err(Box::new(ExError::ApiError(format!("no isolated margin account for {}", symbol)))),
+        }
+    }
+
+    fn get_loan_history(&self, asset: &str, isolated_symbol: Option<&str>) -> APIResult<Vec<MarginLoan>> {
+        let raw = self.get_loan_history_raw(asset, isolated_symbol, None, None)?;
+        Ok(raw.into_iter().map(|loan| loan.into()).collect())
+    }
+
+    fn get_interest_history(&self, asset: &str, isolated_symbol: Option<&str>) -> APIResult<Vec<MarginInterest>> {
+        let raw = self.get_interest_history_raw(asset, isolated_symbol, None, None)?;
+        Ok(raw.into_iter().map(|interest| interest.into()).collect())
+    }
+}
+
+impl Wallet for Binance {
+    fn get_networks(&self, asset: &str) -> APIResult<Vec<WalletNetwork>> {
+        let asset = asset.to_ascii_uppercase();
+        let coins = self.get_coins()?;
+        Ok(coins
+            .into_iter()
+            .filter(|coin| coin.coin == asset)
+            .flat_map(|coin| coin.network_list)
+            .map(|network| network.into())
+            .collect())
+    }
+
+    fn get_deposit_address(&self, asset: &str, network: Option<&str>) -> APIResult<DepositAddress> {
+        let raw = self.get_deposit_address_raw(asset, network)?;
+        // binance answers with the default network's address without naming it
+        let network = match network {
+            Some(network) => network.to_string(),
+            None => self
+                .get_networks(asset)?
+                .into_iter()
+                .find(|network| network.is_default)
+                .map(|network| network.network)
+                .unwrap_or_default(),
+        };
+        Ok(DepositAddress {
+            asset: raw.coin,
+            network,
+            address: raw.address,
+            tag: raw.tag,
+        })
+    }
+
+    fn get_deposit_history(&self, asset: &str) -> APIResult<Vec<WalletTransfer>> {
+        let raw = self.get_deposit_history_raw(asset)?;
+        Ok(raw.into_iter().map(|deposit| deposit.into()).collect())
+    }
+
+    fn get_withdraw_history(&self, asset: &str) -> APIResult<Vec<WalletTransfer>> {
+        let raw = self.get_withdraw_history_raw(asset)?;
+        Ok(raw.into_iter().map(|withdraw| withdraw.into()).collect())
+    }
+
+    fn withdraw(
+        &self,
+        asset: &str,
+        network: Option<&str>,
+        address: &str,
+        tag: Option<&str>,
+        amount: f64,
+    ) -> APIResult<String> {
+        self.withdraw_whitelist.check(asset, network, address, tag)?;
+        let raw = self.withdraw_raw(asset, network, address, tag, amount)?;
+        Ok(raw.id)
+    }
+
+    fn cancel_withdraw(&self, _id: &str) -> APIResult<bool> {
+        Err(Box::new(ExError::ApiError("binance can not cancel withdrawals over the api".into())))
+    }
+
+    fn get_withdraw(&self, asset: &str, id: &str) -> APIResult<WalletTransfer> {
+        match self.get_withdraw_raw(asset, id)? {
+            Some(withdraw) => Ok(withdraw.into()),
+            None => Err(Box::new(ExError::ApiError(format!("withdraw {} not found", id)))),
+        }
+    }
+}
+
+fn is_insufficient_liquidity(err: &(dyn std::error::Error + 'static)) -> bool {
+    matches!(err.downcast_ref::<ExError>(), Some(ExError::InsufficientLiquidity(_)))
+}
+
 #[cfg(test)]
 mod test {
     #![allow(dead_code)]
@@ -679,6 +1289,35 @@ mod test {
         "lCuul7mVApKczbGJBrAgqEIWTWwbQ1BTMBPJyvK19q2BNmlsd5718cAWWByNuY5N";
     const HOST: &'static str = "https://api.binance.com";
 
+    #[test]
+    fn test_margin_order_params() {
+        let mut api = Binance::new(None, None, HOST.into());
+        let params = |api: &Binance| {
+            let mut params = BTreeMap::new();
+            api.add_order_params(&mut params);
+            params
+        };
+        api.set_side_effect(Some(bn_types::SideEffectType::MarginBuy));
+        assert!(params(&api).is_empty());
+
+        api.set_margin();
+        let margin = params(&api);
+        assert_eq!(margin.get("sideEffectType").map(String::as_str), Some("MARGIN_BUY"));
+        assert!(!margin.contains_key("isIsolated"));
+
+        api.set_isolated_margin();
+        let isolated = params(&api);
+        assert_eq!(isolated.get("isIsolated").map(String::as_str), Some("TRUE"));
+        assert_eq!(isolated.get("sideEffectType").map(String::as_str), Some("MARGIN_BUY"));
+
+        api.set_side_effect(None);
+        assert!(!params(&api).contains_key("sideEffectType"));
+
+        api.set_spot();
+        api.set_side_effect(Some(bn_types::SideEffectType::AutoRepay));
+        assert!(params(&api).is_empty());
+    }
+
     //#[test]
     fn test_get_orderbook() {
         let api = Binance::new(None, None, "https://www.binancezh.com".to_string());
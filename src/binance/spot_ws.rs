@@ -1,32 +1,48 @@
 use crate::binance::types::*;
 use crate::errors::*;
 use crate::models::*;
+use crate::recorder::Recorder;
+use crate::replay::*;
 use crate::traits::*;
 
+use crate::websocket::*;
+use futures_channel::mpsc::UnboundedReceiver;
 use log::{info, warn};
-use ws::{Handler, Handshake, Message, Result, Sender};
+use serde_json::{json, Value};
 use std::thread;
-use ordered_float::OrderedFloat;
 use std::collections::HashMap;
-use std::collections::BTreeMap;
 use lazy_static::lazy_static;
-use std::sync::{RwLock, Arc};
-use crate::binance::spot_rest::Binance;
-use std::iter::FromIterator;
-// use std::ops::Index;
+use std::sync::{mpsc, Arc, Mutex};
 
 
 //static WEBSOCKET_URL: &str = "wss://stream.binance.com:9443/ws/btcusdt@depth20";
 
 #[derive(Debug)]
 pub enum WsEvent {
+    // connection lifecycle
+    Connected,
+    Disconnected(String),
+    Resubscribed(usize),
+
     // public stream
     OrderbookEvent(String, Orderbook),
     DepthEvent(String, DepthOrderbookEvent),
     KlineEvent(String, Kline),
+    // best bid and ask, also from !bookTicker
     TickerEvent(String, Ticker),
+    // aggTrade, the id is the aggregate trade id
     TradeEvent(Trade),
+    // @trade, one event per trade with its trade id
+    RawTradeEvent(Trade),
+    MiniTickerEvent(String, DayStats),
+    DayTickerEvent(String, DayStats),
+    // every market that changed in the last second
+    AllMiniTickersEvent(Vec<DayStats>),
+    AllDayTickersEvent(Vec<DayStats>),
+    AvgPriceEvent(String, f64),
     ResponseEvent(ResponseEvent),
+    // stream or event type we have no typed struct for, with its payload
+    RawEvent(String, Value),
 
     // private stream
     AccountUpdateEvent(AccountUpdateEvent),
@@ -35,24 +51,85 @@ pub enum WsEvent {
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ResponseEvent {
-    id: u64,
-    result: Option<String>,
+    pub id: u64,
+    #[serde(default)]
+    pub result: Option<Value>,
+    #[serde(default)]
+    pub error: Option<ResponseError>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResponseError {
+    pub code: i64,
+    pub msg: String,
+}
+
+// levels of the partial book stream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DepthLevels {
+    Five,
+    Ten,
+    Twenty,
+}
+
+// push interval of the depth streams, binance defaults to 1000ms
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UpdateSpeed {
+    Ms100,
+    Ms1000,
+}
+
+impl UpdateSpeed {
+    fn suffix(self) -> &'static str {
+        match self {
+            UpdateSpeed::Ms100 => "@100ms",
+            UpdateSpeed::Ms1000 => "",
+        }
+    }
+}
+
+// btcusdt@depth20@100ms
+pub fn partial_depth_stream(symbol: &str, levels: DepthLevels, speed: UpdateSpeed) -> String {
+    let levels = match levels {
+        DepthLevels::Five => 5,
+        DepthLevels::Ten => 10,
+        DepthLevels::Twenty => 20,
+    };
+    format!("{}@depth{}{}", symbol.to_lowercase(), levels, speed.suffix())
+}
+
+// btcusdt@depth@100ms
+pub fn diff_depth_stream(symbol: &str, speed: UpdateSpeed) -> String {
+    format!("{}@depth{}", symbol.to_lowercase(), speed.suffix())
 }
 
 pub struct BinanceWs<'a> {
     host: String,
-    subs: Vec<String>,
-    out: Option<Sender>,
-
-    handler: Box<dyn FnMut(WsEvent) -> Result<()> + 'a>,
+    state: Arc<Mutex<SubState>>,
+    out: Option<Outbox>,
+    config: ReconnectConfig,
+    ws_config: WsConfig,
+    resubscribe: bool,
+    // what sub_orderbook subscribes to
+    partial_depth: (DepthLevels, UpdateSpeed),
+    recorder: Option<Recorder>,
+    closed_klines_only: bool,
+
+    handler: Box<dyn FnMut(WsEvent) -> APIResult<()> + 'a>,
 }
 
 impl<'a> BinanceWs<'a> {
     pub fn new(host: &str) -> Self {
         BinanceWs {
             host: host.into(),
-            subs: vec![],
+            state: Arc::new(Mutex::new(SubState::new())),
             out: None,
+            config: ReconnectConfig::binance(),
+            ws_config: WsConfig::default(),
+            resubscribe: false,
+            partial_depth: (DepthLevels::Twenty, UpdateSpeed::Ms1000),
+            recorder: None,
+            closed_klines_only: false,
             handler: Box::new(|event| {
                 info!("event: {:?}", event);
                 Ok(())
@@ -60,327 +137,371 @@ impl<'a> BinanceWs<'a> {
         }
     }
 
-    pub fn connect<Callback: Clone>(&mut self, handler: Callback)
-    where
-        Callback: FnMut(WsEvent) -> Result<()> + 'a,
-    {
-        info!("connect begin");
-        let res = ws::connect(self.host.clone(), |out| BinanceWs {
-            host: self.host.clone(),
-            subs: self.subs.clone(),
-            out: Some(out),
-            handler: Box::new(handler.clone()),
-        });
-
-        info!("connect result: {:?}", res);
-        res.unwrap();
-    }
-
-    fn send(&self, msg: &str) {
-        match &self.out {
-            Some(out) => {
-                let _ = out.send(msg);
-            }
-            None => {
-                warn!("self.out is None");
-            }
-        }
+    pub fn set_reconnect_config(&mut self, config: ReconnectConfig) {
+        self.config = config;
     }
 
-    fn deseralize(&self, s: &str) -> APIResult<WsEvent> {
-        if s.find("result") != None {
-            let resp: ResponseEvent = serde_json::from_str(s)?;
-            return Ok(WsEvent::ResponseEvent(resp));
-        }
-        //let val: Value = serde_json::from_str(s)?;
-        if s.find("\"stream\"") != None {
-            if s.find("kline") != None {
-                let resp: StreamMessage<KlineEvent> = serde_json::from_str(&s)?;
-                Ok(WsEvent::KlineEvent(resp.get_symbol(), resp.data.kline.into()))
-            } else if s.find("lastUpdateId") != None {
-                let resp: StreamMessage<RawOrderbook> = serde_json::from_str(&s)?;
-                Ok(WsEvent::OrderbookEvent(resp.get_symbol(), resp.data.into()))
-            } else if s.find("aggTrade") != None {
-                let resp: StreamMessage<TradeEvent> = serde_json::from_str(&s)?;
-                Ok(WsEvent::TradeEvent(resp.data.into()))
-            } else if s.find("A") != None && s.find("B") != None {
-                let resp: StreamMessage<BookTickerEvent> = serde_json::from_str(&s)?;
-                Ok(WsEvent::TickerEvent(resp.get_symbol(),resp.data.into()))
-            }else if s.find("\"depthUpdate\"") != None{
-                let resp: StreamMessage<DepthOrderbookEvent> = serde_json::from_str(&s)?;
-                Ok(WsEvent::DepthEvent(resp.get_symbol(), resp.data.into()))
-            } else {
-                Err(Box::new(ExError::ApiError("msg channel not found".into())))
-            }
-        }else {
-            if s.find("kline") != None {
-                let resp: KlineEvent = serde_json::from_str(&s)?;
-                Ok(WsEvent::KlineEvent(resp.symbol, resp.kline.into()))
-            } else if s.find("lastUpdateId") != None {
-                // let resp: RawOrderbook = serde_json::from_str(&s)?;
-                // Ok(WsEvent::OrderbookEvent(resp.into()))
-                Err(Box::new(ExError::ApiError("Single orderbook not support".into())))
-            } else if s.find("aggTrade") != None {
-                let resp: TradeEvent = serde_json::from_str(&s)?;
-                Ok(WsEvent::TradeEvent(resp.into()))
-            } else if s.find("A") != None && s.find("B") != None {
-                let resp: BookTickerEvent = serde_json::from_str(&s)?;
-                Ok(WsEvent::TickerEvent(resp.symbol.clone(), resp.into()))
-            } else {
-                Err(Box::new(ExError::ApiError("msg channel not found".into())))
-            }
-
-
-        }
+    // tls backend, proxy and ping interval
+    pub fn set_ws_config(&mut self, config: WsConfig) {
+        self.ws_config = config;
     }
 
+    pub fn set_partial_depth(&mut self, levels: DepthLevels, speed: UpdateSpeed) {
+        self.partial_depth = (levels, speed);
+    }
 
+    // every received frame is written to `recorder` before it is parsed
+    pub fn set_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
 
-    pub fn build_local_orderbook(steams_ws_url:&str, rest_url:&str, symbols:Vec<String>){
-
-        let mut symbols_str:Vec<&str> = Vec::new();
+    // drops the in-progress kline updates, each candle is delivered once when it closes
+    pub fn set_closed_klines_only(&mut self, closed_only: bool) {
+        self.closed_klines_only = closed_only;
+    }
 
-        let mut endpoints: Vec<String> = Vec::new();
+    // the handle is Send, use it from other threads while `connect` blocks
+    pub fn handle(&self) -> BinanceWsHandle {
+        BinanceWsHandle::new(self.state.clone())
+    }
 
-        for symbol in symbols.iter() {
-            symbols_str.push(&symbol[..]);
-            endpoints.push(format!("{}@depth@100ms", symbol.to_lowercase()));
-        }
-        init_static_local_orderbook(&symbols_str);
+    // blocks until the handle is shut down, see run_supervised
+    pub fn connect<Callback>(&mut self, handler: Callback)
+    where
+        Callback: FnMut(WsEvent) -> APIResult<()> + 'a,
+    {
+        self.handler = Box::new(handler);
+        self.resubscribe = false;
+        let host = self.host.clone();
+        let (ws_config, config) = (self.ws_config.clone(), self.config.clone());
+        run_supervised(self, &host, ws_config, &config);
+    }
 
-        let handler = |event: WsEvent| {
-            match event {
-                WsEvent::DepthEvent(s,doe) => {
-                    // println!("{:?}", &doe);
-                    let arc_local_orderbook = Arc::clone(&LOCAL_ORDERBOOK);
-                    let mutex_hashmap = arc_local_orderbook.read().unwrap();
-                    let mutex_local_orderbook = mutex_hashmap.get(&s).unwrap();
-                    let mut local_orderbook = mutex_local_orderbook.write().unwrap();
-                    if local_orderbook.on_depth_event(doe){
-                        let url = String::from(rest_url);
-                        let symbol = s.clone();
-                        thread::spawn(move || {
-                            get_depth_snapshot(url, symbol);
-                        });
-                    }
-                }
-                WsEvent::TickerEvent(s,e) => {
-                    println!("{:?}: {:?}",s, e);
-                }
-                _ => {
-                    println!("{:?}", event);
-                }
-            }
-            Ok(())
-        };
-        let full_ws_url = format!("{}/stream?streams={}",steams_ws_url,endpoints.join("/"));
-        let mut ws = BinanceWs::new(&full_ws_url[..]);
-        ws.connect(handler);
+    // runs the connection on its own thread and delivers events over a channel,
+    // the channel closes once the handle is shut down
+    pub fn connect_channel(&self) -> mpsc::Receiver<WsEvent> {
+        spawn_channel(&self.state, |handler| self.spawn(handler))
     }
-}
 
-fn get_depth_snapshot(rest_url:String, symbol:String){
-    info!("get depth snapshot by rest, symbol: {}", &symbol);
-    let rest = Binance::new(None, None, rest_url);
-    let ret = rest.get_orderbook(&symbol[..], 1000);
-    match ret{
-        Ok(orderbook) =>{
-            let arc_local_orderbook = Arc::clone(&LOCAL_ORDERBOOK);
-            let mutex_hashmap = arc_local_orderbook.read().unwrap();
-            let mutex_local_orderbook = mutex_hashmap.get(&symbol).unwrap();
-            let mut local_orderbook = mutex_local_orderbook.write().unwrap();
-            if local_orderbook.rest_update_id==0{
-                local_orderbook.save_depth_snapshot(orderbook);
-            }
-        },
-        Err(error) =>{
-            println!("get orderbook error: {}", error);
-        },
+    // same as `connect_channel`, as a futures Stream for async consumers
+    pub fn connect_stream(&self) -> UnboundedReceiver<WsEvent> {
+        spawn_stream(&self.state, |handler| self.spawn(handler))
     }
-}
 
-// fn timestamp() -> i64 {
-//     let start = SystemTime::now();
-//     let since_the_epoch = start
-//         .duration_since(UNIX_EPOCH)
-//         .expect("Time went backwards");
-//     let ms = since_the_epoch.as_secs() as i64 * 1000i64 + (since_the_epoch.subsec_nanos() as f64 / 1_000_000.0) as i64;
-//     ms
-// }
-
-#[derive(Debug,  Clone)]
-pub struct LocalOrderBook{
-    symbol:String,
-    asks:BTreeMap<OrderedFloat<f64>,f64>,
-    bids:BTreeMap<OrderedFloat<f64>,f64>,
-    depth_cache:BTreeMap<u64,DepthOrderbookEvent>,
-    rest_update_id:u64,
-    ws_final_update_id:u64,
-    ready: bool,
-}
+    pub(crate) fn spawn<Callback>(&self, handler: Callback) -> Option<thread::JoinHandle<()>>
+    where
+        Callback: FnMut(WsEvent) -> APIResult<()> + Send + 'static,
+    {
+        let host = self.host.clone();
+        let state = self.state.clone();
+        let config = self.config.clone();
+        let ws_config = self.ws_config.clone();
+        let partial_depth = self.partial_depth;
+        let recorder = self.recorder.clone();
+        let closed_klines_only = self.closed_klines_only;
+        thread::Builder::new()
+            .name("binance-ws".into())
+            .spawn(move || {
+                let mut ws = BinanceWs::new(&host);
+                ws.state = state;
+                ws.config = config;
+                ws.ws_config = ws_config;
+                ws.partial_depth = partial_depth;
+                ws.recorder = recorder;
+                ws.closed_klines_only = closed_klines_only;
+                ws.connect(handler);
+            })
+            .ok()
+    }
 
-impl LocalOrderBook{
-    fn new(symbol:String) -> LocalOrderBook{
-        LocalOrderBook{
-            symbol,
-            asks:BTreeMap::new(),
-            bids:BTreeMap::new(),
-            depth_cache:BTreeMap::new(),
-            rest_update_id:0,
-            ws_final_update_id:0,
-            ready:false,
+    // combined streams are recorded by stream name, raw ones by the stream in the url
+    fn record_stream(&self, frame: &str) -> String {
+        let header: Option<MessageHeader> = serde_json::from_str(frame).ok();
+        match header {
+            Some(MessageHeader { stream: Some(stream), .. }) => stream,
+            Some(MessageHeader { id: Some(_), .. }) => "response".into(),
+            header => match self.host.split("/ws/").nth(1) {
+                Some(stream) => stream.to_string(),
+                None => header.and_then(|header| header.event_type).unwrap_or_else(|| "unknown".into()),
+            },
         }
     }
 
-    pub fn print_order_book(symbol:&str){
-        let arc_local_orderbook = Arc::clone(&LOCAL_ORDERBOOK);
-        let mutex_hashmap = arc_local_orderbook.read().unwrap();
-        let mutex_local_orderbook = mutex_hashmap.get(symbol).unwrap();
-        let lob = mutex_local_orderbook.read().unwrap();
-        println!("Local Orderbook:\nasks:\n{:?}\nbids:\n{:?}", &lob.asks, &lob.bids);
+    // raw partial depth payloads carry no symbol, it is only known while one such stream is open
+    fn partial_depth_symbol(&self) -> Option<String> {
+        let state = self.state.lock().unwrap();
+        let mut symbols = self
+            .host
+            .split("/ws/")
+            .nth(1)
+            .into_iter()
+            .chain(state.subs.iter().map(|sub| sub.as_str()))
+            .filter(|stream| stream_channel(stream) == Some(Channel::PartialDepth))
+            .filter_map(|stream| stream.split('@').next())
+            .collect::<Vec<&str>>();
+        symbols.sort_unstable();
+        symbols.dedup();
+        match symbols[..] {
+            [symbol] => Some(symbol.to_lowercase()),
+            _ => None,
+        }
     }
 
-    pub fn query_buy_price(symbol: &str, amount: f64) -> (f64,bool) {
-        let arc_local_orderbook = Arc::clone(&LOCAL_ORDERBOOK);
-        let mutex_hashmap = arc_local_orderbook.read().unwrap();
-        let mutex_local_orderbook = mutex_hashmap.get(&symbol.to_lowercase()).unwrap();
-        let lob = mutex_local_orderbook.read().unwrap();
-        if !lob.ready{
-            return (0.0, false);
-        }
-        let mut map = BTreeMap::new();
-        let mut accumulative_amount = 0.0;
-        for en in &(lob.asks){
-            if amount - accumulative_amount <= *en.1 {
-                map.insert(en.0, amount-accumulative_amount);
-                accumulative_amount = amount;
-                break;
-            }else{
-                map.insert(en.0, *en.1);
-                accumulative_amount += *en.1;
-            }
-        }
-        if accumulative_amount == amount{
-            let mut total_money = 0.0;
-            for en in map{
-                total_money += en.0.into_inner()*en.1;
-            }
-            return (total_money/amount, true);
-        }
-        warn!("{} total asks amount {} is not enough, return price 999999999999.0", symbol, amount);
-        (999999999999.0, true)
+    // feeds recorded frames through the same parsing and handler as a live connection,
+    // handles have no connection to send on while it runs
+    pub fn replay<Callback>(&mut self, frames: &[RecordedFrame], speed: ReplaySpeed, handler: Callback)
+    where
+        Callback: FnMut(WsEvent) -> APIResult<()> + 'a,
+    {
+        self.handler = Box::new(handler);
+        replay(frames, speed, |frame| self.handle_frame(&frame.frame));
     }
 
-    pub fn query_sell_price(symbol: &str, amount: f64) -> (f64,bool) {
-        let arc_local_orderbook = Arc::clone(&LOCAL_ORDERBOOK);
-        let mutex_hashmap = arc_local_orderbook.read().unwrap();
-        let mutex_local_orderbook = mutex_hashmap.get(&symbol.to_lowercase()).unwrap();
-        let lob = mutex_local_orderbook.read().unwrap();
-        if !lob.ready{
-            return (0.0, false);
-        }
-        let mut map = BTreeMap::new();
-        let mut accumulative_amount = 0.0;
-        let mut vec = Vec::from_iter(&(lob.bids));
-        vec.reverse();
-        for en in vec{
-            if amount - accumulative_amount <= *en.1 {
-                map.insert(en.0, amount-accumulative_amount);
-                accumulative_amount = amount;
-                break;
-            }else{
-                map.insert(en.0, *en.1);
-                accumulative_amount += *en.1;
+    fn handle_frame(&mut self, frame: &str) {
+        match self.deseralize(frame) {
+            Ok(event) => {
+                if let WsEvent::ResponseEvent(resp) = &event {
+                    let ret = match &resp.error {
+                        Some(err) => Err(ExError::ApiError(format!("{}: {}", err.code, err.msg))),
+                        None => Ok(resp.result.clone().unwrap_or(Value::Null)),
+                    };
+                    self.state.lock().unwrap().resolve(&resp.id.to_string(), ret);
+                }
+                if let WsEvent::KlineEvent(_, kline) = &event {
+                    if self.closed_klines_only && !kline.closed {
+                        return;
+                    }
+                }
+                let _ = (self.handler)(event);
             }
-        }
-        if accumulative_amount == amount{
-            let mut total_money = 0.0;
-            for en in map{
-                total_money += en.0.into_inner()*en.1;
+            Err(err) => {
+                warn!("deseralize msg error: {:?}", err);
             }
-            return (total_money/amount, true);
         }
-        warn!("{} total bids amount {} is not enough, return price 0.0", symbol, amount);
-        (0.0, true)
     }
 
-    fn save_depth_snapshot(&mut self, orderbook:Orderbook){
-        for entry in &(orderbook.asks){
-            self.asks.insert(OrderedFloat(entry.price), entry.amount);
+    pub(crate) fn deseralize(&self, s: &str) -> APIResult<WsEvent> {
+        // !miniTicker@arr and !ticker@arr on a raw stream
+        if s.starts_with('[') {
+            return deseralize_array(s);
         }
-        for entry in &(orderbook.bids){
-            self.bids.insert(OrderedFloat(entry.price), entry.amount);
+        let header: MessageHeader = serde_json::from_str(s)?;
+        if let Some(stream) = header.stream {
+            return match stream_channel(&stream) {
+                Some(Channel::Kline) => {
+                    let resp: StreamMessage<KlineEvent> = serde_json::from_str(s)?;
+                    Ok(WsEvent::KlineEvent(resp.get_symbol(), resp.data.kline.into()))
+                }
+                Some(Channel::PartialDepth) => {
+                    let resp: StreamMessage<RawOrderbook> = serde_json::from_str(s)?;
+                    Ok(WsEvent::OrderbookEvent(resp.get_symbol(), resp.data.into()))
+                }
+                Some(Channel::DepthUpdate) => {
+                    let resp: StreamMessage<DepthOrderbookEvent> = serde_json::from_str(s)?;
+                    Ok(WsEvent::DepthEvent(resp.get_symbol(), resp.data))
+                }
+                Some(Channel::AggTrade) => {
+                    let resp: StreamMessage<TradeEvent> = serde_json::from_str(s)?;
+                    Ok(WsEvent::TradeEvent(resp.data.into()))
+                }
+                Some(Channel::BookTicker) => {
+                    let resp: StreamMessage<BookTickerEvent> = serde_json::from_str(s)?;
+                    Ok(WsEvent::TickerEvent(resp.get_symbol(), resp.data.into()))
+                }
+                Some(Channel::AllBookTickers) => {
+                    let resp: StreamMessage<BookTickerEvent> = serde_json::from_str(s)?;
+                    Ok(WsEvent::TickerEvent(resp.data.symbol.clone(), resp.data.into()))
+                }
+                Some(Channel::Trade) => {
+                    let resp: StreamMessage<RawTradeEvent> = serde_json::from_str(s)?;
+                    Ok(WsEvent::RawTradeEvent(resp.data.into()))
+                }
+                Some(Channel::MiniTicker) => {
+                    let resp: StreamMessage<MiniTickerEvent> = serde_json::from_str(s)?;
+                    Ok(WsEvent::MiniTickerEvent(resp.get_symbol(), resp.data.into()))
+                }
+                Some(Channel::DayTicker) => {
+                    let resp: StreamMessage<DayTickerEvent> = serde_json::from_str(s)?;
+                    Ok(WsEvent::DayTickerEvent(resp.get_symbol(), resp.data.into()))
+                }
+                Some(Channel::AllMiniTickers) => {
+                    let resp: StreamMessage<Vec<MiniTickerEvent>> = serde_json::from_str(s)?;
+                    let stats = resp.data.into_iter().map(|ticker| ticker.into()).collect();
+                    Ok(WsEvent::AllMiniTickersEvent(stats))
+                }
+                Some(Channel::AllDayTickers) => {
+                    let resp: StreamMessage<Vec<DayTickerEvent>> = serde_json::from_str(s)?;
+                    let stats = resp.data.into_iter().map(|ticker| ticker.into()).collect();
+                    Ok(WsEvent::AllDayTickersEvent(stats))
+                }
+                Some(Channel::AvgPrice) => {
+                    let resp: StreamMessage<AvgPriceEvent> = serde_json::from_str(s)?;
+                    Ok(WsEvent::AvgPriceEvent(resp.get_symbol(), resp.data.price))
+                }
+                None => {
+                    let resp: StreamMessage<Value> = serde_json::from_str(s)?;
+                    Ok(WsEvent::RawEvent(stream, resp.data))
+                }
+            };
         }
-        self.rest_update_id = orderbook.timestamp;
-    }
-
-    fn update(&mut self, final_update_id:u64, depth_event: DepthOrderbookEvent) -> bool {
-        if final_update_id<=self.rest_update_id {
-            return true;
-        // }else if depth_event.first_update_id<=self.rest_update_id+1&&depth_event.final_update_id>=self.rest_update_id+1{
-        }else{
-            if self.ws_final_update_id!=0&&self.ws_final_update_id+1!=depth_event.first_update_id{
-                info!("package lost, rebuild symbol {} local orderbook", &(self.symbol));
-                self.ready = false;
-                self.rest_update_id =0;
-                self.ws_final_update_id =0;
-                self.depth_cache.clear();
-                self.depth_cache.insert(final_update_id, depth_event);
-                return false;
+
+        // raw streams carry no stream name, fall back to the event type or the payload shape
+        let channel = match &header.event_type {
+            Some(event_type) => EVENT_CHANNELS.get(event_type.as_str()).copied(),
+            None if header.id.is_some() => {
+                let resp: ResponseEvent = serde_json::from_str(s)?;
+                return Ok(WsEvent::ResponseEvent(resp));
             }
-            for entry in &(depth_event.asks) {
-                if entry.qty==0.0{
-                    self.asks.remove(&OrderedFloat(entry.price));
-                }else {
-                    self.asks.insert(OrderedFloat(entry.price), entry.qty);
-                }
+            None if header.last_update_id.is_some() => Some(Channel::PartialDepth),
+            None if header.update_id.is_some() => Some(Channel::BookTicker),
+            None => None,
+        };
+        match channel {
+            Some(Channel::Kline) => {
+                let resp: KlineEvent = serde_json::from_str(s)?;
+                Ok(WsEvent::KlineEvent(resp.symbol, resp.kline.into()))
             }
-            for entry in &(depth_event.bids){
-                if entry.qty==0.0{
-                    self.bids.remove(&OrderedFloat(entry.price));
-                }else {
-                    self.bids.insert(OrderedFloat(entry.price), entry.qty);
-                }
+            Some(Channel::PartialDepth) => {
+                let symbol = self.partial_depth_symbol().ok_or_else(|| {
+                    ExError::ApiError("partial depth of several symbols on a raw stream, use /stream".into())
+                })?;
+                let resp: RawOrderbook = serde_json::from_str(s)?;
+                Ok(WsEvent::OrderbookEvent(symbol, resp.into()))
             }
-            // info!("first_update_id: {}, final_update_id: {}", depth_event.first_update_id,depth_event.final_update_id);
-            self.ws_final_update_id = final_update_id;
-        // }else{
-        //     warn!("symbol {} unknown branch, ready: {}, first_update_id: {}, final_update_id: {}", &(self.symbol), self.ready, depth_event.first_update_id,depth_event.final_update_id);
-        }
-        true
-    }
-
-    fn on_depth_event(&mut self, depth_event:DepthOrderbookEvent) -> bool{
-        if self.rest_update_id==0{
-            self.depth_cache.insert(depth_event.final_update_id, depth_event);
-            return self.depth_cache.len()>30&&self.depth_cache.len()%30==0;
-        }else if self.depth_cache.len()>0{
-            info!("rest depth snapshot is done, update symbol {} local orderbook", &(self.symbol));
-            let depth_cache = self.depth_cache.clone();
-            for en in depth_cache{
-                if !self.update(en.0,en.1.clone() ){
-                    return false;
-                }
+            Some(Channel::DepthUpdate) => {
+                let resp: DepthOrderbookEvent = serde_json::from_str(s)?;
+                Ok(WsEvent::DepthEvent(resp.symbol.clone(), resp))
             }
-            self.depth_cache.clear();
-            self.ready=true;
-            info!("build local orderbook finished, symbol: {}", &(self.symbol));
+            Some(Channel::AggTrade) => {
+                let resp: TradeEvent = serde_json::from_str(s)?;
+                Ok(WsEvent::TradeEvent(resp.into()))
+            }
+            Some(Channel::BookTicker) | Some(Channel::AllBookTickers) => {
+                let resp: BookTickerEvent = serde_json::from_str(s)?;
+                Ok(WsEvent::TickerEvent(resp.symbol.clone(), resp.into()))
+            }
+            Some(Channel::Trade) => {
+                let resp: RawTradeEvent = serde_json::from_str(s)?;
+                Ok(WsEvent::RawTradeEvent(resp.into()))
+            }
+            Some(Channel::MiniTicker) | Some(Channel::AllMiniTickers) => {
+                let resp: MiniTickerEvent = serde_json::from_str(s)?;
+                Ok(WsEvent::MiniTickerEvent(resp.symbol.clone(), resp.into()))
+            }
+            Some(Channel::DayTicker) | Some(Channel::AllDayTickers) => {
+                let resp: DayTickerEvent = serde_json::from_str(s)?;
+                Ok(WsEvent::DayTickerEvent(resp.symbol.clone(), resp.into()))
+            }
+            Some(Channel::AvgPrice) => {
+                let resp: AvgPriceEvent = serde_json::from_str(s)?;
+                Ok(WsEvent::AvgPriceEvent(resp.symbol, resp.price))
+            }
+            None => {
+                let val: Value = serde_json::from_str(s)?;
+                Ok(WsEvent::RawEvent(header.event_type.unwrap_or_default(), val))
+            }
+        }
+    }
+}
 
+fn deseralize_array(s: &str) -> APIResult<WsEvent> {
+    let events: Vec<Value> = serde_json::from_str(s)?;
+    let event_type = events
+        .first()
+        .and_then(|event| event["e"].as_str())
+        .unwrap_or_default()
+        .to_string();
+    match event_type.as_str() {
+        "24hrMiniTicker" => {
+            let tickers: Vec<MiniTickerEvent> = serde_json::from_value(Value::Array(events))?;
+            Ok(WsEvent::AllMiniTickersEvent(tickers.into_iter().map(|ticker| ticker.into()).collect()))
         }
-        false
+        "24hrTicker" => {
+            let tickers: Vec<DayTickerEvent> = serde_json::from_value(Value::Array(events))?;
+            Ok(WsEvent::AllDayTickersEvent(tickers.into_iter().map(|ticker| ticker.into()).collect()))
+        }
+        _ => Ok(WsEvent::RawEvent(event_type, Value::Array(events))),
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Channel {
+    Kline,
+    PartialDepth,
+    DepthUpdate,
+    AggTrade,
+    BookTicker,
+    AllBookTickers,
+    Trade,
+    MiniTicker,
+    DayTicker,
+    AllMiniTickers,
+    AllDayTickers,
+    AvgPrice,
+}
+
 lazy_static! {
-    static ref LOCAL_ORDERBOOK:Arc<RwLock<HashMap<String, RwLock<LocalOrderBook>>>> = Arc::new(RwLock::new(HashMap::new()));
+    // keyed by the stream name after the symbol, with parameters stripped
+    static ref STREAM_CHANNELS: HashMap<&'static str, Channel> = {
+        let mut map = HashMap::new();
+        map.insert("kline", Channel::Kline);
+        map.insert("depth5", Channel::PartialDepth);
+        map.insert("depth10", Channel::PartialDepth);
+        map.insert("depth20", Channel::PartialDepth);
+        map.insert("depth", Channel::DepthUpdate);
+        map.insert("aggTrade", Channel::AggTrade);
+        map.insert("bookTicker", Channel::BookTicker);
+        map.insert("trade", Channel::Trade);
+        map.insert("miniTicker", Channel::MiniTicker);
+        map.insert("ticker", Channel::DayTicker);
+        map.insert("avgPrice", Channel::AvgPrice);
+        map
+    };
+    // all market streams, keyed by the whole stream name
+    static ref MARKET_CHANNELS: HashMap<&'static str, Channel> = {
+        let mut map = HashMap::new();
+        map.insert("!bookTicker", Channel::AllBookTickers);
+        map.insert("!miniTicker@arr", Channel::AllMiniTickers);
+        map.insert("!ticker@arr", Channel::AllDayTickers);
+        map
+    };
+    // keyed by the "e" field of raw stream payloads
+    pub(crate) static ref EVENT_CHANNELS: HashMap<&'static str, Channel> = {
+        let mut map = HashMap::new();
+        map.insert("kline", Channel::Kline);
+        map.insert("depthUpdate", Channel::DepthUpdate);
+        map.insert("aggTrade", Channel::AggTrade);
+        map.insert("trade", Channel::Trade);
+        map.insert("24hrMiniTicker", Channel::MiniTicker);
+        map.insert("24hrTicker", Channel::DayTicker);
+        map.insert("avgPrice", Channel::AvgPrice);
+        // futures only, the spot payload has no event type
+        map.insert("bookTicker", Channel::BookTicker);
+        map
+    };
 }
 
-pub fn init_static_local_orderbook(symbols:&Vec<&str>){
-    let lo = Arc::clone(&LOCAL_ORDERBOOK);
-    let mut map = lo.write().unwrap();
-    for symbol in symbols{
-        map.insert(symbol.to_lowercase(), RwLock::new(LocalOrderBook::new(String::from(symbol.to_lowercase()))));
+// btcusdt@kline_1m -> kline, btcusdt@depth20@100ms -> depth20
+pub(crate) fn stream_channel(stream: &str) -> Option<Channel> {
+    if stream.starts_with('!') {
+        return MARKET_CHANNELS.get(stream).copied();
     }
+    let name = stream.split('@').nth(1)?;
+    let name = name.split('_').next()?;
+    STREAM_CHANNELS.get(name).copied()
+}
+
+// just enough of a message to route it
+#[derive(Debug, Deserialize)]
+pub(crate) struct MessageHeader {
+    pub stream: Option<String>,
+    #[serde(rename = "e")]
+    pub event_type: Option<String>,
+    pub id: Option<u64>,
+    #[serde(rename = "lastUpdateId")]
+    pub last_update_id: Option<u64>,
+    #[serde(rename = "u")]
+    pub update_id: Option<u64>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -397,82 +518,201 @@ impl<E> StreamMessage<E> {
 }
 
 
+#[derive(Clone)]
+pub struct BinanceWsHandle {
+    state: Arc<Mutex<SubState>>,
+}
+
+impl BinanceWsHandle {
+    pub(crate) fn new(state: Arc<Mutex<SubState>>) -> Self {
+        BinanceWsHandle { state }
+    }
+
+    pub fn subscribe(&self, streams: &[&str]) -> Ack<()> {
+        let streams = to_streams(streams);
+        let mut state = self.state.lock().unwrap();
+        state.add_subs(&streams);
+        Self::request(&mut state, "SUBSCRIBE", streams, true, |_| Ok(()))
+    }
+
+    pub fn unsubscribe(&self, streams: &[&str]) -> Ack<()> {
+        let streams = to_streams(streams);
+        let mut state = self.state.lock().unwrap();
+        state.remove_subs(&streams);
+        Self::request(&mut state, "UNSUBSCRIBE", streams, true, |_| Ok(()))
+    }
+
+    // streams the server has registered for this connection
+    pub fn list_subscriptions(&self) -> Ack<Vec<String>> {
+        let mut state = self.state.lock().unwrap();
+        Self::request(&mut state, "LIST_SUBSCRIPTIONS", vec![], false, |val| {
+            serde_json::from_value::<Vec<String>>(val)
+                .map_err(|err| ExError::ApiError(err.to_string()))
+        })
+    }
+
+    // streams that will be replayed on reconnect
+    pub fn subscriptions(&self) -> Vec<String> {
+        self.state.lock().unwrap().subs.clone()
+    }
+
+    // closes the connection and stops reconnecting, in-flight requests fail. waits for the
+    // threads of connect_channel and connect_stream to exit
+    pub fn shutdown(&self) {
+        shutdown_and_join(&self.state);
+    }
+
+    fn request<T, F>(
+        state: &mut SubState,
+        method: &str,
+        params: Vec<String>,
+        deferrable: bool,
+        convert: F,
+    ) -> Ack<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(Value) -> Result<T, ExError> + Send + 'static,
+    {
+        let id = state.next_id();
+        let msg = if params.is_empty() {
+            json!({"method": method, "id": id}).to_string()
+        } else {
+            json!({"method": method, "params": params, "id": id}).to_string()
+        };
+        let ack = state.expect(id.to_string(), convert);
+        if deferrable {
+            state.send_or_defer(id.to_string(), params, msg);
+        } else {
+            state.send_or_queue(msg);
+        }
+        ack
+    }
+}
+
+fn to_streams(streams: &[&str]) -> Vec<String> {
+    streams.iter().map(|stream| stream.to_string()).collect()
+}
+
 impl<'a> SpotWs for BinanceWs<'a> {
-    fn sub_kline(&mut self, symbol: &str, period: &str) {
-        let msg = format!(
-            "{{\"method\": \"SUBSCRIBE\", \"params\": [\"{}@kline_{}\"], \"id\": {}}}",
-            symbol.to_string().to_lowercase(),
-            period,
-            self.subs.len() + 1,
-        );
-        self.send(msg.as_str());
-        self.subs.push(msg);
-    }
-
-    fn sub_orderbook(&mut self, symbol: &str) {
-        let msg = format!(
-            "{{\"method\": \"SUBSCRIBE\", \"params\": [\"{}@depth20\"], \"id\": {}}}",
-            symbol.to_string().to_lowercase(),
-            self.subs.len() + 1,
-        );
-        self.send(msg.as_str());
-        self.subs.push(msg);
-    }
-
-    fn sub_trade(&mut self, symbol: &str) {
-        let msg = format!(
-            "{{\"method\": \"SUBSCRIBE\", \"params\": [\"{}@aggTrade\"], \"id\": {}}}",
-            symbol.to_string().to_lowercase(),
-            self.subs.len() + 1,
-        );
-        self.send(msg.as_str());
-        self.subs.push(msg);
-    }
-
-    fn sub_ticker(&mut self, symbol: &str) {
-        let msg = format!(
-            "{{\"method\": \"SUBSCRIBE\", \"params\": [\"{}@bookTicker\"], \"id\": {}}}",
-            symbol.to_string().to_lowercase(),
-            self.subs.len() + 1,
-        );
-        info!("{:?}", msg);
-        self.send(msg.as_str());
-        self.subs.push(msg);
-    }
-
-    fn sub_order_update(&mut self, _symbol: &str) {
-        unimplemented!()
+    fn sub_kline(&mut self, symbol: &str, period: &str) -> Ack<()> {
+        let stream = format!("{}@kline_{}", symbol.to_lowercase(), period);
+        self.handle().subscribe(&[&stream])
+    }
+
+    fn sub_orderbook(&mut self, symbol: &str) -> Ack<()> {
+        let (levels, speed) = self.partial_depth;
+        let stream = partial_depth_stream(symbol, levels, speed);
+        self.handle().subscribe(&[&stream])
+    }
+
+    fn sub_trade(&mut self, symbol: &str) -> Ack<()> {
+        let stream = format!("{}@aggTrade", symbol.to_lowercase());
+        self.handle().subscribe(&[&stream])
+    }
+
+    fn sub_ticker(&mut self, symbol: &str) -> Ack<()> {
+        let stream = format!("{}@bookTicker", symbol.to_lowercase());
+        self.handle().subscribe(&[&stream])
+    }
+
+    fn sub_order_update(&mut self, _symbol: &str) -> Ack<()> {
+        // the user data stream is a separate connection on a listen key
+        Ack::failed(ExError::ApiError("order updates need a listen key".into()))
+    }
+}
+
+impl<'a> BinanceWs<'a> {
+    // every trade with its own id, sub_trade gets the aggregated ones
+    pub fn sub_raw_trade(&mut self, symbol: &str) -> Ack<()> {
+        let stream = format!("{}@trade", symbol.to_lowercase());
+        self.handle().subscribe(&[&stream])
+    }
+
+    pub fn sub_mini_ticker(&mut self, symbol: &str) -> Ack<()> {
+        let stream = format!("{}@miniTicker", symbol.to_lowercase());
+        self.handle().subscribe(&[&stream])
+    }
+
+    pub fn sub_day_ticker(&mut self, symbol: &str) -> Ack<()> {
+        let stream = format!("{}@ticker", symbol.to_lowercase());
+        self.handle().subscribe(&[&stream])
+    }
+
+    pub fn sub_avg_price(&mut self, symbol: &str) -> Ack<()> {
+        let stream = format!("{}@avgPrice", symbol.to_lowercase());
+        self.handle().subscribe(&[&stream])
+    }
+
+    pub fn sub_all_mini_tickers(&mut self) -> Ack<()> {
+        self.handle().subscribe(&["!miniTicker@arr"])
+    }
+
+    pub fn sub_all_day_tickers(&mut self) -> Ack<()> {
+        self.handle().subscribe(&["!ticker@arr"])
+    }
+
+    pub fn sub_all_book_tickers(&mut self) -> Ack<()> {
+        self.handle().subscribe(&["!bookTicker"])
+    }
+}
+
+impl<'a> Supervised for BinanceWs<'a> {
+    fn state(&self) -> Arc<Mutex<SubState>> {
+        self.state.clone()
+    }
+
+    fn on_disconnect(&mut self, reason: String) {
+        self.out = None;
+        let _ = (self.handler)(WsEvent::Disconnected(reason));
+        self.resubscribe = true;
     }
 }
 
 impl<'a> Handler for BinanceWs<'a> {
-    fn on_open(&mut self, _shake: Handshake) -> Result<()> {
-        match &self.out {
-            // /*
-            Some(out) => self.subs.iter().for_each(|s| {
-                let _ = out.send(s.as_str());
-            }),
-            // // */
-            // Some(_) => {
-            //     info!("ws connected");
-            // }
-            None => {
-                warn!("self.out is None");
+    fn on_open(&mut self, out: Outbox) -> APIResult<()> {
+        let _ = (self.handler)(WsEvent::Connected);
+        let resubscribed = {
+            let mut state = self.state.lock().unwrap();
+            if state.shutdown {
+                out.close();
+                return Ok(());
+            }
+            state.out = Some(out.clone());
+            let deferred = state.take_deferred();
+            if state.subs.is_empty() {
+                deferred
+                    .iter()
+                    .for_each(|(id, _)| { state.resolve(id, Ok(Value::Null)); });
+            } else {
+                // one request for all streams, binance limits incoming messages to 5 per second
+                let id = state.next_id();
+                let msg = json!({"method": "SUBSCRIBE", "params": state.subs, "id": id});
+                deferred
+                    .iter()
+                    .for_each(|(from, _)| state.merge_pending(from, &id.to_string()));
+                out.send(msg.to_string());
+            }
+            for msg in state.take_queued() {
+                out.send(msg);
             }
+            state.subs.len()
+        };
+        self.out = Some(out);
+        if self.resubscribe {
+            let _ = (self.handler)(WsEvent::Resubscribed(resubscribed));
         }
         Ok(())
     }
 
-    fn on_message(&mut self, msg: Message) -> Result<()> {
+    fn on_message(&mut self, msg: Message) -> APIResult<()> {
         // info!("{:?}", msg);
-        match self.deseralize(&msg.to_string()) {
-            Ok(event) => {
-                let _ = (self.handler)(event);
-            }
-            Err(err) => {
-                warn!("deseralize msg error: {:?}", err);
+        let msg = msg.to_string();
+        if let Some(recorder) = &self.recorder {
+            if let Err(err) = recorder.record("binance", &self.record_stream(&msg), &msg) {
+                warn!("record frame error: {:?}", err);
             }
         }
+        self.handle_frame(&msg);
         Ok(())
     }
 }
@@ -482,6 +722,178 @@ mod test {
     use super::*;
 
     static WEBSOCKET_URL: &str = "wss://stream.binance.com:9443/ws/btcusdt@depth20";
+
+    #[test]
+    fn test_deseralize_routing() {
+        let binance = BinanceWs::new(WEBSOCKET_URL);
+        let depth = r#"{"stream":"btcusdt@depth@100ms","data":{"e":"depthUpdate","E":1,"s":"BTCUSDT","U":10,"u":12,"b":[["0.1","2"]],"a":[]}}"#;
+        match binance.deseralize(depth).unwrap() {
+            WsEvent::DepthEvent(symbol, event) => {
+                assert_eq!(symbol, "btcusdt");
+                assert_eq!(event.final_update_id, 12);
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        let ticker = r#"{"u":400900217,"s":"BNBUSDT","b":"25.35","B":"31.21","a":"25.36","A":"40.66"}"#;
+        match binance.deseralize(ticker).unwrap() {
+            WsEvent::TickerEvent(symbol, ticker) => {
+                assert_eq!(symbol, "BNBUSDT");
+                assert_eq!(ticker.ask.price, 25.36);
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        let unknown = r#"{"stream":"btcusdt@markPrice","data":{"e":"markPriceUpdate","A":"result"}}"#;
+        match binance.deseralize(unknown).unwrap() {
+            WsEvent::RawEvent(stream, data) => {
+                assert_eq!(stream, "btcusdt@markPrice");
+                assert_eq!(data["e"], "markPriceUpdate");
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_market_streams() {
+        let binance = BinanceWs::new("wss://stream.binance.com:9443/stream");
+        let trade = r#"{"stream":"bnbbtc@trade","data":{"e":"trade","E":123456789,"s":"BNBBTC","t":12345,"p":"0.001","q":"100","b":88,"a":50,"T":123456785,"m":true,"M":true}}"#;
+        match binance.deseralize(trade).unwrap() {
+            WsEvent::RawTradeEvent(trade) => {
+                assert_eq!((trade.id, trade.symbol.as_str(), trade.side.as_str()), (12345, "BNBBTC", "sell"));
+                assert_eq!(trade.timestamp, 123456785);
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        let avg = r#"{"stream":"bnbbtc@avgPrice","data":{"e":"avgPrice","E":1693907033000,"s":"BNBBTC","i":"5m","w":"0.25141517","T":1693907032213}}"#;
+        assert!(matches!(binance.deseralize(avg).unwrap(), WsEvent::AvgPriceEvent(symbol, price) if symbol == "bnbbtc" && price == 0.25141517));
+
+        let mini = r#"{"e":"24hrMiniTicker","E":1,"s":"BNBBTC","c":"0.0025","o":"0.0010","h":"0.0025","l":"0.0010","v":"10000","q":"18"}"#;
+        let all = format!(r#"{{"stream":"!miniTicker@arr","data":[{}]}}"#, mini);
+        match binance.deseralize(&all).unwrap() {
+            WsEvent::AllMiniTickersEvent(stats) => {
+                assert_eq!(stats[0].symbol, "BNBBTC");
+                assert_eq!((stats[0].close, stats[0].quote_volume), (0.0025, 18.0));
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+        assert!(matches!(binance.deseralize(mini).unwrap(), WsEvent::MiniTickerEvent(symbol, _) if symbol == "BNBBTC"));
+
+        let ticker = r#"{"e":"24hrTicker","E":1,"s":"BNBBTC","p":"0.0015","P":"250.00","w":"0.0018","x":"0.0009","c":"0.0025","Q":"10","b":"0.0024","B":"10","a":"0.0026","A":"100","o":"0.0010","h":"0.0025","l":"0.0010","v":"10000","q":"18","O":0,"C":86400000,"F":0,"L":18150,"n":18151}"#;
+        match binance.deseralize(&format!("[{}]", ticker)).unwrap() {
+            WsEvent::AllDayTickersEvent(stats) => {
+                assert_eq!((stats[0].bid, stats[0].ask, stats[0].trades), (0.0024, 0.0026, 18151));
+                assert_eq!(stats[0].change_percent(), 150.0);
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+        let book = r#"{"stream":"!bookTicker","data":{"u":400900217,"s":"BNBUSDT","b":"25.35","B":"31.21","a":"25.36","A":"40.66"}}"#;
+        assert!(matches!(binance.deseralize(book).unwrap(), WsEvent::TickerEvent(symbol, _) if symbol == "BNBUSDT"));
+    }
+
+    #[test]
+    fn test_partial_depth_symbol() {
+        let depth = r#"{"lastUpdateId":160,"bids":[["0.0024","10"]],"asks":[["0.0026","100"]]}"#;
+        let binance = BinanceWs::new(WEBSOCKET_URL);
+        match binance.deseralize(depth).unwrap() {
+            WsEvent::OrderbookEvent(symbol, orderbook) => {
+                assert_eq!(symbol, "btcusdt");
+                assert_eq!(orderbook.timestamp, 160);
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        let mut binance = BinanceWs::new("wss://stream.binance.com:9443/ws");
+        assert!(binance.deseralize(depth).is_err());
+        binance.set_partial_depth(DepthLevels::Five, UpdateSpeed::Ms100);
+        binance.sub_orderbook("ETHUSDT");
+        assert_eq!(binance.handle().subscriptions(), vec!["ethusdt@depth5@100ms"]);
+        assert!(matches!(binance.deseralize(depth).unwrap(), WsEvent::OrderbookEvent(symbol, _) if symbol == "ethusdt"));
+        binance.sub_orderbook("BNBUSDT");
+        assert!(binance.deseralize(depth).is_err());
+    }
+
+    #[test]
+    fn test_channel_shutdown() {
+        let binance = BinanceWs::new(WEBSOCKET_URL);
+        let handle = binance.handle();
+        let ack = handle.list_subscriptions();
+        handle.shutdown();
+        assert!(ack.wait().is_err());
+
+        let events = binance.connect_channel();
+        let ret = events.recv_timeout(std::time::Duration::from_secs(5));
+        assert!(matches!(ret, Err(mpsc::RecvTimeoutError::Disconnected)));
+    }
+
+    #[test]
+    fn test_shutdown_joins_thread() {
+        // nothing listens there, the thread keeps retrying until shut down
+        let binance = BinanceWs::new("ws://127.0.0.1:1/ws");
+        let events = binance.connect_channel();
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        binance.handle().shutdown();
+        assert!(matches!(events.try_recv(), Err(mpsc::TryRecvError::Disconnected)));
+    }
+
+    #[test]
+    fn test_subscription_response() {
+        let mut binance = BinanceWs::new(WEBSOCKET_URL);
+        let handle = binance.handle();
+        binance.sub_ticker("BTCUSDT");
+        let ack = handle.subscribe(&["btcusdt@bookTicker", "ethusdt@aggTrade"]);
+        assert_eq!(handle.subscriptions(), vec!["btcusdt@bookTicker", "ethusdt@aggTrade"]);
+        assert!(ack.try_get().is_none());
+
+        let event = binance
+            .deseralize(r#"{"result":["btcusdt@bookTicker"],"id":3}"#)
+            .unwrap();
+        match event {
+            WsEvent::ResponseEvent(resp) => {
+                assert_eq!(resp.id, 3);
+                assert_eq!(resp.result, Some(json!(["btcusdt@bookTicker"])));
+            }
+            _ => panic!("expect response event"),
+        }
+        let event = binance
+            .deseralize(r#"{"error":{"code":2,"msg":"Invalid request"},"id":4}"#)
+            .unwrap();
+        match event {
+            WsEvent::ResponseEvent(resp) => assert_eq!(resp.error.unwrap().code, 2),
+            _ => panic!("expect response event"),
+        }
+    }
+    #[test]
+    fn test_closed_klines_only() {
+        let kline = |closed: bool, close: &str| RecordedFrame {
+            received: 0,
+            stream: "btcusdt@kline_1m".into(),
+            frame: json!({"stream": "btcusdt@kline_1m", "data": {"e": "kline", "E": 1, "s": "BTCUSDT", "k": {
+                "t": 0, "T": 59999, "s": "BTCUSDT", "i": "1m", "f": 3000000000i64, "L": 3000000100i64,
+                "o": "1", "c": close, "h": "3", "l": "0.5", "v": "10", "n": 101, "x": closed,
+                "q": "20", "V": "4", "Q": "8", "B": "0"}}})
+            .to_string(),
+        };
+        let frames = vec![kline(false, "2"), kline(true, "2.5")];
+        let mut klines = vec![];
+        {
+            let mut binance = BinanceWs::new(WEBSOCKET_URL);
+            binance.set_closed_klines_only(true);
+            binance.replay(&frames, ReplaySpeed::AsFastAsPossible, |event| {
+                if let WsEvent::KlineEvent(_, kline) = event {
+                    klines.push(kline);
+                }
+                Ok(())
+            });
+        }
+        assert_eq!(klines.len(), 1);
+        let kline = &klines[0];
+        assert!(kline.closed);
+        assert_eq!((kline.close, kline.close_time, kline.trades), (2.5, 59999, 101));
+        assert_eq!((kline.quote_volume, kline.taker_buy_volume), (20.0, 4.0));
+    }
+
     //#[test]
     fn test_binancews() {
         env_logger::init();
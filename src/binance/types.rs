@@ -1,6 +1,7 @@
 use crate::constant::*;
 use crate::models::*;
 use crate::utils::*;
+use serde_json::Value;
 
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -41,6 +42,15 @@ pub struct Symbol {
     pub filters: Vec<Filters>,
 }
 
+impl Symbol {
+    pub fn tick_size(&self) -> Option<f64> {
+        self.filters.iter().find_map(|filter| match filter {
+            Filters::PriceFilter { tick_size, .. } => tick_size.parse().ok(),
+            _ => None,
+        })
+    }
+}
+
 impl From<Symbol> for SymbolInfo {
     fn from(item: Symbol) -> SymbolInfo {
         SymbolInfo {
@@ -119,30 +129,405 @@ pub struct AccountInfo {
 #[serde(rename_all = "camelCase")]
 pub struct MarginAccountInfo {
     pub borrow_enabled: bool,
-    pub margin_level: String,
-    pub total_asset_of_btc: String,
-    pub total_liability_of_btc: String,
-    pub total_net_asset_of_btc: String,
+    #[serde(with = "string_or_float")]
+    pub margin_level: f64,
+    #[serde(with = "string_or_float")]
+    pub total_asset_of_btc: f64,
+    #[serde(with = "string_or_float")]
+    pub total_liability_of_btc: f64,
+    #[serde(with = "string_or_float")]
+    pub total_net_asset_of_btc: f64,
     pub trade_enabled: bool,
     pub transfer_enabled: bool,
     pub user_assets: Vec<RawMarginBalance>,
 }
+impl From<MarginAccountInfo> for MarginAccount {
+    fn from(item: MarginAccountInfo) -> MarginAccount {
+        MarginAccount {
+            symbol: String::new(),
+            margin_level: item.margin_level,
+            total_asset: item.total_asset_of_btc,
+            total_liability: item.total_liability_of_btc,
+            assets: item.user_assets.into_iter().map(|asset| asset.into()).collect(),
+        }
+    }
+}
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct RawMarginBalance {
     pub asset: String,
-    pub borrowed: String,
-    pub free: String,
-    pub interest: String,
-    pub locked: String,
-    pub net_asset: String,
+    #[serde(with = "string_or_float")]
+    pub borrowed: f64,
+    #[serde(with = "string_or_float")]
+    pub free: f64,
+    #[serde(with = "string_or_float")]
+    pub interest: f64,
+    #[serde(with = "string_or_float")]
+    pub locked: f64,
+    #[serde(with = "string_or_float")]
+    pub net_asset: f64,
 }
 impl From<RawMarginBalance> for Balance {
     fn from(item: RawMarginBalance) -> Balance {
         Balance {
             asset: item.asset,
-            free: item.free.parse::<f64>().unwrap_or(0.0),
-            locked: item.locked.parse::<f64>().unwrap_or(0.0),
+            free: item.free,
+            locked: item.locked,
+        }
+    }
+}
+impl From<RawMarginBalance> for MarginAsset {
+    fn from(item: RawMarginBalance) -> MarginAsset {
+        MarginAsset {
+            asset: item.asset,
+            free: item.free,
+            locked: item.locked,
+            borrowed: item.borrowed,
+            interest: item.interest,
+            net_asset: item.net_asset,
+        }
+    }
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IsolatedAccountInfo {
+    pub assets: Vec<RawIsolatedPair>,
+    #[serde(default, with = "string_or_float")]
+    pub total_asset_of_btc: f64,
+    #[serde(default, with = "string_or_float")]
+    pub total_liability_of_btc: f64,
+    #[serde(default, with = "string_or_float")]
+    pub total_net_asset_of_btc: f64,
+}
+// one isolated pair of the account
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RawIsolatedPair {
+    pub symbol: String,
+    pub base_asset: RawIsolatedAsset,
+    pub quote_asset: RawIsolatedAsset,
+    pub isolated_created: bool,
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(with = "string_or_float")]
+    pub margin_level: f64,
+    // EXCESSIVE, NORMAL, MARGIN_CALL, PRE_LIQUIDATION or FORCE_LIQUIDATION
+    pub margin_level_status: String,
+    #[serde(with = "string_or_float")]
+    pub margin_ratio: f64,
+    #[serde(with = "string_or_float")]
+    pub index_price: f64,
+    #[serde(with = "string_or_float")]
+    pub liquidate_price: f64,
+    #[serde(with = "string_or_float")]
+    pub liquidate_rate: f64,
+    pub trade_enabled: bool,
+}
+// binance values a pair only by its net asset, the totals are left at 0
+impl From<RawIsolatedPair> for MarginAccount {
+    fn from(item: RawIsolatedPair) -> MarginAccount {
+        MarginAccount {
+            symbol: item.symbol,
+            margin_level: item.margin_level,
+            assets: vec![item.base_asset.into(), item.quote_asset.into()],
+            ..Default::default()
+        }
+    }
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RawIsolatedAsset {
+    pub asset: String,
+    pub borrow_enabled: bool,
+    #[serde(with = "string_or_float")]
+    pub borrowed: f64,
+    #[serde(with = "string_or_float")]
+    pub free: f64,
+    #[serde(with = "string_or_float")]
+    pub interest: f64,
+    #[serde(with = "string_or_float")]
+    pub locked: f64,
+    #[serde(with = "string_or_float")]
+    pub net_asset: f64,
+    #[serde(with = "string_or_float")]
+    pub net_asset_of_btc: f64,
+    pub repay_enabled: bool,
+    #[serde(with = "string_or_float")]
+    pub total_asset: f64,
+}
+impl From<RawIsolatedAsset> for MarginAsset {
+    fn from(item: RawIsolatedAsset) -> MarginAsset {
+        MarginAsset {
+            asset: item.asset,
+            free: item.free,
+            locked: item.locked,
+            borrowed: item.borrowed,
+            interest: item.interest,
+            net_asset: item.net_asset,
+        }
+    }
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct IsolatedSymbol {
+    pub symbol: String,
+    pub base: String,
+    pub quote: String,
+    pub is_margin_trade: bool,
+    pub is_buy_allowed: bool,
+    pub is_sell_allowed: bool,
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MarginTransaction {
+    pub tran_id: u64,
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MaxBorrowable {
+    #[serde(with = "string_or_float")]
+    pub amount: f64,
+    // the pair or account limit before the available inventory is taken into account
+    #[serde(default, with = "string_or_float")]
+    pub borrow_limit: f64,
+}
+// a page of loan, repay or interest records
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MarginRecords<T> {
+    pub rows: Vec<T>,
+    pub total: u64,
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RawMarginLoan {
+    #[serde(default)]
+    pub isolated_symbol: String,
+    pub tx_id: u64,
+    pub asset: String,
+    #[serde(with = "string_or_float")]
+    pub principal: f64,
+    pub timestamp: u64,
+    // PENDING, CONFIRMED or FAILED
+    pub status: String,
+}
+impl From<RawMarginLoan> for MarginLoan {
+    fn from(item: RawMarginLoan) -> MarginLoan {
+        MarginLoan {
+            id: item.tx_id.to_string(),
+            symbol: item.isolated_symbol,
+            asset: item.asset,
+            principal: item.principal,
+            interest: 0.0,
+            timestamp: item.timestamp,
+            status: item.status,
+        }
+    }
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RawMarginRepay {
+    #[serde(default)]
+    pub isolated_symbol: String,
+    pub tx_id: u64,
+    pub asset: String,
+    #[serde(with = "string_or_float")]
+    pub amount: f64,
+    #[serde(with = "string_or_float")]
+    pub interest: f64,
+    #[serde(with = "string_or_float")]
+    pub principal: f64,
+    pub timestamp: u64,
+    pub status: String,
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RawMarginInterest {
+    #[serde(default)]
+    pub isolated_symbol: String,
+    pub asset: String,
+    #[serde(with = "string_or_float")]
+    pub interest: f64,
+    pub interest_accured_time: u64,
+    #[serde(with = "string_or_float")]
+    pub interest_rate: f64,
+    #[serde(with = "string_or_float")]
+    pub principal: f64,
+    // PERIODIC, ON_BORROW, PERIODIC_CONVERTED or ON_BORROW_CONVERTED
+    #[serde(rename = "type")]
+    pub interest_type: String,
+}
+impl From<RawMarginInterest> for MarginInterest {
+    fn from(item: RawMarginInterest) -> MarginInterest {
+        MarginInterest {
+            symbol: item.isolated_symbol,
+            asset: item.asset,
+            interest: item.interest,
+            rate: item.interest_rate,
+            timestamp: item.interest_accured_time,
+        }
+    }
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CoinInfo {
+    pub coin: String,
+    pub name: String,
+    pub deposit_all_enable: bool,
+    pub withdraw_all_enable: bool,
+    pub network_list: Vec<CoinNetwork>,
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CoinNetwork {
+    pub network: String,
+    pub coin: String,
+    pub is_default: bool,
+    pub deposit_enable: bool,
+    pub withdraw_enable: bool,
+    #[serde(with = "string_or_float")]
+    pub withdraw_fee: f64,
+    #[serde(with = "string_or_float")]
+    pub withdraw_min: f64,
+    #[serde(with = "string_or_float")]
+    pub withdraw_max: f64,
+    #[serde(default)]
+    pub min_confirm: u32,
+    #[serde(default)]
+    pub address_regex: String,
+    #[serde(default)]
+    pub memo_regex: String,
+}
+impl From<CoinNetwork> for WalletNetwork {
+    fn from(item: CoinNetwork) -> WalletNetwork {
+        WalletNetwork {
+            asset: item.coin,
+            network: item.network,
+            is_default: item.is_default,
+            deposit_enabled: item.deposit_enable,
+            withdraw_enabled: item.withdraw_enable,
+            withdraw_fee: item.withdraw_fee,
+            withdraw_min: item.withdraw_min,
+            withdraw_max: item.withdraw_max,
+            deposit_min: 0.0,
+            confirmations: item.min_confirm,
+        }
+    }
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RawDepositAddress {
+    pub address: String,
+    pub coin: String,
+    pub tag: String,
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RawDeposit {
+    #[serde(default)]
+    pub id: String,
+    #[serde(with = "string_or_float")]
+    pub amount: f64,
+    pub coin: String,
+    pub network: String,
+    // 0 pending, 1 success, 6 credited but not yet withdrawable, 7 wrong deposit, 8 waiting for
+    // the user to confirm
+    pub status: u8,
+    pub address: String,
+    #[serde(default)]
+    pub address_tag: String,
+    pub tx_id: String,
+    pub insert_time: u64,
+}
+impl From<RawDeposit> for WalletTransfer {
+    fn from(item: RawDeposit) -> WalletTransfer {
+        let status = match item.status {
+            1 | 6 => TRANSFER_STATUS_COMPLETED,
+            7 => TRANSFER_STATUS_FAILED,
+            _ => TRANSFER_STATUS_PENDING,
+        };
+        WalletTransfer {
+            id: item.id,
+            asset: item.coin,
+            network: item.network,
+            address: item.address,
+            tag: item.address_tag,
+            amount: item.amount,
+            fee: 0.0,
+            tx_id: item.tx_id,
+            timestamp: item.insert_time,
+            status,
+        }
+    }
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RawWithdraw {
+    pub id: String,
+    #[serde(with = "string_or_float")]
+    pub amount: f64,
+    #[serde(with = "string_or_float")]
+    pub transaction_fee: f64,
+    pub coin: String,
+    // 0 email sent, 1 cancelled, 2 awaiting approval, 3 rejected, 4 processing, 5 failure,
+    // 6 completed
+    pub status: u8,
+    pub address: String,
+    #[serde(default)]
+    pub address_tag: String,
+    #[serde(default)]
+    pub tx_id: String,
+    // utc, "2019-10-12 11:12:02"
+    pub apply_time: String,
+    #[serde(default)]
+    pub network: String,
+}
+impl From<RawWithdraw> for WalletTransfer {
+    fn from(item: RawWithdraw) -> WalletTransfer {
+        let status = match item.status {
+            6 => TRANSFER_STATUS_COMPLETED,
+            1 => TRANSFER_STATUS_CANCELLED,
+            3 | 5 => TRANSFER_STATUS_FAILED,
+            _ => TRANSFER_STATUS_PENDING,
+        };
+        let apply_time = format!("{} +0000", item.apply_time);
+        let timestamp = chrono::DateTime::parse_from_str(&apply_time, "%Y-%m-%d %H:%M:%S %z")
+            .map(|time| time.timestamp_millis() as u64)
+            .unwrap_or(0);
+        WalletTransfer {
+            id: item.id,
+            asset: item.coin,
+            network: item.network,
+            address: item.address,
+            tag: item.address_tag,
+            amount: item.amount,
+            fee: item.transaction_fee,
+            tx_id: item.tx_id,
+            timestamp,
+            status,
+        }
+    }
+}
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WithdrawResult {
+    pub id: String,
+}
+// how a margin order borrows or repays on its own
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SideEffectType {
+    NoSideEffect,
+    // borrows what the order is short of
+    MarginBuy,
+    // repays debt with the proceeds
+    AutoRepay,
+}
+impl SideEffectType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SideEffectType::NoSideEffect => "NO_SIDE_EFFECT",
+            SideEffectType::MarginBuy => "MARGIN_BUY",
+            SideEffectType::AutoRepay => "AUTO_REPAY",
         }
     }
 }
@@ -362,6 +747,8 @@ pub struct TradeHistory {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct PriceStats {
+    #[serde(default)]
+    pub symbol: String,
     pub price_change: String,
     pub price_change_percent: String,
     pub weighted_avg_price: String,
@@ -381,13 +768,34 @@ pub struct PriceStats {
     pub low_price: f64,
     #[serde(with = "string_or_float")]
     pub volume: f64,
+    #[serde(with = "string_or_float")]
+    pub quote_volume: f64,
     pub open_time: u64,
     pub close_time: u64,
-    pub first_id: u64,
-    pub last_id: u64,
+    // -1 without trades in the window
+    pub first_id: i64,
+    pub last_id: i64,
     pub count: u64,
 }
 
+impl From<PriceStats> for DayStats {
+    fn from(item: PriceStats) -> DayStats {
+        DayStats {
+            symbol: item.symbol,
+            timestamp: item.close_time,
+            open: item.open_price,
+            high: item.high_price,
+            low: item.low_price,
+            close: item.last_price,
+            volume: item.volume,
+            quote_volume: item.quote_volume,
+            trades: item.count,
+            bid: item.bid_price,
+            ask: item.ask_price,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct AccountUpdateEvent {
@@ -513,7 +921,9 @@ impl From<TradeEvent> for Trade {
         let side = if item.is_buyer_maker { "sell" } else { "buy" };
 
         Trade {
-            timestamp: item.event_time,
+            id: item.aggregated_trade_id,
+            symbol: item.symbol,
+            timestamp: item.trade_order_time,
             amount: str_to_f64(&item.qty),
             price: str_to_f64(&item.price),
             side: side.into(),
@@ -521,6 +931,101 @@ impl From<TradeEvent> for Trade {
     }
 }
 
+// <symbol>@trade, one event per trade
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RawTradeEvent {
+    #[serde(rename = "e")]
+    pub event_type: String,
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "t")]
+    pub trade_id: u64,
+    #[serde(rename = "p")]
+    pub price: String,
+    #[serde(rename = "q")]
+    pub qty: String,
+    #[serde(rename = "T")]
+    pub trade_time: u64,
+    #[serde(rename = "m")]
+    pub is_buyer_maker: bool,
+}
+
+impl From<RawTradeEvent> for Trade {
+    fn from(item: RawTradeEvent) -> Trade {
+        let side = if item.is_buyer_maker { "sell" } else { "buy" };
+        Trade {
+            id: item.trade_id,
+            symbol: item.symbol,
+            timestamp: item.trade_time,
+            amount: str_to_f64(&item.qty),
+            price: str_to_f64(&item.price),
+            side: side.into(),
+        }
+    }
+}
+
+// a row of /api/v3/trades and /api/v3/historicalTrades, they carry no symbol
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RawTrade {
+    pub id: u64,
+    #[serde(with = "string_or_float")]
+    pub price: f64,
+    #[serde(with = "string_or_float")]
+    pub qty: f64,
+    pub time: u64,
+    pub is_buyer_maker: bool,
+}
+
+impl From<RawTrade> for Trade {
+    fn from(item: RawTrade) -> Trade {
+        let side = if item.is_buyer_maker { "sell" } else { "buy" };
+        Trade {
+            id: item.id,
+            symbol: String::new(),
+            timestamp: item.time,
+            amount: item.qty,
+            price: item.price,
+            side: side.into(),
+        }
+    }
+}
+
+// a row of /api/v3/aggTrades
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RawAggTrade {
+    #[serde(rename = "a")]
+    pub id: u64,
+    #[serde(rename = "p", with = "string_or_float")]
+    pub price: f64,
+    #[serde(rename = "q", with = "string_or_float")]
+    pub qty: f64,
+    #[serde(rename = "f")]
+    pub first_trade_id: u64,
+    #[serde(rename = "l")]
+    pub last_trade_id: u64,
+    #[serde(rename = "T")]
+    pub time: u64,
+    #[serde(rename = "m")]
+    pub is_buyer_maker: bool,
+}
+
+impl From<RawAggTrade> for Trade {
+    fn from(item: RawAggTrade) -> Trade {
+        let side = if item.is_buyer_maker { "sell" } else { "buy" };
+        Trade {
+            id: item.id,
+            symbol: String::new(),
+            timestamp: item.time,
+            amount: item.qty,
+            price: item.price,
+            side: side.into(),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct BookTickerEvent {
@@ -605,6 +1110,80 @@ pub struct DayTickerEvent {
     pub num_trades: u64,
 }
 
+// <symbol>@miniTicker and !miniTicker@arr, 24h stats without the book or trade count
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct MiniTickerEvent {
+    #[serde(rename = "e")]
+    pub event_type: String,
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "c")]
+    pub close: String,
+    #[serde(rename = "o")]
+    pub open: String,
+    #[serde(rename = "h")]
+    pub high: String,
+    #[serde(rename = "l")]
+    pub low: String,
+    #[serde(rename = "v")]
+    pub volume: String,
+    #[serde(rename = "q")]
+    pub quote_volume: String,
+}
+
+impl From<MiniTickerEvent> for DayStats {
+    fn from(item: MiniTickerEvent) -> DayStats {
+        DayStats {
+            symbol: item.symbol,
+            timestamp: item.event_time,
+            open: str_to_f64(&item.open),
+            high: str_to_f64(&item.high),
+            low: str_to_f64(&item.low),
+            close: str_to_f64(&item.close),
+            volume: str_to_f64(&item.volume),
+            quote_volume: str_to_f64(&item.quote_volume),
+            ..Default::default()
+        }
+    }
+}
+
+// <symbol>@avgPrice
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AvgPriceEvent {
+    #[serde(rename = "e")]
+    pub event_type: String,
+    #[serde(rename = "E")]
+    pub event_time: u64,
+    #[serde(rename = "s")]
+    pub symbol: String,
+    #[serde(rename = "i")]
+    pub interval: String,
+    #[serde(rename = "w", with = "string_or_float")]
+    pub price: f64,
+    #[serde(rename = "T")]
+    pub last_trade_time: u64,
+}
+
+impl From<DayTickerEvent> for DayStats {
+    fn from(item: DayTickerEvent) -> DayStats {
+        DayStats {
+            symbol: item.symbol,
+            timestamp: item.close_time,
+            open: str_to_f64(&item.open),
+            high: str_to_f64(&item.high),
+            low: str_to_f64(&item.low),
+            close: str_to_f64(&item.current_close),
+            volume: str_to_f64(&item.volume),
+            quote_volume: str_to_f64(&item.quote_volume),
+            trades: item.num_trades,
+            bid: str_to_f64(&item.best_bid),
+            ask: str_to_f64(&item.best_ask),
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct KlineEvent {
@@ -633,6 +1212,45 @@ pub struct KlineSummary {
     pub taker_buy_quote_asset_volume: f64,
 }
 
+// one row of /api/v3/klines or /fapi/v1/klines
+impl From<&Vec<Value>> for KlineSummary {
+    fn from(row: &Vec<Value>) -> KlineSummary {
+        KlineSummary {
+            open_time: to_i64(&row[0]),
+            open: to_f64(&row[1]),
+            high: to_f64(&row[2]),
+            low: to_f64(&row[3]),
+            close: to_f64(&row[4]),
+            volume: to_f64(&row[5]),
+            close_time: to_i64(&row[6]),
+            quote_asset_volume: to_f64(&row[7]),
+            number_of_trades: to_i64(&row[8]),
+            taker_buy_base_asset_volume: to_f64(&row[9]),
+            taker_buy_quote_asset_volume: to_f64(&row[10]),
+        }
+    }
+}
+
+// rest rows carry no closed flag, the last row is open until its close time passes
+impl From<KlineSummary> for Kline {
+    fn from(item: KlineSummary) -> Kline {
+        let now = get_timestamp().unwrap_or(0) as i64;
+        Kline {
+            timestamp: item.open_time as u64,
+            open: item.open,
+            high: item.high,
+            low: item.low,
+            close: item.close,
+            volume: item.volume,
+            close_time: item.close_time as u64,
+            closed: item.close_time < now,
+            quote_volume: item.quote_asset_volume,
+            trades: item.number_of_trades as u64,
+            taker_buy_volume: item.taker_buy_base_asset_volume,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct RawKline {
@@ -645,9 +1263,9 @@ pub struct RawKline {
     #[serde(rename = "i")]
     pub interval: String,
     #[serde(rename = "f")]
-    pub first_trade_id: i32,
+    pub first_trade_id: i64,
     #[serde(rename = "L")]
-    pub last_trade_id: i32,
+    pub last_trade_id: i64,
     #[serde(rename = "o")]
     pub open: String,
     #[serde(rename = "c")]
@@ -659,7 +1277,7 @@ pub struct RawKline {
     #[serde(rename = "v")]
     pub volume: String,
     #[serde(rename = "n")]
-    pub number_of_trades: i32,
+    pub number_of_trades: i64,
     #[serde(rename = "x")]
     pub is_final_bar: bool,
     #[serde(rename = "q")]
@@ -681,10 +1299,36 @@ impl From<RawKline> for Kline {
             low: str_to_f64(&item.low),
             close: str_to_f64(&item.close),
             volume: str_to_f64(&item.volume),
+            close_time: item.end_time as u64,
+            closed: item.is_final_bar,
+            quote_volume: str_to_f64(&item.quote_volume),
+            trades: item.number_of_trades as u64,
+            taker_buy_volume: str_to_f64(&item.active_buy_volume),
         }
     }
 }
 
+// binance serves every KlineInterval
+pub fn kline_interval(interval: KlineInterval) -> &'static str {
+    match interval {
+        KlineInterval::Min1 => "1m",
+        KlineInterval::Min3 => "3m",
+        KlineInterval::Min5 => "5m",
+        KlineInterval::Min15 => "15m",
+        KlineInterval::Min30 => "30m",
+        KlineInterval::Hour1 => "1h",
+        KlineInterval::Hour2 => "2h",
+        KlineInterval::Hour4 => "4h",
+        KlineInterval::Hour6 => "6h",
+        KlineInterval::Hour8 => "8h",
+        KlineInterval::Hour12 => "12h",
+        KlineInterval::Day1 => "1d",
+        KlineInterval::Day3 => "3d",
+        KlineInterval::Week1 => "1w",
+        KlineInterval::Month1 => "1M",
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct DepthOrderbookEvent {
@@ -813,3 +1457,119 @@ pub struct RawSwapAccount {
     pub assets: Vec<RawSwapBalance>,
     pub positions: Vec<RawPosition>,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transfer_status() {
+        let raw = r#"{"id":"769800519366885376","amount":"0.001","coin":"BNB","network":"BNB","status":1,
+            "address":"bnb136ns6lfw4zs5hg4n85vdthaad7hq5m4gtkgf23","addressTag":"101764890",
+            "txId":"98A3EA560C6B3336D348B6C83F0F95ECE4F1F5919E94BD006E5BF3BF264FACFC","insertTime":1661493146000,
+            "transferType":0,"confirmTimes":"1/1","unlockConfirm":0,"walletType":0}"#;
+        let deposit: RawDeposit = serde_json::from_str(raw).unwrap();
+        let transfer = WalletTransfer::from(deposit.clone());
+        assert_eq!((transfer.amount, transfer.tag.as_str()), (0.001, "101764890"));
+        assert_eq!((transfer.timestamp, transfer.status), (1661493146000, TRANSFER_STATUS_COMPLETED));
+        let status = |status: u8| WalletTransfer::from(RawDeposit { status, ..deposit.clone() }).status;
+        assert_eq!(status(0), TRANSFER_STATUS_PENDING);
+        assert_eq!(status(6), TRANSFER_STATUS_COMPLETED);
+        assert_eq!(status(7), TRANSFER_STATUS_FAILED);
+        assert_eq!(status(8), TRANSFER_STATUS_PENDING);
+
+        let raw = r#"{"id":"b6ae22b3aa844210a7041aee7589627c","amount":"8.91000000","transactionFee":"0.004",
+            "coin":"USDT","status":6,"address":"0x94df8b352de7f46f64b01d3666bf6e936e44ce60",
+            "txId":"0xb5ef8c13b968a406cc62a93a8bd80f9e9a906ef1b3fcf20a2e48573c17659268",
+            "applyTime":"2019-10-12 11:12:02","network":"ETH","transferType":0}"#;
+        let withdraw: RawWithdraw = serde_json::from_str(raw).unwrap();
+        let transfer = WalletTransfer::from(withdraw.clone());
+        assert_eq!((transfer.amount, transfer.fee, transfer.network.as_str()), (8.91, 0.004, "ETH"));
+        assert_eq!((transfer.timestamp, transfer.status), (1570878722000, TRANSFER_STATUS_COMPLETED));
+        let status = |status: u8| WalletTransfer::from(RawWithdraw { status, ..withdraw.clone() }).status;
+        assert_eq!(status(0), TRANSFER_STATUS_PENDING);
+        assert_eq!(status(1), TRANSFER_STATUS_CANCELLED);
+        assert_eq!(status(3), TRANSFER_STATUS_FAILED);
+        assert_eq!(status(4), TRANSFER_STATUS_PENDING);
+        assert_eq!(status(5), TRANSFER_STATUS_FAILED);
+    }
+
+    #[test]
+    fn test_trades() {
+        // the symbol is filled in by the caller, neither endpoint carries it
+        let raw = r#"[{"id":28457,"price":"4.00000100","qty":"12.00000000","quoteQty":"48.000012",
+            "time":1499865549590,"isBuyerMaker":true,"isBestMatch":true}]"#;
+        let trades: Vec<RawTrade> = serde_json::from_str(raw).unwrap();
+        let trade = Trade::from(trades[0].clone());
+        assert_eq!((trade.id, trade.symbol.as_str(), trade.side.as_str()), (28457, "", "sell"));
+        assert_eq!((trade.timestamp, trade.price, trade.amount), (1499865549590, 4.000001, 12.0));
+
+        // keyed by the aggregate id, not the first or last trade id it spans
+        let raw = r#"[{"a":26129,"p":"0.01633102","q":"4.70443515","f":27781,"l":27783,"T":1498793709153,
+            "m":false,"M":true}]"#;
+        let trades: Vec<RawAggTrade> = serde_json::from_str(raw).unwrap();
+        assert_eq!((trades[0].first_trade_id, trades[0].last_trade_id), (27781, 27783));
+        let trade = Trade::from(trades[0].clone());
+        assert_eq!((trade.id, trade.symbol.as_str(), trade.side.as_str()), (26129, "", "buy"));
+        assert_eq!((trade.timestamp, trade.price, trade.amount), (1498793709153, 0.01633102, 4.70443515));
+    }
+
+    #[test]
+    fn test_margin_account() {
+        let raw = r#"{"borrowEnabled":true,"marginLevel":"11.64405625","totalAssetOfBtc":"6.82728457",
+            "totalLiabilityOfBtc":"0.58633215","totalNetAssetOfBtc":"6.24095242","tradeEnabled":true,
+            "transferEnabled":true,"userAssets":[{"asset":"BTC","borrowed":"0.00000000","free":"0.00499500",
+            "interest":"0.00000000","locked":"0.00000000","netAsset":"0.00499500"},{"asset":"USDT",
+            "borrowed":"100.00000000","free":"250.50000000","interest":"0.01250000","locked":"10.00000000",
+            "netAsset":"160.48750000"}]}"#;
+        let account = MarginAccount::from(serde_json::from_str::<MarginAccountInfo>(raw).unwrap());
+        assert_eq!(account.symbol, "");
+        assert_eq!(account.margin_level, 11.64405625);
+        assert_eq!((account.total_asset, account.total_liability), (6.82728457, 0.58633215));
+        let usdt = &account.assets[1];
+        assert_eq!((usdt.asset.as_str(), usdt.free, usdt.locked), ("USDT", 250.5, 10.0));
+        assert_eq!((usdt.borrowed, usdt.interest, usdt.net_asset), (100.0, 0.0125, 160.4875));
+
+        let raw = r#"{"assets":[{"baseAsset":{"asset":"BTC","borrowEnabled":true,"borrowed":"0.00010000",
+            "free":"0.00500000","interest":"0.00000010","locked":"0.00000000","netAsset":"0.00489990",
+            "netAssetOfBtc":"0.00489990","repayEnabled":true,"totalAsset":"0.00500000"},"quoteAsset":{"asset":"USDT",
+            "borrowEnabled":true,"borrowed":"0.00000000","free":"80.00000000","interest":"0.00000000",
+            "locked":"20.00000000","netAsset":"100.00000000","netAssetOfBtc":"0.00250000","repayEnabled":true,
+            "totalAsset":"100.00000000"},"symbol":"BTCUSDT","isolatedCreated":true,"enabled":true,
+            "marginLevel":"2.50000000","marginLevelStatus":"EXCESSIVE","marginRatio":"10.00000000",
+            "indexPrice":"40000.00000000","liquidatePrice":"1000.00000000","liquidateRate":"1.00000000",
+            "tradeEnabled":true}],"totalAssetOfBtc":"0.00750000","totalLiabilityOfBtc":"0.00010000",
+            "totalNetAssetOfBtc":"0.00739990"}"#;
+        let info: IsolatedAccountInfo = serde_json::from_str(raw).unwrap();
+        assert_eq!(info.total_net_asset_of_btc, 0.0073999);
+        let account = MarginAccount::from(info.assets[0].clone());
+        assert_eq!((account.symbol.as_str(), account.margin_level, account.total_asset), ("BTCUSDT", 2.5, 0.0));
+        let assets: Vec<_> = account.assets.iter().map(|asset| (asset.asset.as_str(), asset.net_asset)).collect();
+        assert_eq!(assets, vec![("BTC", 0.0048999), ("USDT", 100.0)]);
+        assert_eq!((account.assets[0].borrowed, account.assets[0].interest), (0.0001, 0.0000001));
+        assert_eq!((account.assets[1].free, account.assets[1].locked), (80.0, 20.0));
+    }
+
+    #[test]
+    fn test_margin_records() {
+        let raw = r#"{"rows":[{"isolatedSymbol":"BNBUSDT","txId":12807067523,"asset":"BNB","principal":"0.84624403",
+            "timestamp":1555056425000,"status":"CONFIRMED"},{"txId":12807067524,"asset":"USDT",
+            "principal":"10.00000000","timestamp":1555056426000,"status":"PENDING"}],"total":2}"#;
+        let page: MarginRecords<RawMarginLoan> = serde_json::from_str(raw).unwrap();
+        assert_eq!(page.total, 2);
+        let loans: Vec<MarginLoan> = page.rows.into_iter().map(|row| row.into()).collect();
+        let loan = &loans[0];
+        assert_eq!((loan.id.as_str(), loan.symbol.as_str(), loan.asset.as_str()), ("12807067523", "BNBUSDT", "BNB"));
+        assert_eq!((loans[0].principal, loans[0].interest, loans[0].timestamp), (0.84624403, 0.0, 1555056425000));
+        assert_eq!((loans[1].symbol.as_str(), loans[1].status.as_str()), ("", "PENDING"));
+
+        let raw = r#"{"rows":[{"isolatedSymbol":"BNBUSDT","asset":"BNB","interest":"0.02414667",
+            "interestAccuredTime":1566813600000,"interestRate":"0.01600000","principal":"36.22000000",
+            "type":"ON_BORROW"}],"total":1}"#;
+        let page: MarginRecords<RawMarginInterest> = serde_json::from_str(raw).unwrap();
+        assert_eq!(page.rows[0].interest_type, "ON_BORROW");
+        let interest = MarginInterest::from(page.rows[0].clone());
+        assert_eq!((interest.symbol.as_str(), interest.asset.as_str()), ("BNBUSDT", "BNB"));
+        assert_eq!((interest.interest, interest.rate, interest.timestamp), (0.02414667, 0.016, 1566813600000));
+    }
+}
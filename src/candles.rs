@@ -0,0 +1,274 @@
+use crate::errors::*;
+use crate::models::*;
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+
+// what closes a bar, thresholds are reached by the trade that crosses them, trades are never split
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BarKind {
+    // bars of this many ms aligned to the epoch, e.g. KlineInterval::Min3.millis() or 7 * 60 * 1000,
+    // not 0
+    Time(u64),
+    // number of trades
+    Tick(u64),
+    // base amount
+    Volume(f64),
+    // quote amount, price * amount summed
+    Dollar(f64),
+}
+
+// builds bars from the trades of one symbol, fed in the order they arrive
+#[derive(Debug, Clone)]
+pub struct CandleBuilder {
+    kind: BarKind,
+    bar: Option<Kline>,
+}
+
+impl CandleBuilder {
+    pub fn new(kind: BarKind) -> APIResult<Self> {
+        if kind == BarKind::Time(0) {
+            return Err(Box::new(ExError::ApiError("time bars need a period".into())));
+        }
+        Ok(CandleBuilder { kind, bar: None })
+    }
+
+    // returns the bar the trade completed, if any. Time bars close on the first trade of a
+    // later bar, intervals without trades produce no bar, and trades older than the open
+    // bar are dropped
+    pub fn push(&mut self, trade: &Trade) -> Option<Kline> {
+        let mut closed = None;
+        if let BarKind::Time(period) = self.kind {
+            let start = trade.timestamp - trade.timestamp % period;
+            match &self.bar {
+                Some(bar) if start < bar.timestamp => return None,
+                Some(bar) if start > bar.timestamp => closed = self.close(),
+                _ => {}
+            }
+            if self.bar.is_none() {
+                self.open(start, trade.price);
+                self.bar.as_mut().unwrap().close_time = start + period - 1;
+            }
+        } else if self.bar.is_none() {
+            self.open(trade.timestamp, trade.price);
+        }
+
+        let bar = self.bar.as_mut().unwrap();
+        bar.high = bar.high.max(trade.price);
+        bar.low = bar.low.min(trade.price);
+        bar.close = trade.price;
+        bar.volume += trade.amount;
+        bar.quote_volume += trade.price * trade.amount;
+        bar.trades += 1;
+        if trade.side == "buy" {
+            bar.taker_buy_volume += trade.amount;
+        }
+
+        let full = match self.kind {
+            BarKind::Time(_) => false,
+            BarKind::Tick(trades) => bar.trades >= trades,
+            BarKind::Volume(volume) => bar.volume >= volume,
+            BarKind::Dollar(quote) => bar.quote_volume >= quote,
+        };
+        if full {
+            bar.close_time = trade.timestamp;
+            closed = self.close();
+        }
+        closed
+    }
+
+    // every bar completed by `trades`, in order
+    pub fn push_all(&mut self, trades: &[Trade]) -> Vec<Kline> {
+        trades.iter().filter_map(|trade| self.push(trade)).collect()
+    }
+
+    // the bar still being built
+    pub fn current(&self) -> Option<&Kline> {
+        self.bar.as_ref()
+    }
+
+    // hands out the open bar early, e.g. at the end of a backfill, it stays marked as not closed
+    pub fn flush(&mut self) -> Option<Kline> {
+        self.bar.take()
+    }
+
+    fn open(&mut self, timestamp: u64, price: f64) {
+        self.bar = Some(Kline {
+            timestamp,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            ..Default::default()
+        });
+    }
+
+    fn close(&mut self) -> Option<Kline> {
+        let mut bar = self.bar.take()?;
+        bar.closed = true;
+        Some(bar)
+    }
+}
+
+// merges sorted candles into bars of `interval`, opening when the exchanges open theirs: weeks
+// on monday, months on the 1st (utc) and shorter bars aligned to the epoch. A bucket the input
+// only partly covers is still returned, it counts as closed once a closed candle reaches its end
+pub fn resample(klines: &[Kline], interval: KlineInterval) -> Vec<Kline> {
+    let mut bars: Vec<Kline> = vec![];
+    for kline in klines {
+        let (start, end) = bucket(interval, kline.timestamp);
+        match bars.last_mut() {
+            Some(bar) if bar.timestamp == start => {
+                bar.high = bar.high.max(kline.high);
+                bar.low = bar.low.min(kline.low);
+                bar.close = kline.close;
+                bar.volume += kline.volume;
+                bar.quote_volume += kline.quote_volume;
+                bar.trades += kline.trades;
+                bar.taker_buy_volume += kline.taker_buy_volume;
+                bar.closed = kline.closed && kline.close_time >= end;
+            }
+            _ => bars.push(Kline {
+                timestamp: start,
+                close_time: end,
+                closed: kline.closed && kline.close_time >= end,
+                ..kline.clone()
+            }),
+        }
+    }
+    bars
+}
+
+// open time and last ms of the bar of `interval` holding `timestamp`
+fn bucket(interval: KlineInterval, timestamp: u64) -> (u64, u64) {
+    const DAY: u64 = 24 * 3600 * 1000;
+    match interval {
+        KlineInterval::Month1 => {
+            let date = NaiveDateTime::from_timestamp((timestamp / 1000) as i64, 0).date();
+            let (year, month) = (date.year(), date.month());
+            let next = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+            let start = NaiveDate::from_ymd(year, month, 1).and_hms(0, 0, 0).timestamp() as u64 * 1000;
+            let end = NaiveDate::from_ymd(next.0, next.1, 1).and_hms(0, 0, 0).timestamp() as u64 * 1000;
+            (start, end - 1)
+        }
+        // the epoch is a thursday, monday is 4 days later
+        KlineInterval::Week1 => {
+            let period = interval.millis();
+            let start = timestamp - (timestamp + 3 * DAY) % period;
+            (start, start + period - 1)
+        }
+        _ => {
+            let period = interval.millis();
+            let start = timestamp - timestamp % period;
+            (start, start + period - 1)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn trade(timestamp: u64, price: f64, amount: f64) -> Trade {
+        Trade {
+            timestamp,
+            amount,
+            price,
+            side: "buy".into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_candle_builder() {
+        let minute = KlineInterval::Min1.millis();
+        let mut builder = CandleBuilder::new(BarKind::Time(3 * minute)).unwrap();
+        let trades = vec![
+            trade(10, 100.0, 1.0),
+            trade(minute, 103.0, 2.0),
+            trade(2 * minute, 99.0, 1.0),
+            trade(3 * minute + 5, 101.0, 1.0),
+            trade(5, 50.0, 1.0),
+            trade(10 * minute, 102.0, 1.0),
+        ];
+        let bars = builder.push_all(&trades);
+        assert_eq!(bars.len(), 2);
+        let bar = &bars[0];
+        assert_eq!(bar.timestamp, 0);
+        assert_eq!((bar.open, bar.high, bar.low, bar.close, bar.volume), (100.0, 103.0, 99.0, 99.0, 4.0));
+        assert!(bar.closed);
+        assert_eq!((bar.close_time, bar.trades, bar.taker_buy_volume), (3 * minute - 1, 3, 4.0));
+        assert_eq!((bars[1].timestamp, bars[1].volume), (3 * minute, 1.0));
+        assert_eq!(builder.current().unwrap().timestamp, 9 * minute);
+        assert!(builder.flush().is_some());
+        assert!(builder.current().is_none());
+
+        let mut builder = CandleBuilder::new(BarKind::Tick(2)).unwrap();
+        let bars = builder.push_all(&trades[..5]);
+        assert_eq!(bars.iter().map(|bar| bar.timestamp).collect::<Vec<_>>(), vec![10, 2 * minute]);
+
+        let mut builder = CandleBuilder::new(BarKind::Volume(3.0)).unwrap();
+        let bars = builder.push_all(&trades[..4]);
+        assert_eq!(bars.len(), 1);
+        assert_eq!((bars[0].close, bars[0].volume), (103.0, 3.0));
+
+        let mut builder = CandleBuilder::new(BarKind::Dollar(200.0)).unwrap();
+        let bars = builder.push_all(&trades[..3]);
+        assert_eq!(bars.len(), 1);
+        assert_eq!(bars[0].close, 103.0);
+        assert_eq!(builder.current().unwrap().open, 99.0);
+    }
+
+    #[test]
+    fn test_resample() {
+        let klines = (0..7u64)
+            .map(|i| Kline {
+                timestamp: i * 60_000,
+                open: i as f64,
+                high: i as f64 + 2.0,
+                low: i as f64 - 1.0,
+                close: i as f64 + 1.0,
+                volume: 1.0,
+                close_time: i * 60_000 + 59_999,
+                closed: true,
+                trades: 2,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let bars = resample(&klines, KlineInterval::Min3);
+        assert_eq!(bars.len(), 3);
+        let bar = &bars[1];
+        assert_eq!(bar.timestamp, 180_000);
+        assert_eq!((bar.open, bar.high, bar.low, bar.close, bar.volume), (3.0, 7.0, 2.0, 6.0, 3.0));
+        assert_eq!((bar.close_time, bar.trades, bar.closed), (359_999, 6, true));
+        assert_eq!((bars[2].open, bars[2].volume, bars[2].closed), (6.0, 1.0, false));
+
+        // daily candles from thursday 2024-01-25 to saturday 2024-03-02
+        let day = KlineInterval::Day1.millis();
+        let first = 1706140800000;
+        let klines = (0..38u64)
+            .map(|i| Kline {
+                timestamp: first + i * day,
+                close_time: first + (i + 1) * day - 1,
+                volume: 1.0,
+                closed: true,
+                ..Default::default()
+            })
+            .collect::<Vec<_>>();
+        let weeks = resample(&klines, KlineInterval::Week1);
+        // monday 2024-01-22, 2024-01-29 and 2024-02-26
+        assert_eq!((weeks[0].timestamp, weeks[0].volume, weeks[0].closed), (1705881600000, 4.0, true));
+        assert_eq!((weeks[1].timestamp, weeks[1].close_time), (1706486400000, 1706486400000 + 7 * day - 1));
+        assert_eq!((weeks[5].timestamp, weeks[5].volume, weeks[5].closed), (1708905600000, 6.0, false));
+        let months = resample(&klines, KlineInterval::Month1);
+        // 2024-01-01, 2024-02-01 with its 29 days and 2024-03-01
+        assert_eq!(months.len(), 3);
+        assert_eq!((months[0].timestamp, months[0].volume), (1704067200000, 7.0));
+        assert_eq!((months[1].timestamp, months[1].close_time), (1706745600000, 1709251200000 - 1));
+        assert_eq!((months[1].volume, months[1].closed), (29.0, true));
+        assert_eq!((months[2].timestamp, months[2].volume, months[2].closed), (1709251200000, 2.0, false));
+    }
+
+    #[test]
+    fn test_zero_time_bars() {
+        assert!(CandleBuilder::new(BarKind::Time(0)).is_err());
+    }
+}
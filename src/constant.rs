@@ -9,3 +9,8 @@ pub const ORDER_STATUS_PART_FILLED: u8 = 2;
 pub const ORDER_STATUS_CANCELLED: u8 = 3;
 pub const ORDER_STATUS_SUBMITTED: u8 = 4;
 pub const ORDER_STATUS_FAILED: u8 = 5;
+
+pub const TRANSFER_STATUS_PENDING: u8 = 1;
+pub const TRANSFER_STATUS_COMPLETED: u8 = 2;
+pub const TRANSFER_STATUS_FAILED: u8 = 3;
+pub const TRANSFER_STATUS_CANCELLED: u8 = 4;
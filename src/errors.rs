@@ -8,6 +8,8 @@ pub enum ExError {
     ApiError(String),
     RateLimitExceeded(String),
     IpBanned(String),
+    InsufficientLiquidity(String),
+    OrderbookNotReady(String),
 }
 
 impl fmt::Display for ExError {
@@ -16,6 +18,8 @@ impl fmt::Display for ExError {
             ExError::ApiError(why) => write!(f, "ApiError: {}", why),
             ExError::RateLimitExceeded(why) => write!(f, "RateLimitExceeded: {}", why),
             ExError::IpBanned(why) => write!(f, "IpBanned: {}", why),
+            ExError::InsufficientLiquidity(why) => write!(f, "InsufficientLiquidity: {}", why),
+            ExError::OrderbookNotReady(why) => write!(f, "OrderbookNotReady: {}", why),
         }
     }
 }
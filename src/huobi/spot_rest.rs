@@ -1,20 +1,28 @@
 use crate::errors::*;
+use crate::huobi::spot_ws::{request_all, TradeTick};
 use crate::huobi::types::*;
 use crate::models::*;
 use crate::utils::*;
 use crate::traits::*;
+use crate::websocket::WsConfig;
 
 use ring::{digest, hmac};
-use serde_json::Value;
-use std::collections::BTreeMap;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+
+// the most /v1/query/deposit-withdraw returns per call
+const DEPOSIT_WITHDRAW_PAGE: usize = 500;
 
 #[derive(Clone)]
 pub struct Huobi {
     api_key: String,
     secret_key: String,
     host: String,
+    ws_host: String,
+    ws_config: WsConfig,
     account_id: String,
     account_type: String,
+    withdraw_whitelist: WithdrawWhitelist,
 }
 
 impl Huobi {
@@ -22,17 +30,36 @@ impl Huobi {
         Huobi {
             api_key: api_key.unwrap_or_else(|| "".into()),
             secret_key: secret_key.unwrap_or_else(|| "".into()),
+            ws_host: format!("{}/ws", host.replacen("https://", "wss://", 1)),
+            ws_config: WsConfig::default(),
             host,
             account_id: "".into(),
             account_type: "spot".into(),
+            withdraw_whitelist: WithdrawWhitelist::default(),
         }
     }
 
+    // market data websocket for the requests the rest api can not serve,
+    // wss://<rest host>/ws by default
+    pub fn set_ws_host(&mut self, ws_host: &str) {
+        self.ws_host = ws_host.into();
+    }
+
+    // proxy, tls and timeouts of that websocket
+    pub fn set_ws_config(&mut self, ws_config: WsConfig) {
+        self.ws_config = ws_config;
+    }
+
     pub fn set_account(&mut self, account_type: &str, account_id: &str) {
         self.account_id = account_id.into();
         self.account_type = account_type.into();
     }
 
+    // `tag` is the memo of chains that need one, see WithdrawWhitelist for `chain`
+    pub fn allow_withdraw_address(&mut self, asset: &str, chain: Option<&str>, address: &str, tag: Option<&str>) {
+        self.withdraw_whitelist.allow(asset, chain, address, tag);
+    }
+
     pub fn get_account_id(&self, account_type: &str) -> APIResult<String> {
         let uri = "/v1/account/accounts";
         let params: BTreeMap<String, String> = BTreeMap::new();
@@ -215,6 +242,45 @@ impl Huobi {
         Ok(ticker)
     }
 
+    // /market/detail carries no book, bid and ask are left at 0
+    pub fn get_24h_stats_raw(&self, symbol: &str) -> APIResult<DayStats> {
+        let uri = "/market/detail";
+        let params = format!("symbol={}", symbol.to_lowercase());
+        let ret = self.get(uri, &params)?;
+        let resp: Response<RawDetail> = serde_json::from_str(&ret)?;
+        let mut stats: DayStats = resp.tick.into();
+        stats.symbol = symbol.to_lowercase();
+        stats.timestamp = resp.ts;
+        Ok(stats)
+    }
+
+    // 24h stats and the best bid and ask of every market
+    pub fn get_tickers_raw(&self) -> APIResult<(u64, Vec<MarketTicker>)> {
+        let uri = "/market/tickers";
+        let ret = self.get(uri, "")?;
+        let resp: Response<Vec<MarketTicker>> = serde_json::from_str(&ret)?;
+        Ok((resp.ts, resp.data))
+    }
+
+    // newest first, at most 2000
+    pub fn get_trades_raw(&self, symbol: &str, limit: u16) -> APIResult<Vec<Trade>> {
+        let uri = "/market/history/trade";
+        let symbol = symbol.to_lowercase();
+        let params = format!("symbol={}&size={}", symbol, limit);
+        let ret = self.get(uri, &params)?;
+        let resp: Response<Vec<TradeTick>> = serde_json::from_str(&ret)?;
+        let trades = resp
+            .data
+            .into_iter()
+            .flat_map(|tick| tick.data)
+            .map(|raw_trade| Trade {
+                symbol: symbol.clone(),
+                ..raw_trade.into()
+            })
+            .collect::<Vec<Trade>>();
+        Ok(trades)
+    }
+
     pub fn get_kline_raw(&self, symbol: &str, period: &str, limit: u16) -> APIResult<Vec<Kline>> {
         let uri = "/market/history/kline";
         let params = format!(
@@ -228,11 +294,58 @@ impl Huobi {
         let klines = resp
             .data
             .into_iter()
-            .map(|kline| kline.into())
+            .map(|kline| kline.into_kline(period))
             .collect::<Vec<Kline>>();
         Ok(klines)
     }
 
+    // the rest api only serves the latest candles, history is pulled over the websocket
+    // in windows of at most 300 candles
+    pub fn get_kline_range_raw(
+        &self,
+        symbol: &str,
+        interval: KlineInterval,
+        start: u64,
+        end: u64,
+    ) -> APIResult<Vec<Kline>> {
+        const WINDOW: u64 = 300;
+        let period = match kline_period(interval) {
+            Some(period) => period,
+            None => {
+                return Err(Box::new(ExError::ApiError(format!(
+                    "huobi has no {:?} klines",
+                    interval
+                ))))
+            }
+        };
+        let channel = format!("market.{}.kline.{}", symbol.to_lowercase(), period);
+        let span = WINDOW * interval.millis() / 1000;
+        let (from, to) = (start / 1000, end / 1000);
+        let mut reqs = vec![];
+        let mut cursor = from;
+        while cursor <= to {
+            let until = (cursor + span - 1).min(to);
+            reqs.push(json!({
+                "req": channel,
+                "id": (reqs.len() + 1).to_string(),
+                "from": cursor,
+                "to": until,
+            }));
+            cursor = until + 1;
+        }
+
+        let mut klines = BTreeMap::new();
+        for data in request_all(&self.ws_host, self.ws_config.clone(), reqs)? {
+            let page: Vec<RawKline> = serde_json::from_value(data)?;
+            for kline in page {
+                if kline.id * 1000 >= start && kline.id * 1000 <= end {
+                    klines.insert(kline.id, kline.into_kline(period));
+                }
+            }
+        }
+        Ok(klines.into_values().collect())
+    }
+
     pub fn get_balance_raw(&self, asset: &str) -> APIResult<Balance> {
         let uri = format!("/v1/account/accounts/{}/balance", self.account_id);
         let params: BTreeMap<String, String> = BTreeMap::new();
@@ -397,6 +510,190 @@ impl Huobi {
     pub fn get_history_orders_raw(&self, _symbol: &str) -> APIResult<Vec<Order>> {
         unimplemented!()
     }
+
+    // isolated margin of `symbol`, cross margin without one
+    pub fn get_loan_info(&self, symbol: Option<&str>) -> APIResult<Vec<LoanCurrency>> {
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        let ret = match symbol {
+            Some(symbol) => {
+                params.insert("symbols".into(), symbol.to_lowercase());
+                let ret = self.get_signed("/v1/margin/loan-info", params)?;
+                let resp: Response<Vec<LoanInfo>> = serde_json::from_str(&ret)?;
+                resp.data.into_iter().flat_map(|info| info.currencies).collect()
+            }
+            None => {
+                let ret = self.get_signed("/v1/cross-margin/loan-info", params)?;
+                let resp: Response<Vec<LoanCurrency>> = serde_json::from_str(&ret)?;
+                resp.data
+            }
+        };
+        Ok(ret)
+    }
+
+    // returns the id of the loan order
+    pub fn apply_loan(&self, currency: &str, amount: f64, symbol: Option<&str>) -> APIResult<String> {
+        let mut body: BTreeMap<String, String> = BTreeMap::new();
+        body.insert("currency".into(), currency.to_lowercase());
+        body.insert("amount".into(), amount.to_string());
+        let uri = match symbol {
+            Some(symbol) => {
+                body.insert("symbol".into(), symbol.to_lowercase());
+                "/v1/margin/orders"
+            }
+            None => "/v1/cross-margin/orders",
+        };
+        let ret = self.post_signed(uri, BTreeMap::new(), &body)?;
+        let resp: Response<u64> = serde_json::from_str(&ret)?;
+        Ok(resp.data.to_string())
+    }
+
+    // `amount` goes to the principal after the interest of the loan order
+    pub fn repay_loan(&self, order_id: &str, amount: &str, isolated: bool) -> APIResult<()> {
+        let uri = if isolated {
+            format!("/v1/margin/orders/{}/repay", order_id)
+        } else {
+            format!("/v1/cross-margin/orders/{}/repay", order_id)
+        };
+        let mut body: BTreeMap<String, String> = BTreeMap::new();
+        body.insert("amount".into(), amount.into());
+        let ret = self.post_signed(&uri, BTreeMap::new(), &body)?;
+        let resp: Response<Value> = serde_json::from_str(&ret)?;
+        if resp.status == "ok" {
+            Ok(())
+        } else {
+            Err(Box::new(ExError::ApiError(format!("{}: {}", resp.err_code, resp.err_msg))))
+        }
+    }
+
+    // every loan order of `currency`, newest first, `states` is a comma separated filter
+    // such as "accrual"
+    pub fn get_loan_orders(&self, currency: &str, symbol: Option<&str>, states: Option<&str>) -> APIResult<Vec<RawLoanOrder>> {
+        const PAGE: usize = 100;
+        let currency = currency.to_lowercase();
+        let mut orders: Vec<RawLoanOrder> = vec![];
+        loop {
+            let mut params: BTreeMap<String, String> = BTreeMap::new();
+            params.insert("size".into(), PAGE.to_string());
+            if let Some(last) = orders.last() {
+                params.insert("from".into(), last.id.to_string());
+                params.insert("direct".into(), "next".into());
+            }
+            let uri = match symbol {
+                Some(symbol) => {
+                    params.insert("symbol".into(), symbol.to_lowercase());
+                    if let Some(states) = states {
+                        params.insert("states".into(), states.into());
+                    }
+                    "/v1/margin/loan-orders"
+                }
+                None => {
+                    params.insert("currency".into(), currency.clone());
+                    if let Some(states) = states {
+                        params.insert("state".into(), states.into());
+                    }
+                    "/v1/cross-margin/loan-orders"
+                }
+            };
+            let ret = self.get_signed(uri, params)?;
+            let resp: Response<Vec<RawLoanOrder>> = serde_json::from_str(&ret)?;
+            let full = resp.data.len() == PAGE;
+            orders.extend(resp.data);
+            if !full {
+                break;
+            }
+        }
+        orders.retain(|order| order.currency == currency);
+        orders.sort_by_key(|order| std::cmp::Reverse(order.created_at));
+        Ok(orders)
+    }
+
+    pub fn get_currency_chains(&self, currency: &str) -> APIResult<Vec<ReferenceCurrency>> {
+        let params = format!("currency={}", currency.to_lowercase());
+        let ret = self.get("/v2/reference/currencies", &params)?;
+        let resp: ResponseV2<Vec<ReferenceCurrency>> = serde_json::from_str(&ret)?;
+        check_v2(&resp)?;
+        Ok(resp.data)
+    }
+
+    // one address per chain
+    pub fn get_deposit_addresses(&self, currency: &str) -> APIResult<Vec<RawDepositAddress>> {
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        params.insert("currency".into(), currency.to_lowercase());
+        let ret = self.get_signed("/v2/account/deposit/address", params)?;
+        let resp: ResponseV2<Vec<RawDepositAddress>> = serde_json::from_str(&ret)?;
+        check_v2(&resp)?;
+        Ok(resp.data)
+    }
+
+    // the latest 500 deposits or withdrawals, `ty` is deposit or withdraw
+    pub fn get_deposit_withdraw(&self, currency: &str, ty: &str) -> APIResult<Vec<RawDepositWithdraw>> {
+        self.query_deposit_withdraw(currency, ty, None)
+    }
+
+    // a page of up to DEPOSIT_WITHDRAW_PAGE records, going back from record id `from` when set
+    fn query_deposit_withdraw(
+        &self,
+        currency: &str,
+        ty: &str,
+        from: Option<u64>,
+    ) -> APIResult<Vec<RawDepositWithdraw>> {
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        params.insert("currency".into(), currency.to_lowercase());
+        params.insert("type".into(), ty.into());
+        params.insert("size".into(), DEPOSIT_WITHDRAW_PAGE.to_string());
+        params.insert("direct".into(), "next".into());
+        if let Some(from) = from {
+            params.insert("from".into(), from.to_string());
+        }
+        let ret = self.get_signed("/v1/query/deposit-withdraw", params)?;
+        let resp: Response<Vec<RawDepositWithdraw>> = serde_json::from_str(&ret)?;
+        let mut records = resp.data;
+        records.sort_by_key(|record| std::cmp::Reverse(record.created_at));
+        Ok(records)
+    }
+
+    // no whitelist check, only reachable through Wallet::withdraw
+    fn withdraw_raw(
+        &self,
+        currency: &str,
+        chain: Option<&str>,
+        address: &str,
+        tag: Option<&str>,
+        amount: f64,
+        fee: f64,
+    ) -> APIResult<String> {
+        let mut body: BTreeMap<String, String> = BTreeMap::new();
+        body.insert("currency".into(), currency.to_lowercase());
+        body.insert("address".into(), address.into());
+        body.insert("amount".into(), amount.to_string());
+        if let Some(chain) = chain {
+            body.insert("chain".into(), chain.into());
+        }
+        if let Some(tag) = tag {
+            body.insert("addr-tag".into(), tag.into());
+        }
+        body.insert("fee".into(), fee.to_string());
+        let ret = self.post_signed("/v1/dw/withdraw/api/create", BTreeMap::new(), &body)?;
+        let resp: Response<u64> = serde_json::from_str(&ret)?;
+        Ok(resp.data.to_string())
+    }
+
+    pub fn get_margin_balance(&self, symbol: Option<&str>) -> APIResult<Vec<MarginBalanceInfo>> {
+        let mut params: BTreeMap<String, String> = BTreeMap::new();
+        match symbol {
+            Some(symbol) => {
+                params.insert("symbol".into(), symbol.to_lowercase());
+                let ret = self.get_signed("/v1/margin/accounts/balance", params)?;
+                let resp: Response<Vec<MarginBalanceInfo>> = serde_json::from_str(&ret)?;
+                Ok(resp.data)
+            }
+            None => {
+                let ret = self.get_signed("/v1/cross-margin/accounts/balance", params)?;
+                let resp: Response<MarginBalanceInfo> = serde_json::from_str(&ret)?;
+                Ok(vec![resp.data])
+            }
+        }
+    }
 }
 
 impl SpotRest for Huobi {
@@ -415,10 +712,56 @@ impl SpotRest for Huobi {
         Ok(raw.into())
     }
 
+    fn get_all_book_tickers(&self) -> APIResult<HashMap<String, Ticker>> {
+        let (ts, tickers) = self.get_tickers_raw()?;
+        Ok(tickers
+            .iter()
+            .map(|ticker| {
+                let mut book: Ticker = ticker.into();
+                book.timestamp = ts;
+                (ticker.symbol.clone(), book)
+            })
+            .collect())
+    }
+
+    fn get_all_prices(&self) -> APIResult<HashMap<String, f64>> {
+        let (_, tickers) = self.get_tickers_raw()?;
+        Ok(tickers.into_iter().map(|ticker| (ticker.symbol, ticker.close)).collect())
+    }
+
+    fn get_24h_stats(&self, symbol: &str) -> APIResult<DayStats> {
+        self.get_24h_stats_raw(symbol)
+    }
+
+    fn get_all_24h_stats(&self) -> APIResult<Vec<DayStats>> {
+        let (ts, tickers) = self.get_tickers_raw()?;
+        Ok(tickers
+            .into_iter()
+            .map(|ticker| DayStats {
+                timestamp: ts,
+                ..ticker.into()
+            })
+            .collect())
+    }
+
+    fn get_avg_price(&self, _symbol: &str) -> APIResult<f64> {
+        Err(Box::new(ExError::ApiError("huobi has no average price endpoint".into())))
+    }
+
+    fn get_recent_trades(&self, symbol: &str, limit: u16) -> APIResult<Vec<Trade>> {
+        let mut trades = self.get_trades_raw(symbol, limit)?;
+        trades.sort_by_key(|trade| trade.id);
+        Ok(trades)
+    }
+
     fn get_kline(&self, symbol: &str, period: &str, limit: u16) -> APIResult<Vec<Kline>> {
         self.get_kline_raw(symbol, period, limit)
     }
 
+    fn get_kline_range(&self, symbol: &str, interval: KlineInterval, start: u64, end: u64) -> APIResult<Vec<Kline>> {
+        self.get_kline_range_raw(symbol, interval, start, end)
+    }
+
     fn get_balance(&self, asset: &str) -> APIResult<Balance> {
         self.get_balance_raw(asset)
     }
@@ -503,6 +846,187 @@ impl SpotRest for Huobi {
     }
 }
 
+impl Wallet for Huobi {
+    fn get_networks(&self, asset: &str) -> APIResult<Vec<WalletNetwork>> {
+        let currencies = self.get_currency_chains(asset)?;
+        Ok(currencies
+            .into_iter()
+            .flat_map(|currency| {
+                let name = currency.currency;
+                currency
+                    .chains
+                    .into_iter()
+                    .map(move |chain| WalletNetwork::from((name.as_str(), chain)))
+                    .collect::<Vec<_>>()
+            })
+            .collect())
+    }
+
+    fn get_deposit_address(&self, asset: &str, network: Option<&str>) -> APIResult<DepositAddress> {
+        let currency = asset.to_lowercase();
+        let mut addresses = self.get_deposit_addresses(&currency)?;
+        let chain = network.unwrap_or(&currency);
+        let index = match addresses.iter().position(|address| address.chain == chain) {
+            Some(index) => Some(index),
+            // any chain will do without one named after the currency
+            None if network.is_none() && !addresses.is_empty() => Some(0),
+            None => None,
+        };
+        match index {
+            Some(index) => Ok(addresses.swap_remove(index).into()),
+            None => Err(Box::new(ExError::ApiError(format!("no deposit address for {} on {}", asset, chain)))),
+        }
+    }
+
+    fn get_deposit_history(&self, asset: &str) -> APIResult<Vec<WalletTransfer>> {
+        let raw = self.get_deposit_withdraw(asset, "deposit")?;
+        Ok(raw.into_iter().map(|deposit| deposit.into()).collect())
+    }
+
+    fn get_withdraw_history(&self, asset: &str) -> APIResult<Vec<WalletTransfer>> {
+        let raw = self.get_deposit_withdraw(asset, "withdraw")?;
+        Ok(raw.into_iter().map(|withdraw| withdraw.into()).collect())
+    }
+
+    // the fee is looked up for the chain
+    fn withdraw(
+        &self,
+        asset: &str,
+        network: Option<&str>,
+        address: &str,
+        tag: Option<&str>,
+        amount: f64,
+    ) -> APIResult<String> {
+        self.withdraw_whitelist.check(asset, network, address, tag)?;
+        let currencies = self.get_currency_chains(asset)?;
+        let chain = currencies
+            .iter()
+            .flat_map(|currency| {
+                let name = network.unwrap_or(&currency.currency);
+                currency.chains.iter().filter(move |chain| chain.chain == name)
+            })
+            .next()
+            .ok_or_else(|| ExError::ApiError(format!("unknown chain {} for {}", network.unwrap_or(asset), asset)))?;
+        // huobi rejects withdrawals without a fee on chains that charge one
+        let fee = chain
+            .withdraw_fee()
+            .ok_or_else(|| ExError::ApiError(format!("no withdraw fee for {} on {}", asset, chain.chain)))?;
+        self.withdraw_raw(asset, network, address, tag, amount, fee)
+    }
+
+    fn cancel_withdraw(&self, id: &str) -> APIResult<bool> {
+        let uri = format!("/v1/dw/withdraw-virtual/{}/cancel", id);
+        let ret = self.post_signed(&uri, BTreeMap::new(), &BTreeMap::new())?;
+        let resp: Response<u64> = serde_json::from_str(&ret)?;
+        Ok(resp.status == "ok")
+    }
+
+    // pages back through the history until it passes the id
+    fn get_withdraw(&self, asset: &str, id: &str) -> APIResult<WalletTransfer> {
+        let not_found = || Box::new(ExError::ApiError(format!("withdraw {} not found", id)));
+        let id: u64 = id.parse().map_err(|_| not_found())?;
+        let mut from = None;
+        loop {
+            let page = self.query_deposit_withdraw(asset, "withdraw", from)?;
+            let oldest = page.iter().map(|withdraw| withdraw.id).min();
+            let full = page.len() == DEPOSIT_WITHDRAW_PAGE;
+            if let Some(withdraw) = page.into_iter().find(|withdraw| withdraw.id == id) {
+                return Ok(withdraw.into());
+            }
+            match oldest {
+                Some(oldest) if full && oldest > id => from = Some(oldest - 1),
+                _ => return Err(not_found()),
+            }
+        }
+    }
+}
+
+fn check_v2<T>(resp: &ResponseV2<T>) -> APIResult<()> {
+    if resp.code == 200 {
+        Ok(())
+    } else {
+        Err(Box::new(ExError::ApiError(format!("{}: {}", resp.code, resp.message))))
+    }
+}
+
+// huobi repays by loan order, so a repay settles the oldest open loans of the asset first
+impl MarginRest for Huobi {
+    fn margin_borrow(&self, asset: &str, amount: f64, isolated_symbol: Option<&str>) -> APIResult<String> {
+        self.apply_loan(asset, amount, isolated_symbol)
+    }
+
+    // repays the oldest loans first and returns the loan orders repaid, comma separated.
+    // fails before repaying anything when `amount` is more than is owed, and with the loans
+    // already repaid and the amount left when a repayment fails halfway
+    fn margin_repay(&self, asset: &str, amount: f64, isolated_symbol: Option<&str>) -> APIResult<String> {
+        let mut left = to_loan_units(&amount.to_string())
+            .ok_or_else(|| ExError::ApiError(format!("invalid repay amount {}", amount)))?;
+        let mut loans = self.get_loan_orders(asset, isolated_symbol, Some("accrual"))?;
+        loans.reverse();
+        let owed = loans.iter().map(|loan| loan.owed()).sum::<u128>();
+        if owed == 0 {
+            return Err(Box::new(ExError::ApiError(format!("no open loan of {}", asset))));
+        }
+        if left > owed {
+            let msg = format!("repay {} {} exceeds the {} owed", amount, asset, from_loan_units(owed));
+            return Err(Box::new(ExError::ApiError(msg)));
+        }
+        let mut repaid = vec![];
+        for loan in loans {
+            if left == 0 {
+                break;
+            }
+            let pay = loan.owed().min(left);
+            if pay == 0 {
+                continue;
+            }
+            let id = loan.id.to_string();
+            if let Err(err) = self.repay_loan(&id, &from_loan_units(pay), isolated_symbol.is_some()) {
+                let msg = format!(
+                    "repay loan {} failed after repaying [{}], {} {} left: {}",
+                    id,
+                    repaid.join(","),
+                    from_loan_units(left),
+                    asset,
+                    err
+                );
+                return Err(Box::new(ExError::ApiError(msg)));
+            }
+            left -= pay;
+            repaid.push(id);
+        }
+        Ok(repaid.join(","))
+    }
+
+    fn max_borrowable(&self, asset: &str, isolated_symbol: Option<&str>) -> APIResult<f64> {
+        let currency = asset.to_lowercase();
+        let info = self.get_loan_info(isolated_symbol)?;
+        match info.iter().find(|info| info.currency == currency) {
+            Some(info) => Ok(info.loanable_amt.parse::<f64>().unwrap_or(0.0)),
+            None => Err(Box::new(ExError::ApiError(format!("{} can not be borrowed", asset)))),
+        }
+    }
+
+    fn get_margin_account(&self, isolated_symbol: Option<&str>) -> APIResult<MarginAccount> {
+        match self.get_margin_balance(isolated_symbol)?.into_iter().next() {
+            Some(balance) => Ok(balance.into()),
+            None => Err(Box::new(ExError::ApiError("margin account not found".into()))),
+        }
+    }
+
+    fn get_loan_history(&self, asset: &str, isolated_symbol: Option<&str>) -> APIResult<Vec<MarginLoan>> {
+        let orders = self.get_loan_orders(asset, isolated_symbol, None)?;
+        Ok(orders.iter().map(|order| order.into()).collect())
+    }
+
+    fn get_interest_history(&self, asset: &str, isolated_symbol: Option<&str>) -> APIResult<Vec<MarginInterest>> {
+        let orders = self.get_loan_orders(asset, isolated_symbol, None)?;
+        let mut interests = orders.iter().map(MarginInterest::from).collect::<Vec<_>>();
+        interests.sort_by_key(|interest| std::cmp::Reverse(interest.timestamp));
+        Ok(interests)
+    }
+}
+
 #[cfg(test)]
 mod test {
     #![allow(dead_code)]
@@ -1,26 +1,86 @@
 use crate::errors::*;
 use crate::huobi::types::*;
 use crate::models::*;
+use crate::recorder::Recorder;
+use crate::replay::*;
+use crate::websocket::*;
+use futures_channel::mpsc::UnboundedReceiver;
 use flate2::read::GzDecoder;
+use log::{debug, warn};
+use serde_json::{json, Value};
+use std::collections::HashMap;
 use std::io::prelude::*;
-use ws::{Handler, Handshake, Message, Result, Sender};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
 
 #[derive(Debug)]
 pub enum WsEvent {
-    OrderbookEvent(Orderbook),
-    KlineEvent(Kline),
-    TickerEvent(Ticker),
-    TradeEvent(Vec<Trade>),
+    Connected,
+    Disconnected(String),
+    Resubscribed(usize),
+    // symbol first, as in the channel
+    OrderbookEvent(String, Orderbook),
+    // symbol, period
+    KlineEvent(String, String, Kline),
+    // best bid and ask
+    TickerEvent(String, Ticker),
+    TradeEvent(String, Vec<Trade>),
+    // market.$symbol.detail, 24h stats without the book
+    DetailEvent(String, DayStats),
+    // market.$symbol.ticker
+    DayTickerEvent(String, DayStats),
+    // market.tickers, every market
+    AllTickersEvent(Vec<DayStats>),
     ResponseEvent(ResponseEvent),
     PingEvent(Ping),
+    // channel we have no typed struct for, with its tick
+    RawEvent(String, Value),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ResponseEvent {
-    id: String,
-    status: String,
-    subbed: String,
-    ts: i64,
+    #[serde(default)]
+    pub id: String,
+    pub status: String,
+    #[serde(default)]
+    pub subbed: String,
+    #[serde(default)]
+    pub unsubbed: String,
+    #[serde(default, rename = "err-code")]
+    pub err_code: String,
+    #[serde(default, rename = "err-msg")]
+    pub err_msg: String,
+    // channel a req was answered for
+    #[serde(default)]
+    pub rep: String,
+    // reply to a req, handed to the ack of the request and left null here
+    #[serde(default)]
+    pub data: Value,
+    pub ts: i64,
+}
+
+// price aggregation of market.$symbol.depth, step0 is unaggregated
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DepthStep {
+    Step0,
+    Step1,
+    Step2,
+    Step3,
+    Step4,
+    Step5,
+}
+
+// market.btcusdt.depth.step0
+pub fn depth_channel(symbol: &str, step: DepthStep) -> String {
+    let step = match step {
+        DepthStep::Step0 => 0,
+        DepthStep::Step1 => 1,
+        DepthStep::Step2 => 2,
+        DepthStep::Step3 => 3,
+        DepthStep::Step4 => 4,
+        DepthStep::Step5 => 5,
+    };
+    format!("market.{}.depth.step{}", symbol.to_lowercase(), step)
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -30,137 +90,647 @@ pub struct Ping {
 
 pub struct HuobiWs<'a> {
     host: String,
-    subs: Vec<String>,
-    out: Option<Sender>,
+    state: Arc<Mutex<SubState>>,
+    out: Option<Outbox>,
+    config: ReconnectConfig,
+    ws_config: WsConfig,
+    resubscribe: bool,
+    recorder: Option<Recorder>,
+    // what sub_orderbook subscribes to
+    depth_step: DepthStep,
+    closed_klines_only: bool,
+    // latest update of the open candle per symbol and period
+    open_klines: HashMap<(String, String), Kline>,
 
-    handler: Box<dyn FnMut(WsEvent) -> Result<()> + 'a>,
+    handler: Box<dyn FnMut(WsEvent) -> APIResult<()> + 'a>,
 }
 
 impl<'a> HuobiWs<'a> {
     pub fn new(host: &str) -> Self {
         HuobiWs {
             host: host.into(),
-            subs: vec![],
+            state: Arc::new(Mutex::new(SubState::new())),
             out: None,
-            handler: Box::new(|event| {
-                println!("event: {:?}", event);
-                Ok(())
-            }),
+            config: ReconnectConfig::huobi(),
+            ws_config: WsConfig::default(),
+            resubscribe: false,
+            recorder: None,
+            depth_step: DepthStep::Step0,
+            closed_klines_only: false,
+            open_klines: HashMap::new(),
+            handler: Box::new(|_| Ok(())),
+        }
+    }
+
+    pub fn set_reconnect_config(&mut self, config: ReconnectConfig) {
+        self.config = config;
+    }
+
+    // tls backend, proxy and ping interval
+    pub fn set_ws_config(&mut self, config: WsConfig) {
+        self.ws_config = config;
+    }
+
+    // every received frame is written to `recorder` once gunzipped, before it is parsed
+    pub fn set_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    pub fn set_depth_step(&mut self, step: DepthStep) {
+        self.depth_step = step;
+    }
+
+    // huobi has no closed flag, with this set a candle is delivered once, marked closed, when
+    // the first update of the next one arrives. A candle open across a reconnect is dropped
+    // since updates to it may have been missed
+    pub fn set_closed_klines_only(&mut self, closed_only: bool) {
+        self.closed_klines_only = closed_only;
+    }
+
+    // the handle is Send, use it from other threads while `connect` blocks
+    pub fn handle(&self) -> HuobiWsHandle {
+        HuobiWsHandle {
+            state: self.state.clone(),
         }
     }
 
-    pub fn connect<Callback: Clone>(&mut self, handler: Callback)
+    // blocks until the handle is shut down, see run_supervised
+    pub fn connect<Callback>(&mut self, handler: Callback)
     where
-        Callback: FnMut(WsEvent) -> Result<()> + 'a,
+        Callback: FnMut(WsEvent) -> APIResult<()> + 'a,
     {
-        ws::connect(self.host.clone(), |out| HuobiWs {
-            host: self.host.clone(),
-            subs: self.subs.clone(),
-            out: Some(out),
-            handler: Box::new(handler.clone()),
-        })
-        .unwrap();
+        self.handler = Box::new(handler);
+        self.resubscribe = false;
+        let host = self.host.clone();
+        let (ws_config, config) = (self.ws_config.clone(), self.config.clone());
+        run_supervised(self, &host, ws_config, &config);
     }
 
-    pub fn sub_kline(&mut self, symbol: &str, period: &str) {
-        self.subs.push(format!(
-            "{{\"sub\": \"market.{}.kline.{}\", \"id\": \"id1\"}}",
-            symbol.to_string().to_lowercase(),
-            period
-        ));
+    // runs the connection on its own thread and delivers events over a channel,
+    // the channel closes once the handle is shut down
+    pub fn connect_channel(&self) -> mpsc::Receiver<WsEvent> {
+        spawn_channel(&self.state, |handler| self.spawn(handler))
     }
 
-    pub fn sub_orderbook(&mut self, symbol: &str) {
-        self.subs.push(format!(
-            "{{\"sub\": \"market.{}.depth.{}\", \"id\": \"id1\"}}",
-            symbol.to_string().to_lowercase(),
-            "step0"
-        ));
+    // same as `connect_channel`, as a futures Stream for async consumers
+    pub fn connect_stream(&self) -> UnboundedReceiver<WsEvent> {
+        spawn_stream(&self.state, |handler| self.spawn(handler))
     }
 
-    pub fn sub_trade(&mut self, symbol: &str) {
-        self.subs.push(format!(
-            "{{\"sub\": \"market.{}.trade.detail\", \"id\": \"id1\"}}",
-            symbol.to_string().to_lowercase()
-        ));
+    fn spawn<Callback>(&self, handler: Callback) -> Option<thread::JoinHandle<()>>
+    where
+        Callback: FnMut(WsEvent) -> APIResult<()> + Send + 'static,
+    {
+        let host = self.host.clone();
+        let state = self.state.clone();
+        let config = self.config.clone();
+        let ws_config = self.ws_config.clone();
+        let recorder = self.recorder.clone();
+        let depth_step = self.depth_step;
+        let closed_klines_only = self.closed_klines_only;
+        thread::Builder::new()
+            .name("huobi-ws".into())
+            .spawn(move || {
+                let mut ws = HuobiWs::new(&host);
+                ws.state = state;
+                ws.config = config;
+                ws.ws_config = ws_config;
+                ws.recorder = recorder;
+                ws.depth_step = depth_step;
+                ws.closed_klines_only = closed_klines_only;
+                ws.connect(handler);
+            })
+            .ok()
+    }
+
+    pub fn sub_kline(&mut self, symbol: &str, period: &str) -> Ack<()> {
+        let channel = format!("market.{}.kline.{}", symbol.to_lowercase(), period);
+        self.handle().subscribe(&channel)
     }
 
-    pub fn sub_ticker(&mut self, symbol: &str) {
-        self.subs.push(format!(
-            "{{\"sub\": \"market.{}.bbo\", \"id\": \"id1\"}}",
-            symbol.to_string().to_lowercase()
-        ));
+    pub fn sub_orderbook(&mut self, symbol: &str) -> Ack<()> {
+        let channel = depth_channel(symbol, self.depth_step);
+        self.handle().subscribe(&channel)
+    }
+
+    pub fn sub_trade(&mut self, symbol: &str) -> Ack<()> {
+        let channel = format!("market.{}.trade.detail", symbol.to_lowercase());
+        self.handle().subscribe(&channel)
+    }
+
+    pub fn sub_ticker(&mut self, symbol: &str) -> Ack<()> {
+        let channel = format!("market.{}.bbo", symbol.to_lowercase());
+        self.handle().subscribe(&channel)
+    }
+
+    pub fn sub_detail(&mut self, symbol: &str) -> Ack<()> {
+        let channel = format!("market.{}.detail", symbol.to_lowercase());
+        self.handle().subscribe(&channel)
+    }
+
+    pub fn sub_day_ticker(&mut self, symbol: &str) -> Ack<()> {
+        let channel = format!("market.{}.ticker", symbol.to_lowercase());
+        self.handle().subscribe(&channel)
+    }
+
+    pub fn sub_all_tickers(&mut self) -> Ack<()> {
+        self.handle().subscribe(ALL_TICKERS)
+    }
+
+    // feeds recorded frames through the same parsing and handler as a live connection,
+    // pings are answered only while connected
+    pub fn replay<Callback>(&mut self, frames: &[RecordedFrame], speed: ReplaySpeed, handler: Callback)
+    where
+        Callback: FnMut(WsEvent) -> APIResult<()> + 'a,
+    {
+        self.handler = Box::new(handler);
+        replay(frames, speed, |frame| self.handle_frame(&frame.frame));
+    }
+
+    fn handle_frame(&mut self, frame: &str) {
+        match self.deseralize(frame) {
+            Ok(WsEvent::KlineEvent(symbol, period, kline)) if self.closed_klines_only => {
+                let key = (symbol, period);
+                if let Some(kline) = self.close_kline(key.clone(), kline) {
+                    let _ = (self.handler)(WsEvent::KlineEvent(key.0, key.1, kline));
+                }
+            }
+            Ok(mut event) => {
+                if let WsEvent::ResponseEvent(resp) = &mut event {
+                    let ret = if resp.status == "ok" {
+                        Ok(resp.data.take())
+                    } else {
+                        Err(ExError::ApiError(format!("{}: {}", resp.err_code, resp.err_msg)))
+                    };
+                    self.state.lock().unwrap().resolve(&resp.id, ret);
+                }
+                let _ = (self.handler)(event);
+            }
+            Err(err) => {
+                warn!("deseralize msg error: {:?}", err);
+            }
+        }
+    }
+
+    // keeps the latest update of each symbol and period's candle and hands out the previous candle
+    // once a later one starts
+    fn close_kline(&mut self, key: (String, String), kline: Kline) -> Option<Kline> {
+        if let Some(open) = self.open_klines.get(&key) {
+            if open.timestamp > kline.timestamp {
+                return None;
+            }
+        }
+        let timestamp = kline.timestamp;
+        let mut prev = self.open_klines.insert(key, kline)?;
+        if prev.timestamp == timestamp {
+            return None;
+        }
+        prev.closed = true;
+        Some(prev)
     }
 
     pub fn deseralize(&self, s: &str) -> APIResult<WsEvent> {
-        if s.find("ping") != None {
-            let ping: Ping = serde_json::from_str(s)?;
+        let header: MessageHeader = serde_json::from_str(s)?;
+        if let Some(ping) = header.ping {
             match &self.out {
                 Some(out) => {
-                    let msg = format!("{{\"pong\":{}}}", ping.ping);
-                    let _ = out.send(msg.as_str());
-                }
-                None => {
-                    println!("self.out is None");
+                    let msg = format!("{{\"pong\":{}}}", ping);
+                    out.send(msg);
                 }
+                // replayed frames have no connection to answer on
+                None => debug!("ping {} without a connection", ping),
+            }
+            return Ok(WsEvent::PingEvent(Ping { ping }));
+        }
+        let ch = match header.ch {
+            Some(ch) => ch,
+            None if header.status.is_some() => {
+                let resp: ResponseEvent = serde_json::from_str(s)?;
+                return Ok(WsEvent::ResponseEvent(resp));
             }
-            return Ok(WsEvent::PingEvent(ping));
-        }
-        if s.find("tick") == None {
-            let resp: ResponseEvent = serde_json::from_str(s)?;
-            return Ok(WsEvent::ResponseEvent(resp));
-        }
-        //let val: Value = serde_json::from_str(s)?;
-        if s.find("kline") != None {
-            let resp: Response<RawKline> = serde_json::from_str(&s)?;
-            Ok(WsEvent::KlineEvent(resp.tick.into()))
-        } else if s.find("depth") != None {
-            let resp: Response<RawOrderbook> = serde_json::from_str(&s)?;
-            Ok(WsEvent::OrderbookEvent(resp.tick.into()))
-        } else if s.find("bbo") != None {
-            let resp: Response<RawTicker> = serde_json::from_str(&s)?;
-            Ok(WsEvent::TickerEvent(resp.tick.into()))
-        } else if s.find("trade.detail") != None {
-            let resp: Response<Response<Vec<RawTrade>>> = serde_json::from_str(&s)?;
-            let trades = resp
-                .tick
+            None => {
+                let val: Value = serde_json::from_str(s)?;
+                return Ok(WsEvent::RawEvent(String::new(), val));
+            }
+        };
+        if ch == ALL_TICKERS {
+            let resp: DataMessage<Vec<MarketTicker>> = serde_json::from_str(s)?;
+            let ts = resp.ts;
+            let stats = resp
                 .data
                 .into_iter()
-                .map(|raw_trade| raw_trade.into())
+                .map(|ticker| DayStats {
+                    timestamp: ts,
+                    ..ticker.into()
+                })
+                .collect();
+            return Ok(WsEvent::AllTickersEvent(stats));
+        }
+        let symbol = ch.split('.').nth(1).unwrap_or_default().to_string();
+        match channel(&ch) {
+            Some(Channel::Kline) => {
+                let resp: TickMessage<RawKline> = serde_json::from_str(s)?;
+                let period = ch.split('.').nth(3).unwrap_or_default().to_string();
+                let kline = resp.tick.into_kline(&period);
+                Ok(WsEvent::KlineEvent(symbol, period, kline))
+            }
+            Some(Channel::Depth) => {
+                let resp: TickMessage<RawOrderbook> = serde_json::from_str(s)?;
+                Ok(WsEvent::OrderbookEvent(symbol, resp.tick.into()))
+            }
+            Some(Channel::Bbo) => {
+                let resp: TickMessage<RawBbo> = serde_json::from_str(s)?;
+                Ok(WsEvent::TickerEvent(symbol, resp.tick.into()))
+            }
+            Some(Channel::Trade) => {
+                let resp: TickMessage<TradeTick> = serde_json::from_str(s)?;
+                let trades = resp
+                    .tick
+                    .data
+                    .into_iter()
+                    .map(|raw_trade| Trade {
+                        symbol: symbol.clone(),
+                        ..raw_trade.into()
+                    })
+                    .collect::<Vec<Trade>>();
+                Ok(WsEvent::TradeEvent(symbol, trades))
+            }
+            Some(Channel::Detail) => {
+                let resp: TickMessage<RawDetail> = serde_json::from_str(s)?;
+                let stats = DayStats {
+                    symbol: symbol.clone(),
+                    timestamp: resp.ts,
+                    ..resp.tick.into()
+                };
+                Ok(WsEvent::DetailEvent(symbol, stats))
+            }
+            Some(Channel::Ticker) => {
+                let resp: TickMessage<MarketTicker> = serde_json::from_str(s)?;
+                let stats = DayStats {
+                    symbol: symbol.clone(),
+                    timestamp: resp.ts,
+                    ..resp.tick.into()
+                };
+                Ok(WsEvent::DayTickerEvent(symbol, stats))
+            }
+            None => {
+                let resp: TickMessage<Value> = serde_json::from_str(s)?;
+                Ok(WsEvent::RawEvent(ch, resp.tick))
+            }
+        }
+    }
+}
+
+const ALL_TICKERS: &str = "market.tickers";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Channel {
+    Kline,
+    Depth,
+    Bbo,
+    Trade,
+    Detail,
+    Ticker,
+}
+
+lazy_static! {
+    // keyed by the channel segment after the symbol, market.$symbol.$channel...
+    static ref CHANNELS: HashMap<&'static str, Channel> = {
+        let mut map = HashMap::new();
+        map.insert("kline", Channel::Kline);
+        map.insert("depth", Channel::Depth);
+        map.insert("bbo", Channel::Bbo);
+        map.insert("trade", Channel::Trade);
+        map.insert("detail", Channel::Detail);
+        map.insert("ticker", Channel::Ticker);
+        map
+    };
+}
+
+fn channel(ch: &str) -> Option<Channel> {
+    let name = ch.split('.').nth(2)?;
+    CHANNELS.get(name).copied()
+}
+
+// frames are recorded by channel, pings and responses on their own
+fn record_stream(frame: &str) -> String {
+    match serde_json::from_str::<MessageHeader>(frame) {
+        Ok(MessageHeader { ch: Some(ch), .. }) => ch,
+        Ok(MessageHeader { ping: Some(_), .. }) => "ping".into(),
+        Ok(MessageHeader { status: Some(_), .. }) => "response".into(),
+        _ => "unknown".into(),
+    }
+}
+
+// just enough of a message to route it
+#[derive(Debug, Deserialize)]
+struct MessageHeader {
+    ch: Option<String>,
+    ping: Option<i64>,
+    status: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TickMessage<T> {
+    pub ch: String,
+    pub ts: u64,
+    pub tick: T,
+}
+
+// channels covering several markets push `data` instead of `tick`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DataMessage<T> {
+    pub ch: String,
+    pub ts: u64,
+    pub data: T,
+}
+
+// also an entry of /market/history/trade, the trades of one taker order
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct TradeTick {
+    pub id: u64,
+    pub ts: u64,
+    pub data: Vec<RawTrade>,
+}
+
+#[derive(Clone)]
+pub struct HuobiWsHandle {
+    state: Arc<Mutex<SubState>>,
+}
+
+impl HuobiWsHandle {
+    pub fn subscribe(&self, channel: &str) -> Ack<()> {
+        let mut state = self.state.lock().unwrap();
+        state.add_subs(&[channel.to_string()]);
+        Self::send(&mut state, "sub", channel, json!({}), true, |_| Ok(()))
+    }
+
+    pub fn unsubscribe(&self, channel: &str) -> Ack<()> {
+        let mut state = self.state.lock().unwrap();
+        state.remove_subs(&[channel.to_string()]);
+        Self::send(&mut state, "unsub", channel, json!({}), true, |_| Ok(()))
+    }
+
+    // a one off pull of `channel` over the live connection, queued until it is up.
+    // `params` (e.g. {"from": .., "to": ..}) go into the req, the ack resolves with the
+    // data of the reply
+    pub fn request(&self, channel: &str, params: Value) -> Ack<Value> {
+        let mut state = self.state.lock().unwrap();
+        Self::send(&mut state, "req", channel, params, false, Ok)
+    }
+
+    // at most 300 candles opening in [from, to], ms since the epoch
+    pub fn request_klines(&self, symbol: &str, period: &str, from: u64, to: u64) -> Ack<Vec<Kline>> {
+        let channel = format!("market.{}.kline.{}", symbol.to_lowercase(), period);
+        let period = period.to_string();
+        let mut state = self.state.lock().unwrap();
+        let params = json!({"from": from / 1000, "to": to / 1000});
+        Self::send(&mut state, "req", &channel, params, false, move |val| {
+            let klines: Vec<RawKline> = from_data(val)?;
+            let mut klines = klines
+                .into_iter()
+                .map(|kline| kline.into_kline(&period))
+                .collect::<Vec<Kline>>();
+            klines.sort_by_key(|kline| kline.timestamp);
+            Ok(klines)
+        })
+    }
+
+    pub fn request_depth(&self, symbol: &str, step: DepthStep) -> Ack<Orderbook> {
+        let channel = depth_channel(symbol, step);
+        let mut state = self.state.lock().unwrap();
+        Self::send(&mut state, "req", &channel, json!({}), false, |val| {
+            let orderbook: RawOrderbook = from_data(val)?;
+            Ok(orderbook.into())
+        })
+    }
+
+    // the latest trades, oldest first
+    pub fn request_trades(&self, symbol: &str) -> Ack<Vec<Trade>> {
+        let symbol = symbol.to_lowercase();
+        let channel = format!("market.{}.trade.detail", symbol);
+        let mut state = self.state.lock().unwrap();
+        Self::send(&mut state, "req", &channel, json!({}), false, move |val| {
+            let trades: Vec<RawTrade> = from_data(val)?;
+            let mut trades = trades
+                .into_iter()
+                .map(|trade| Trade {
+                    symbol: symbol.clone(),
+                    ..trade.into()
+                })
                 .collect::<Vec<Trade>>();
-            Ok(WsEvent::TradeEvent(trades))
+            trades.sort_by_key(|trade| trade.id);
+            Ok(trades)
+        })
+    }
+
+    // huobi has no server side listing, these are the channels replayed on reconnect
+    pub fn subscriptions(&self) -> Vec<String> {
+        self.state.lock().unwrap().subs.clone()
+    }
+
+    // closes the connection and stops reconnecting, in-flight requests fail. waits for the
+    // threads of connect_channel and connect_stream to exit
+    pub fn shutdown(&self) {
+        shutdown_and_join(&self.state);
+    }
+
+    // sub and unsub are deferred to the resubscription while disconnected, req is queued
+    fn send<T, F>(
+        state: &mut SubState,
+        method: &str,
+        channel: &str,
+        params: Value,
+        deferrable: bool,
+        convert: F,
+    ) -> Ack<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(Value) -> Result<T, ExError> + Send + 'static,
+    {
+        let id = state.next_id().to_string();
+        let mut msg = json!({ method: channel, "id": id });
+        if let Value::Object(params) = params {
+            for (key, val) in params {
+                msg[key.as_str()] = val;
+            }
+        }
+        let ack = state.expect(id.clone(), convert);
+        if deferrable {
+            state.send_or_defer(id, vec![channel.to_string()], msg.to_string());
         } else {
-            Err(Box::new(ExError::ApiError("msg channel not found".into())))
+            state.send_or_queue(msg.to_string());
         }
+        ack
+    }
+}
+
+fn from_data<T: serde::de::DeserializeOwned>(val: Value) -> Result<T, ExError> {
+    serde_json::from_value(val).map_err(|err| ExError::ApiError(err.to_string()))
+}
+
+impl<'a> Supervised for HuobiWs<'a> {
+    fn state(&self) -> Arc<Mutex<SubState>> {
+        self.state.clone()
+    }
+
+    fn on_disconnect(&mut self, reason: String) {
+        self.out = None;
+        self.open_klines.clear();
+        let _ = (self.handler)(WsEvent::Disconnected(reason));
+        self.resubscribe = true;
     }
 }
 
 impl<'a> Handler for HuobiWs<'a> {
-    fn on_open(&mut self, _shake: Handshake) -> Result<()> {
-        match &self.out {
-            Some(out) => self.subs.iter().for_each(|s| {
-                let _ = out.send(s.as_str());
-            }),
-            None => {
-                println!("self.out is None");
+    fn on_open(&mut self, out: Outbox) -> APIResult<()> {
+        let _ = (self.handler)(WsEvent::Connected);
+        let resubscribed = {
+            let mut state = self.state.lock().unwrap();
+            if state.shutdown {
+                out.close();
+                return Ok(());
+            }
+            state.out = Some(out.clone());
+            let deferred = state.take_deferred();
+            for channel in state.subs.clone() {
+                let id = state.next_id().to_string();
+                deferred
+                    .iter()
+                    .filter(|(_, channels)| channels.contains(&channel))
+                    .for_each(|(from, _)| state.merge_pending(from, &id));
+                out.send(json!({"sub": channel, "id": id}).to_string());
+            }
+            // unsubscribed before the connection came up, nothing left to ack
+            let unsubbed = deferred
+                .iter()
+                .filter(|(_, channels)| !channels.iter().any(|c| state.subs.contains(c)))
+                .map(|(id, _)| id.clone())
+                .collect::<Vec<String>>();
+            for id in unsubbed {
+                state.resolve(&id, Ok(Value::Null));
             }
+            for msg in state.take_queued() {
+                out.send(msg);
+            }
+            state.subs.len()
+        };
+        self.out = Some(out);
+        if self.resubscribe {
+            let _ = (self.handler)(WsEvent::Resubscribed(resubscribed));
         }
         Ok(())
     }
 
-    fn on_message(&mut self, msg: Message) -> Result<()> {
-        let slice = &msg.into_data()[..];
-        let mut d = GzDecoder::new(slice);
-        let mut s = String::new();
-        d.read_to_string(&mut s).unwrap();
-        match self.deseralize(&s) {
-            Ok(event) => {
-                let _ = (self.handler)(event);
-            }
+    // huobi gzips every frame
+    fn on_message(&mut self, msg: Message) -> APIResult<()> {
+        let s = match gunzip(msg) {
+            Ok(s) => s,
             Err(err) => {
-                println!("deseralize msg error: {:?}", err);
+                warn!("gunzip msg error: {:?}", err);
+                return Ok(());
+            }
+        };
+        if let Some(recorder) = &self.recorder {
+            if let Err(err) = recorder.record("huobi", &record_stream(&s), &s) {
+                warn!("record frame error: {:?}", err);
             }
         }
+        self.handle_frame(&s);
+        Ok(())
+    }
+}
+
+fn gunzip(msg: Message) -> std::io::Result<String> {
+    let slice = &msg.into_data()[..];
+    let mut s = String::new();
+    GzDecoder::new(slice).read_to_string(&mut s)?;
+    Ok(s)
+}
+
+// sends `reqs` one after another over a connection of its own and returns the data of
+// every reply in order, for pulls like kline history that the rest api does not serve
+pub(crate) fn request_all(url: &str, config: WsConfig, reqs: Vec<Value>) -> APIResult<Vec<Value>> {
+    if reqs.is_empty() {
+        return Ok(vec![]);
+    }
+    let total = reqs.len();
+    let mut requests = Requests {
+        reqs: reqs.into_iter(),
+        pending: None,
+        out: None,
+        data: Vec::with_capacity(total),
+        error: None,
+    };
+    let transport = Transport::new(config, ReconnectConfig::huobi());
+    let ret = transport.run(url, &mut requests);
+    if let Some(err) = requests.error {
+        return Err(Box::new(err));
+    }
+    ret?;
+    if requests.data.len() < total {
+        return Err(Box::new(ExError::ApiError(format!(
+            "connection closed after {} of {} replies",
+            requests.data.len(),
+            total
+        ))));
+    }
+    Ok(requests.data)
+}
+
+struct Requests {
+    reqs: std::vec::IntoIter<Value>,
+    // id of the request in flight
+    pending: Option<String>,
+    out: Option<Outbox>,
+    data: Vec<Value>,
+    error: Option<ExError>,
+}
+
+impl Requests {
+    fn send_next(&mut self) {
+        let out = self.out.as_ref().unwrap();
+        match self.reqs.next() {
+            Some(req) => {
+                self.pending = req["id"].as_str().map(|id| id.to_string());
+                out.send(req.to_string());
+            }
+            None => {
+                self.pending = None;
+                out.close();
+            }
+        }
+    }
+}
+
+impl Handler for Requests {
+    fn on_open(&mut self, out: Outbox) -> APIResult<()> {
+        self.out = Some(out);
+        self.send_next();
+        Ok(())
+    }
+
+    fn on_message(&mut self, msg: Message) -> APIResult<()> {
+        let s = gunzip(msg)?;
+        let mut val: Value = serde_json::from_str(&s)?;
+        if let Some(ping) = val.get("ping") {
+            self.out.as_ref().unwrap().send(json!({ "pong": ping }).to_string());
+            return Ok(());
+        }
+        if self.pending.is_none() || val["id"].as_str() != self.pending.as_deref() {
+            return Ok(());
+        }
+        if val["status"].as_str() != Some("ok") {
+            self.error = Some(ExError::ApiError(format!(
+                "{}: {}",
+                val["err-code"].as_str().unwrap_or_default(),
+                val["err-msg"].as_str().unwrap_or_default()
+            )));
+            self.out.as_ref().unwrap().close();
+            return Ok(());
+        }
+        self.data.push(val["data"].take());
+        self.send_next();
         Ok(())
     }
 }
@@ -169,6 +739,201 @@ impl<'a> Handler for HuobiWs<'a> {
 mod test {
     use super::*;
     use crate::utils::get_timestamp;
+    use log::info;
+
+    #[test]
+    fn test_deseralize_routing() {
+        let huobi = HuobiWs::new("wss://api.huobi.pro/ws");
+        let kline = r#"{"ch":"market.btcusdt.kline.1min","ts":1,"tick":{"id":1489464480,"amount":0.0,"count":0,"open":7962.62,"close":7962.62,"low":7962.62,"high":7962.62,"vol":0.0}}"#;
+        match huobi.deseralize(kline).unwrap() {
+            WsEvent::KlineEvent(symbol, period, kline) => {
+                assert_eq!((symbol.as_str(), period.as_str()), ("btcusdt", "1min"));
+                assert_eq!(kline.timestamp, 1489464480000);
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        let trade = r#"{"ch":"market.btcusdt.trade.detail","ts":1,"tick":{"id":14650745135,"ts":1533265950234,"data":[{"amount":0.0099,"ts":1533265950234,"id":146507451359183894799,"tradeId":102043495674,"price":401.74,"direction":"buy"}]}}"#;
+        match huobi.deseralize(trade).unwrap() {
+            WsEvent::TradeEvent(_, trades) => {
+                assert_eq!(trades[0].price, 401.74);
+                assert_eq!((trades[0].id, trades[0].symbol.as_str()), (102043495674, "btcusdt"));
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        let detail = r#"{"ch":"market.btcusdt.detail","ts":1494496390000,"tick":{"id":1494496390,"amount":12.5,"count":265,"open":7962.62,"close":7962.62,"low":7962.62,"high":7962.62,"vol":99532.1,"version":12}}"#;
+        match huobi.deseralize(detail).unwrap() {
+            WsEvent::DetailEvent(symbol, stats) => {
+                assert_eq!((symbol.as_str(), stats.symbol.as_str()), ("btcusdt", "btcusdt"));
+                assert_eq!((stats.volume, stats.quote_volume, stats.trades), (12.5, 99532.1, 265));
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        let ticker = r#"{"ch":"market.ethusdt.ticker","ts":1630982370526,"tick":{"open":51732,"high":52785.64,"low":51000,"close":52735.63,"amount":13259.24,"vol":687640987.41,"count":448737,"bid":52732.88,"bidSize":0.036,"ask":52732.89,"askSize":0.583653,"lastPrice":52735.63,"lastSize":0.0086}}"#;
+        match huobi.deseralize(ticker).unwrap() {
+            WsEvent::DayTickerEvent(symbol, stats) => {
+                assert_eq!(symbol, "ethusdt");
+                assert_eq!((stats.bid, stats.ask, stats.timestamp), (52732.88, 52732.89, 1630982370526));
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        let tickers = r#"{"ch":"market.tickers","ts":1,"data":[{"symbol":"btcusdt","open":1.0,"high":2.0,"low":0.5,"close":1.5,"amount":10.0,"vol":15.0,"count":3}]}"#;
+        match huobi.deseralize(tickers).unwrap() {
+            WsEvent::AllTickersEvent(stats) => {
+                assert_eq!((stats[0].symbol.as_str(), stats[0].close, stats[0].bid), ("btcusdt", 1.5, 0.0));
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+
+        let unknown = r#"{"ch":"market.btcusdt.mbp.150","ts":1,"tick":{"seqNum":1}}"#;
+        match huobi.deseralize(unknown).unwrap() {
+            WsEvent::RawEvent(ch, tick) => {
+                assert_eq!(ch, "market.btcusdt.mbp.150");
+                assert_eq!(tick["seqNum"], 1);
+            }
+            event => panic!("unexpected event: {:?}", event),
+        }
+    }
+
+    #[test]
+    fn test_deferred_subscribe() {
+        let mut huobi = HuobiWs::new("wss://api.huobi.pro/ws");
+        let handle = huobi.handle();
+        huobi.set_depth_step(DepthStep::Step2);
+        huobi.sub_orderbook("BTCUSDT");
+        assert_eq!(handle.subscriptions(), vec!["market.btcusdt.depth.step2".to_string()]);
+        handle.unsubscribe("market.btcusdt.depth.step2");
+        let ack = handle.subscribe("market.btcusdt.kline.1min");
+        handle.subscribe("market.ethusdt.bbo");
+        handle.unsubscribe("market.ethusdt.bbo");
+        assert_eq!(handle.subscriptions(), vec!["market.btcusdt.kline.1min".to_string()]);
+        assert!(ack.try_get().is_none());
+
+        let resp = r#"{"id":"1","status":"error","err-code":"bad-request","err-msg":"invalid topic","ts":1}"#;
+        let event = huobi.deseralize(resp).unwrap();
+        match event {
+            WsEvent::ResponseEvent(resp) => {
+                assert_eq!(resp.id, "1");
+                assert_eq!(resp.err_code, "bad-request");
+            }
+            _ => panic!("expect response event"),
+        }
+    }
+
+    #[test]
+    fn test_request() {
+        let mut huobi = HuobiWs::new("wss://api.huobi.pro/ws");
+        let handle = huobi.handle();
+        let klines = handle.request_klines("BTCUSDT", "1min", 60_000, 120_000);
+        let depth = handle.request_depth("btcusdt", DepthStep::Step1);
+        let queued = huobi.state.lock().unwrap().take_queued();
+        let req: Value = serde_json::from_str(&queued[0]).unwrap();
+        assert_eq!(req, json!({"req": "market.btcusdt.kline.1min", "id": "1", "from": 60, "to": 120}));
+        assert_eq!(queued.len(), 2);
+
+        let frames = [
+            json!({"id": "1", "rep": "market.btcusdt.kline.1min", "status": "ok", "ts": 1, "data": [
+                {"id": 120, "amount": 1.0, "count": 1, "open": 2.0, "close": 2.0, "low": 2.0, "high": 2.0, "vol": 2.0},
+                {"id": 60, "amount": 1.0, "count": 1, "open": 1.0, "close": 1.0, "low": 1.0, "high": 1.0, "vol": 1.0}]}),
+            json!({"id": "2", "status": "error", "err-code": "bad-request", "err-msg": "invalid topic", "ts": 1}),
+        ]
+        .iter()
+        .map(|frame| RecordedFrame {
+            received: 0,
+            stream: String::new(),
+            frame: frame.to_string(),
+        })
+        .collect::<Vec<_>>();
+        huobi.replay(&frames, ReplaySpeed::AsFastAsPossible, |_| Ok(()));
+        let klines = klines.try_get().unwrap().unwrap();
+        assert_eq!(klines.iter().map(|kline| kline.timestamp).collect::<Vec<_>>(), vec![60_000, 120_000]);
+        assert_eq!(klines[0].close_time, 119_999);
+        assert!(depth.try_get().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_request_all() {
+        use flate2::write::GzEncoder;
+        use flate2::Compression;
+        use futures_util::{SinkExt, StreamExt};
+
+        let gzip = |val: Value| {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            encoder.write_all(val.to_string().as_bytes()).unwrap();
+            Message::Binary(encoder.finish().unwrap())
+        };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            runtime.block_on(async {
+                listener.set_nonblocking(true).unwrap();
+                let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+                let (conn, _) = listener.accept().await.unwrap();
+                let mut ws = async_tungstenite::tokio::accept_async(conn).await.unwrap();
+                ws.send(gzip(json!({"ping": 1}))).await.unwrap();
+                let mut received = vec![];
+                while let Some(Ok(Message::Text(msg))) = ws.next().await {
+                    let req: Value = serde_json::from_str(&msg).unwrap();
+                    received.push(req.clone());
+                    if req.get("req").is_some() {
+                        let rep = json!({"id": req["id"], "rep": req["req"], "status": "ok", "data": [req["from"]]});
+                        ws.send(gzip(json!({"ch": "market.btcusdt.bbo", "ts": 1, "tick": {}}))).await.unwrap();
+                        ws.send(gzip(rep)).await.unwrap();
+                    }
+                }
+                received
+            })
+        });
+
+        let reqs = vec![
+            json!({"req": "market.btcusdt.kline.1min", "id": "1", "from": 0, "to": 17999}),
+            json!({"req": "market.btcusdt.kline.1min", "id": "2", "from": 18000, "to": 20000}),
+        ];
+        let url = format!("ws://127.0.0.1:{}", port);
+        let data = request_all(&url, WsConfig::default(), reqs).unwrap();
+        assert_eq!(data, vec![json!([0]), json!([18000])]);
+        let received = server.join().unwrap();
+        assert!(received.contains(&json!({"pong": 1})));
+        assert_eq!(received.len(), 3);
+    }
+
+    #[test]
+    fn test_closed_klines_only() {
+        let kline = |ch: &str, id: u64, close: f64| RecordedFrame {
+            received: 0,
+            stream: ch.into(),
+            frame: json!({"ch": ch, "ts": 1, "tick": {"id": id, "amount": 2.0, "count": 3,
+                "open": 1.0, "close": close, "low": 0.5, "high": 3.0, "vol": 4.0}})
+            .to_string(),
+        };
+        let frames = vec![
+            kline("market.btcusdt.kline.1min", 60, 1.0),
+            kline("market.ethusdt.kline.1min", 60, 10.0),
+            kline("market.btcusdt.kline.1min", 60, 2.0),
+            kline("market.btcusdt.kline.1min", 120, 3.0),
+        ];
+        let mut klines = vec![];
+        {
+            let mut huobi = HuobiWs::new("wss://api.huobi.pro/ws");
+            huobi.set_closed_klines_only(true);
+            huobi.replay(&frames, ReplaySpeed::AsFastAsPossible, |event| {
+                if let WsEvent::KlineEvent(symbol, _, kline) = event {
+                    klines.push((symbol, kline));
+                }
+                Ok(())
+            });
+        }
+        assert_eq!(klines.len(), 1);
+        let (symbol, kline) = &klines[0];
+        assert_eq!(symbol, "btcusdt");
+        assert!(kline.closed);
+        assert_eq!((kline.timestamp, kline.close, kline.close_time), (60_000, 2.0, 119_999));
+        assert_eq!((kline.volume, kline.quote_volume, kline.trades), (2.0, 4.0, 3));
+    }
 
     //#[test]
     fn test_huobiws() {
@@ -176,14 +941,14 @@ mod test {
 
         let handler = |event: WsEvent| {
             match event {
-                WsEvent::OrderbookEvent(e) => {
-                    println!("orderbook: {:?}", e);
+                WsEvent::OrderbookEvent(_, e) => {
+                    info!("orderbook: {:?}", e);
                     let ts = get_timestamp();
                     let diff = ts.unwrap() - e.timestamp;
-                    println!("diff: {:?}", diff);
+                    info!("diff: {:?}", diff);
                 }
                 _ => {
-                    println!("event: {:?}", event);
+                    info!("event: {:?}", event);
                 }
             }
             Ok(())
@@ -1,5 +1,6 @@
 use crate::constant::*;
 use crate::models::*;
+use crate::utils::*;
 
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct Response<T> {
@@ -137,32 +138,201 @@ impl From<RawTicker> for Ticker {
     }
 }
 
+// market.$symbol.detail and /market/detail
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct RawDetail {
+    pub id: u64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub amount: f64,
+    pub vol: f64,
+    pub count: u64,
+}
+
+impl From<RawDetail> for DayStats {
+    fn from(item: RawDetail) -> DayStats {
+        DayStats {
+            open: item.open,
+            high: item.high,
+            low: item.low,
+            close: item.close,
+            volume: item.amount,
+            quote_volume: item.vol,
+            trades: item.count,
+            ..Default::default()
+        }
+    }
+}
+
+// one market of /market/tickers, the book is left out for markets without orders
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MarketTicker {
+    // left out by market.$symbol.ticker
+    #[serde(default)]
+    pub symbol: String,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub amount: f64,
+    pub vol: f64,
+    pub count: u64,
+    #[serde(default)]
+    pub bid: Option<f64>,
+    #[serde(default)]
+    pub bid_size: Option<f64>,
+    #[serde(default)]
+    pub ask: Option<f64>,
+    #[serde(default)]
+    pub ask_size: Option<f64>,
+}
+
+impl From<&MarketTicker> for Ticker {
+    fn from(item: &MarketTicker) -> Ticker {
+        Ticker {
+            timestamp: 0,
+            bid: Bid {
+                price: item.bid.unwrap_or(0.0),
+                amount: item.bid_size.unwrap_or(0.0),
+            },
+            ask: Ask {
+                price: item.ask.unwrap_or(0.0),
+                amount: item.ask_size.unwrap_or(0.0),
+            },
+        }
+    }
+}
+
+impl From<MarketTicker> for DayStats {
+    fn from(item: MarketTicker) -> DayStats {
+        DayStats {
+            timestamp: 0,
+            open: item.open,
+            high: item.high,
+            low: item.low,
+            close: item.close,
+            volume: item.amount,
+            quote_volume: item.vol,
+            trades: item.count,
+            bid: item.bid.unwrap_or(0.0),
+            ask: item.ask.unwrap_or(0.0),
+            symbol: item.symbol,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RawBbo {
+    pub seq_id: u64,
+    pub ask: f64,
+    pub ask_size: f64,
+    pub bid: f64,
+    pub bid_size: f64,
+    pub quote_time: u64,
+    pub symbol: String,
+}
+
+impl From<RawBbo> for Ticker {
+    fn from(item: RawBbo) -> Ticker {
+        Ticker {
+            timestamp: item.quote_time,
+            ask: Ask {
+                price: item.ask,
+                amount: item.ask_size,
+            },
+            bid: Bid {
+                price: item.bid,
+                amount: item.bid_size,
+            },
+        }
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct RawKline {
     pub id: u64,
+    // base amount
     pub amount: f64,
     pub count: u64,
     pub open: f64,
     pub high: f64,
     pub low: f64,
     pub close: f64,
+    // quote amount
     #[serde(rename = "vol")]
     pub volume: f64,
 }
 
+// huobi sends no close time or closed flag, see RawKline::into_kline.
+// The open time comes in seconds
 impl From<RawKline> for Kline {
     fn from(item: RawKline) -> Kline {
         Kline {
-            timestamp: item.id,
+            timestamp: item.id * 1000,
             open: item.open,
             high: item.high,
             low: item.low,
             close: item.close,
-            volume: item.volume,
+            volume: item.amount,
+            quote_volume: item.volume,
+            trades: item.count,
+            ..Default::default()
         }
     }
 }
 
+impl RawKline {
+    // fills close time from the period the candle was requested with, the candle counts
+    // as closed once that time has passed
+    pub fn into_kline(self, period: &str) -> Kline {
+        let mut kline: Kline = self.into();
+        if let Some(secs) = period_secs(period) {
+            kline.close_time = kline.timestamp + secs * 1000 - 1;
+            let now = get_timestamp().unwrap_or(0);
+            kline.closed = kline.close_time < now;
+        }
+        kline
+    }
+}
+
+// a month counts as 31 days and a year as 365
+pub fn period_secs(period: &str) -> Option<u64> {
+    const DAY: u64 = 24 * 3600;
+    match period {
+        "1min" => Some(60),
+        "5min" => Some(5 * 60),
+        "15min" => Some(15 * 60),
+        "30min" => Some(30 * 60),
+        "60min" => Some(3600),
+        "4hour" => Some(4 * 3600),
+        "1day" => Some(DAY),
+        "1week" => Some(7 * DAY),
+        "1mon" => Some(31 * DAY),
+        "1year" => Some(365 * DAY),
+        _ => None,
+    }
+}
+
+// None for the intervals huobi has no candles for
+pub fn kline_period(interval: KlineInterval) -> Option<&'static str> {
+    match interval {
+        KlineInterval::Min1 => Some("1min"),
+        KlineInterval::Min5 => Some("5min"),
+        KlineInterval::Min15 => Some("15min"),
+        KlineInterval::Min30 => Some("30min"),
+        KlineInterval::Hour1 => Some("60min"),
+        KlineInterval::Hour4 => Some("4hour"),
+        KlineInterval::Day1 => Some("1day"),
+        KlineInterval::Week1 => Some("1week"),
+        KlineInterval::Month1 => Some("1mon"),
+        _ => None,
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct BalanceInfoItem {
     pub currency: String,
@@ -242,7 +412,8 @@ pub struct RawTrade {
     pub amount: f64,
     pub ts: u64,
     pub id: u128,
-    #[serde(rename = "tradeId")]
+    // trade-id over rest
+    #[serde(rename = "tradeId", alias = "trade-id")]
     pub trade_id: u64,
     pub price: f64,
     pub direction: String,
@@ -251,6 +422,8 @@ pub struct RawTrade {
 impl From<RawTrade> for Trade {
     fn from(item: RawTrade) -> Trade {
         Trade {
+            id: item.trade_id,
+            symbol: String::new(),
             timestamp: item.ts,
             amount: item.amount,
             price: item.price,
@@ -258,3 +431,477 @@ impl From<RawTrade> for Trade {
         }
     }
 }
+
+// margin account balance, isolated accounts come one per symbol
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct MarginBalanceInfo {
+    pub id: u32,
+    #[serde(rename = "type")]
+    pub ty: String,
+    #[serde(default)]
+    pub symbol: String,
+    pub state: String,
+    #[serde(rename = "risk-rate")]
+    pub risk_rate: String,
+    // cross margin only, in usdt
+    #[serde(default, rename = "acct-balance-sum")]
+    pub acct_balance_sum: String,
+    #[serde(default, rename = "debt-balance-sum")]
+    pub debt_balance_sum: String,
+    pub list: Vec<BalanceInfoItem>,
+}
+
+// loans and interest are listed as negative balances
+impl From<MarginBalanceInfo> for MarginAccount {
+    fn from(item: MarginBalanceInfo) -> MarginAccount {
+        let mut assets: Vec<MarginAsset> = vec![];
+        for balance in item.list {
+            let index = match assets.iter().position(|asset| asset.asset == balance.currency) {
+                Some(index) => index,
+                None => {
+                    assets.push(MarginAsset {
+                        asset: balance.currency.clone(),
+                        ..Default::default()
+                    });
+                    assets.len() - 1
+                }
+            };
+            let asset = &mut assets[index];
+            let amount = balance.balance.parse::<f64>().unwrap_or(0.0);
+            match balance.ty.as_str() {
+                "trade" => asset.free = amount,
+                "frozen" => asset.locked = amount,
+                "loan" => asset.borrowed = -amount,
+                "interest" => asset.interest = -amount,
+                _ => continue,
+            }
+            asset.net_asset = asset.free + asset.locked - asset.borrowed - asset.interest;
+        }
+        MarginAccount {
+            symbol: item.symbol,
+            margin_level: item.risk_rate.parse::<f64>().unwrap_or(0.0),
+            total_asset: item.acct_balance_sum.parse::<f64>().unwrap_or(0.0),
+            total_liability: item.debt_balance_sum.parse::<f64>().unwrap_or(0.0),
+            assets,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct LoanInfo {
+    pub symbol: String,
+    pub currencies: Vec<LoanCurrency>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct LoanCurrency {
+    pub currency: String,
+    // per day
+    #[serde(rename = "interest-rate")]
+    pub interest_rate: String,
+    #[serde(rename = "min-loan-amt")]
+    pub min_loan_amt: String,
+    #[serde(rename = "max-loan-amt")]
+    pub max_loan_amt: String,
+    #[serde(rename = "loanable-amt")]
+    pub loanable_amt: String,
+    // after any discount
+    #[serde(default, rename = "actual-rate")]
+    pub actual_rate: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct RawLoanOrder {
+    pub id: u64,
+    // isolated margin only
+    #[serde(default)]
+    pub symbol: String,
+    pub currency: String,
+    #[serde(rename = "loan-amount")]
+    pub loan_amount: String,
+    // principal still owed
+    #[serde(rename = "loan-balance")]
+    pub loan_balance: String,
+    #[serde(rename = "interest-rate")]
+    pub interest_rate: String,
+    #[serde(rename = "interest-amount")]
+    pub interest_amount: String,
+    // interest still owed
+    #[serde(rename = "interest-balance")]
+    pub interest_balance: String,
+    #[serde(rename = "created-at")]
+    pub created_at: u64,
+    #[serde(rename = "accrued-at")]
+    pub accrued_at: u64,
+    // created, accrual, cleared or invalid
+    pub state: String,
+}
+
+impl RawLoanOrder {
+    // principal and interest left to repay, in loan units
+    pub fn owed(&self) -> u128 {
+        to_loan_units(&self.loan_balance).unwrap_or(0) + to_loan_units(&self.interest_balance).unwrap_or(0)
+    }
+}
+
+// huobi quotes loan balances with 18 decimals, repayments are summed and split in units of
+// 1e-18 so no float rounding ends up in the amount sent
+const LOAN_DECIMALS: usize = 18;
+const LOAN_SCALE: u128 = 1_000_000_000_000_000_000;
+
+// "0.3" -> 300000000000000000, digits past the 18th decimal are dropped
+pub fn to_loan_units(amount: &str) -> Option<u128> {
+    let mut parts = amount.trim().splitn(2, '.');
+    let int = parts.next().unwrap_or("");
+    let frac = parts.next().unwrap_or("");
+    if !int.chars().chain(frac.chars()).all(|c| c.is_ascii_digit()) || int.len() + frac.len() == 0 {
+        return None;
+    }
+    let int = if int.is_empty() { 0 } else { int.parse::<u128>().ok()? };
+    let frac = format!("{:0<width$.width$}", frac, width = LOAN_DECIMALS);
+    int.checked_mul(LOAN_SCALE)?.checked_add(frac.parse::<u128>().ok()?)
+}
+
+// 300000000000000000 -> "0.3"
+pub fn from_loan_units(units: u128) -> String {
+    let amount = format!("{}.{:018}", units / LOAN_SCALE, units % LOAN_SCALE);
+    amount.trim_end_matches('0').trim_end_matches('.').to_string()
+}
+
+impl From<&RawLoanOrder> for MarginLoan {
+    fn from(item: &RawLoanOrder) -> MarginLoan {
+        MarginLoan {
+            id: item.id.to_string(),
+            symbol: item.symbol.clone(),
+            asset: item.currency.clone(),
+            principal: item.loan_amount.parse::<f64>().unwrap_or(0.0),
+            interest: item.interest_amount.parse::<f64>().unwrap_or(0.0),
+            timestamp: item.created_at,
+            status: item.state.clone(),
+        }
+    }
+}
+
+// huobi keeps no separate interest records, a loan order carries the interest accrued on it
+impl From<&RawLoanOrder> for MarginInterest {
+    fn from(item: &RawLoanOrder) -> MarginInterest {
+        MarginInterest {
+            symbol: item.symbol.clone(),
+            asset: item.currency.clone(),
+            interest: item.interest_amount.parse::<f64>().unwrap_or(0.0),
+            rate: item.interest_rate.parse::<f64>().unwrap_or(0.0),
+            timestamp: item.accrued_at,
+        }
+    }
+}
+
+// /v2 endpoints answer with a code instead of a status
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct ResponseV2<T> {
+    pub code: u32,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default)]
+    pub data: T,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferenceCurrency {
+    pub currency: String,
+    pub chains: Vec<RawChain>,
+    pub inst_status: String,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RawChain {
+    pub chain: String,
+    #[serde(default)]
+    pub display_name: String,
+    #[serde(default)]
+    pub base_chain: String,
+    pub num_of_confirmations: u32,
+    // allowed or prohibited
+    pub deposit_status: String,
+    #[serde(default)]
+    pub min_deposit_amt: String,
+    pub withdraw_status: String,
+    #[serde(default)]
+    pub min_withdraw_amt: String,
+    #[serde(default)]
+    pub max_withdraw_amt: String,
+    // fixed, circulated or ratio
+    #[serde(default)]
+    pub withdraw_fee_type: String,
+    #[serde(default)]
+    pub transact_fee_withdraw: String,
+    #[serde(default)]
+    pub min_transact_fee_withdraw: String,
+}
+
+impl RawChain {
+    // the whole fee where it is fixed, the least one otherwise. None when huobi sends no
+    // usable fee, it rejects a zero fee on circulated and ratio chains
+    pub fn withdraw_fee(&self) -> Option<f64> {
+        if self.withdraw_fee_type == "fixed" {
+            return self.transact_fee_withdraw.parse::<f64>().ok();
+        }
+        match self.min_transact_fee_withdraw.parse::<f64>() {
+            Ok(fee) if fee > 0.0 => Some(fee),
+            _ => None,
+        }
+    }
+}
+
+// huobi flags no default chain, the one named after the currency is taken for it
+impl From<(&str, RawChain)> for WalletNetwork {
+    fn from((currency, item): (&str, RawChain)) -> WalletNetwork {
+        WalletNetwork {
+            asset: currency.to_string(),
+            is_default: item.chain == currency,
+            deposit_enabled: item.deposit_status == "allowed",
+            withdraw_enabled: item.withdraw_status == "allowed",
+            withdraw_fee: item.withdraw_fee().unwrap_or(0.0),
+            withdraw_min: item.min_withdraw_amt.parse::<f64>().unwrap_or(0.0),
+            withdraw_max: item.max_withdraw_amt.parse::<f64>().unwrap_or(0.0),
+            deposit_min: item.min_deposit_amt.parse::<f64>().unwrap_or(0.0),
+            confirmations: item.num_of_confirmations,
+            network: item.chain,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RawDepositAddress {
+    pub currency: String,
+    pub address: String,
+    #[serde(default)]
+    pub address_tag: String,
+    pub chain: String,
+}
+
+impl From<RawDepositAddress> for DepositAddress {
+    fn from(item: RawDepositAddress) -> DepositAddress {
+        DepositAddress {
+            asset: item.currency,
+            network: item.chain,
+            address: item.address,
+            tag: item.address_tag,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+pub struct RawDepositWithdraw {
+    pub id: u64,
+    // deposit or withdraw
+    #[serde(rename = "type")]
+    pub ty: String,
+    pub currency: String,
+    #[serde(default, rename = "tx-hash")]
+    pub tx_hash: String,
+    #[serde(default)]
+    pub chain: String,
+    pub amount: f64,
+    pub address: String,
+    #[serde(default, rename = "address-tag")]
+    pub address_tag: String,
+    #[serde(default)]
+    pub fee: f64,
+    pub state: String,
+    #[serde(rename = "created-at")]
+    pub created_at: u64,
+    #[serde(rename = "updated-at")]
+    pub updated_at: u64,
+}
+
+impl From<RawDepositWithdraw> for WalletTransfer {
+    fn from(item: RawDepositWithdraw) -> WalletTransfer {
+        let status = match (item.ty.as_str(), item.state.as_str()) {
+            ("deposit", "safe") | ("deposit", "confirmed") => TRANSFER_STATUS_COMPLETED,
+            ("deposit", "orphan") => TRANSFER_STATUS_FAILED,
+            ("deposit", _) => TRANSFER_STATUS_PENDING,
+            (_, "confirmed") => TRANSFER_STATUS_COMPLETED,
+            (_, "canceled") | (_, "repealed") => TRANSFER_STATUS_CANCELLED,
+            (_, "failed") | (_, "reject") | (_, "wallet-reject") | (_, "confirm-error") => TRANSFER_STATUS_FAILED,
+            _ => TRANSFER_STATUS_PENDING,
+        };
+        WalletTransfer {
+            id: item.id.to_string(),
+            asset: item.currency,
+            network: item.chain,
+            address: item.address,
+            tag: item.address_tag,
+            amount: item.amount,
+            fee: item.fee,
+            tx_id: item.tx_hash,
+            timestamp: item.created_at,
+            status,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tickers() {
+        let raw = r#"{"id":272156789143,"version":272156789143,"ts":1630982370526,"close":52735.63,
+            "open":51200.0,"high":52920.0,"low":50700.0,"amount":1234.5,"count":98765,"vol":64000000.0,
+            "ask":[52735.64,0.6],"bid":[52735.63,1.2]}"#;
+        let ticker = Ticker::from(serde_json::from_str::<RawTicker>(raw).unwrap());
+        assert_eq!(ticker.timestamp, 1630982370526);
+        assert_eq!((ticker.bid.price, ticker.bid.amount), (52735.63, 1.2));
+        assert_eq!((ticker.ask.price, ticker.ask.amount), (52735.64, 0.6));
+
+        let raw = r#"{"id":272156789143,"version":272156789143,"open":51200.0,"close":52735.63,
+            "low":50700.0,"high":52920.0,"amount":1234.5,"vol":64000000.0,"count":98765}"#;
+        let stats = DayStats::from(serde_json::from_str::<RawDetail>(raw).unwrap());
+        assert_eq!((stats.open, stats.high, stats.low, stats.close), (51200.0, 52920.0, 50700.0, 52735.63));
+        assert_eq!((stats.volume, stats.quote_volume, stats.trades), (1234.5, 64000000.0, 98765));
+
+        let raw = r#"[{"symbol":"btcusdt","open":51200.0,"high":52920.0,"low":50700.0,"close":52735.63,
+            "amount":1234.5,"vol":64000000.0,"count":98765,"bid":52735.63,"bidSize":1.2,"ask":52735.64,
+            "askSize":0.6},{"symbol":"newcoin","open":1.0,"high":1.0,"low":1.0,"close":1.0,"amount":0.0,
+            "vol":0.0,"count":0}]"#;
+        let tickers: Vec<MarketTicker> = serde_json::from_str(raw).unwrap();
+        let ticker = Ticker::from(&tickers[0]);
+        assert_eq!((ticker.bid.price, ticker.bid.amount, ticker.ask.amount), (52735.63, 1.2, 0.6));
+        assert_eq!(Ticker::from(&tickers[1]).bid.price, 0.0);
+        let stats = tickers.into_iter().map(DayStats::from).collect::<Vec<_>>();
+        assert_eq!((stats[0].symbol.as_str(), stats[0].bid, stats[0].ask), ("btcusdt", 52735.63, 52735.64));
+        assert_eq!((stats[0].volume, stats[0].quote_volume, stats[0].trades), (1234.5, 64000000.0, 98765));
+        assert_eq!((stats[1].symbol.as_str(), stats[1].bid, stats[1].ask), ("newcoin", 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_kline_period() {
+        assert_eq!(kline_period(KlineInterval::Hour1), Some("60min"));
+        assert_eq!(kline_period(KlineInterval::Hour2), None);
+        assert_eq!(period_secs(kline_period(KlineInterval::Week1).unwrap()), Some(7 * 24 * 3600));
+
+        let kline = RawKline {
+            id: 60,
+            ..Default::default()
+        };
+        let kline = kline.into_kline("1min");
+        assert_eq!((kline.timestamp, kline.close_time, kline.closed), (60_000, 119_999, true));
+    }
+
+    #[test]
+    fn test_trades() {
+        // rest spells the trade id trade-id, the ws channel tradeId
+        let raw = r#"{"id":102405474617837693651219937,"ts":1630994511000,"trade-id":102110897862,
+            "amount":0.0012,"price":52800.01,"direction":"buy"}"#;
+        let trade = Trade::from(serde_json::from_str::<RawTrade>(raw).unwrap());
+        assert_eq!((trade.id, trade.symbol.as_str(), trade.side.as_str()), (102110897862, "", "buy"));
+        assert_eq!((trade.timestamp, trade.price, trade.amount), (1630994511000, 52800.01, 0.0012));
+
+        let raw = r#"{"id":102405474617837693651219938,"ts":1630994511032,"tradeId":102110897863,
+            "amount":0.5,"price":52799.99,"direction":"sell"}"#;
+        let trade = Trade::from(serde_json::from_str::<RawTrade>(raw).unwrap());
+        assert_eq!((trade.id, trade.side.as_str(), trade.timestamp), (102110897863, "sell", 1630994511032));
+    }
+
+    #[test]
+    fn test_margin_account() {
+        let raw = r#"{"id":18264,"type":"margin","symbol":"btcusdt","state":"working","risk-rate":"1.5",
+            "list":[{"currency":"btc","type":"trade","balance":"1.2"},
+            {"currency":"btc","type":"frozen","balance":"0.3"},
+            {"currency":"btc","type":"loan","balance":"-0.5"},
+            {"currency":"btc","type":"interest","balance":"-0.001"},
+            {"currency":"btc","type":"transfer-out-available","balance":"0.7"},
+            {"currency":"usdt","type":"trade","balance":"100"}]}"#;
+        let account = MarginAccount::from(serde_json::from_str::<MarginBalanceInfo>(raw).unwrap());
+        assert_eq!((account.symbol.as_str(), account.margin_level), ("btcusdt", 1.5));
+        assert_eq!((account.total_asset, account.total_liability), (0.0, 0.0));
+        assert_eq!(account.assets.len(), 2);
+        let btc = &account.assets[0];
+        assert_eq!((btc.asset.as_str(), btc.free, btc.locked), ("btc", 1.2, 0.3));
+        assert_eq!((btc.borrowed, btc.interest), (0.5, 0.001));
+        assert!((btc.net_asset - 0.999).abs() < 1e-12);
+        assert_eq!((account.assets[1].free, account.assets[1].net_asset), (100.0, 100.0));
+
+        let raw = r#"{"id":18265,"type":"cross-margin","state":"working","risk-rate":"2",
+            "acct-balance-sum":"3000","debt-balance-sum":"1000","list":[]}"#;
+        let account = MarginAccount::from(serde_json::from_str::<MarginBalanceInfo>(raw).unwrap());
+        assert_eq!((account.total_asset, account.total_liability), (3000.0, 1000.0));
+    }
+
+    #[test]
+    fn test_loan_units() {
+        assert_eq!(to_loan_units("0.3"), Some(300_000_000_000_000_000));
+        assert_eq!(to_loan_units("12"), Some(12 * LOAN_SCALE));
+        assert_eq!(to_loan_units("0.100000000000000000123"), Some(100_000_000_000_000_000));
+        assert_eq!(to_loan_units("-1"), None);
+        assert_eq!(to_loan_units(""), None);
+        let owed = to_loan_units("0.1").unwrap() + to_loan_units("0.2").unwrap();
+        assert_eq!(from_loan_units(owed), "0.3");
+        assert_eq!(from_loan_units(5 * LOAN_SCALE), "5");
+        assert_eq!(from_loan_units(0), "0");
+
+        let loan = RawLoanOrder {
+            loan_balance: "0.100000000000000000".into(),
+            interest_balance: "0.000200000000000000".into(),
+            ..Default::default()
+        };
+        assert_eq!(from_loan_units(loan.owed()), "0.1002");
+    }
+
+    #[test]
+    fn test_transfer_status() {
+        let raw = r#"{"id":101123262,"type":"deposit","currency":"usdt","tx-hash":"c1a1a2","chain":"trc20usdt",
+            "amount":12.5,"address":"TXaddress","address-tag":"","fee":0,"state":"safe",
+            "created-at":1510912472199,"updated-at":1511145876575}"#;
+        let transfer = WalletTransfer::from(serde_json::from_str::<RawDepositWithdraw>(raw).unwrap());
+        assert_eq!((transfer.id.as_str(), transfer.network.as_str()), ("101123262", "trc20usdt"));
+        assert_eq!((transfer.tx_id.as_str(), transfer.amount, transfer.timestamp), ("c1a1a2", 12.5, 1510912472199));
+        assert_eq!(transfer.status, TRANSFER_STATUS_COMPLETED);
+
+        let status = |ty: &str, state: &str| {
+            WalletTransfer::from(RawDepositWithdraw {
+                ty: ty.into(),
+                state: state.into(),
+                ..Default::default()
+            })
+            .status
+        };
+        assert_eq!(status("deposit", "confirming"), TRANSFER_STATUS_PENDING);
+        assert_eq!(status("deposit", "confirmed"), TRANSFER_STATUS_COMPLETED);
+        assert_eq!(status("deposit", "orphan"), TRANSFER_STATUS_FAILED);
+        assert_eq!(status("withdraw", "submitted"), TRANSFER_STATUS_PENDING);
+        assert_eq!(status("withdraw", "pre-transfer"), TRANSFER_STATUS_PENDING);
+        assert_eq!(status("withdraw", "confirmed"), TRANSFER_STATUS_COMPLETED);
+        assert_eq!(status("withdraw", "canceled"), TRANSFER_STATUS_CANCELLED);
+        assert_eq!(status("withdraw", "repealed"), TRANSFER_STATUS_CANCELLED);
+        assert_eq!(status("withdraw", "wallet-reject"), TRANSFER_STATUS_FAILED);
+        assert_eq!(status("withdraw", "confirm-error"), TRANSFER_STATUS_FAILED);
+    }
+
+    #[test]
+    fn test_withdraw_fee() {
+        let chain = |ty: &str, fee: &str, min_fee: &str| RawChain {
+            withdraw_fee_type: ty.into(),
+            transact_fee_withdraw: fee.into(),
+            min_transact_fee_withdraw: min_fee.into(),
+            ..Default::default()
+        };
+        assert_eq!(chain("fixed", "0.5", "").withdraw_fee(), Some(0.5));
+        assert_eq!(chain("fixed", "0", "").withdraw_fee(), Some(0.0));
+        assert_eq!(chain("circulated", "", "1.2").withdraw_fee(), Some(1.2));
+        assert_eq!(chain("circulated", "", "").withdraw_fee(), None);
+        assert_eq!(chain("ratio", "0.001", "0").withdraw_fee(), None);
+
+        let raw = r#"{"chain":"trc20usdt","displayName":"TRC20","baseChain":"TRX","numOfConfirmations":1,
+            "depositStatus":"allowed","minDepositAmt":"1","withdrawStatus":"allowed","minWithdrawAmt":"2",
+            "maxWithdrawAmt":"1000000","withdrawFeeType":"ratio","transactFeeWithdraw":"0.001",
+            "minTransactFeeWithdraw":"1"}"#;
+        let network = WalletNetwork::from(("usdt", serde_json::from_str::<RawChain>(raw).unwrap()));
+        assert_eq!(network.network, "trc20usdt");
+        assert!(!network.is_default && network.withdraw_enabled);
+        assert_eq!((network.withdraw_fee, network.withdraw_min, network.withdraw_max), (1.0, 2.0, 1000000.0));
+    }
+}
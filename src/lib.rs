@@ -3,17 +3,21 @@ extern crate env_logger;
 extern crate log;
 extern crate serde;
 extern crate serde_json;
-extern crate ws;
+extern crate async_tungstenite;
 
 #[macro_use]
 extern crate serde_derive;
 #[macro_use]
 extern crate lazy_static;
 
+pub mod candles;
 pub mod constant;
 pub mod errors;
 pub mod models;
+pub mod recorder;
+pub mod replay;
 pub mod traits;
+pub mod websocket;
 mod utils;
 
 pub mod binance;
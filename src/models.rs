@@ -1,3 +1,7 @@
+use crate::errors::*;
+
+use std::collections::HashSet;
+
 // for futures
 #[derive(Debug)]
 pub enum PositionType {
@@ -35,28 +39,32 @@ pub struct Order {
     pub status: u8,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Orderbook {
     pub timestamp: u64,
     pub bids: Vec<Bid>,
     pub asks: Vec<Ask>,
 }
 
-#[derive(Debug)]
+// id is the aggregate trade id for binance aggTrade and its stream, the trade id otherwise
+#[derive(Debug, Clone, Default)]
 pub struct Trade {
+    pub id: u64,
+    pub symbol: String,
     pub timestamp: u64,
     pub amount: f64,
     pub price: f64,
+    // taker side
     pub side: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Bid {
     pub price: f64,
     pub amount: f64,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Ask {
     pub price: f64,
     pub amount: f64,
@@ -85,14 +93,230 @@ impl Ticker {
     }
 }
 
-#[derive(Debug)]
+// rolling 24 hour statistics of a symbol, bid and ask are 0 where the exchange leaves them out
+#[derive(Debug, Clone, Default)]
+pub struct DayStats {
+    pub symbol: String,
+    pub timestamp: u64,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    // last price
+    pub close: f64,
+    // base amount
+    pub volume: f64,
+    pub quote_volume: f64,
+    pub trades: u64,
+    pub bid: f64,
+    pub ask: f64,
+}
+
+impl DayStats {
+    pub fn change_percent(&self) -> f64 {
+        if self.open == 0.0 {
+            return 0.0;
+        }
+        (self.close - self.open) / self.open * 100.0
+    }
+}
+
+// times are ms since the epoch
+#[derive(Debug, Clone, Default)]
 pub struct Kline {
+    // open time
     pub timestamp: u64,
     pub open: f64,
     pub high: f64,
     pub low: f64,
     pub close: f64,
+    // base amount
     pub volume: f64,
+    // last moment covered by the candle
+    pub close_time: u64,
+    // false while the candle can still change
+    pub closed: bool,
+    pub quote_volume: f64,
+    pub trades: u64,
+    // base amount bought by takers, 0 where the exchange does not report it
+    pub taker_buy_volume: f64,
+}
+
+// exchanges name these differently and not every exchange serves all of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KlineInterval {
+    Min1,
+    Min3,
+    Min5,
+    Min15,
+    Min30,
+    Hour1,
+    Hour2,
+    Hour4,
+    Hour6,
+    Hour8,
+    Hour12,
+    Day1,
+    Day3,
+    Week1,
+    Month1,
+}
+
+impl KlineInterval {
+    // a month is taken as 31 days, resample buckets calendar months
+    pub fn millis(&self) -> u64 {
+        const MINUTE: u64 = 60 * 1000;
+        const HOUR: u64 = 60 * MINUTE;
+        const DAY: u64 = 24 * HOUR;
+        match self {
+            KlineInterval::Min1 => MINUTE,
+            KlineInterval::Min3 => 3 * MINUTE,
+            KlineInterval::Min5 => 5 * MINUTE,
+            KlineInterval::Min15 => 15 * MINUTE,
+            KlineInterval::Min30 => 30 * MINUTE,
+            KlineInterval::Hour1 => HOUR,
+            KlineInterval::Hour2 => 2 * HOUR,
+            KlineInterval::Hour4 => 4 * HOUR,
+            KlineInterval::Hour6 => 6 * HOUR,
+            KlineInterval::Hour8 => 8 * HOUR,
+            KlineInterval::Hour12 => 12 * HOUR,
+            KlineInterval::Day1 => DAY,
+            KlineInterval::Day3 => 3 * DAY,
+            KlineInterval::Week1 => 7 * DAY,
+            KlineInterval::Month1 => 31 * DAY,
+        }
+    }
+}
+
+// cross margin, or the isolated margin of `symbol`
+#[derive(Debug, Clone, Default)]
+pub struct MarginAccount {
+    // the isolated pair, empty for cross margin
+    pub symbol: String,
+    // assets over liabilities, binance's margin level and huobi's risk rate
+    pub margin_level: f64,
+    // valued in btc on binance and usdt on huobi, 0 where the exchange leaves them out
+    pub total_asset: f64,
+    pub total_liability: f64,
+    pub assets: Vec<MarginAsset>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MarginAsset {
+    pub asset: String,
+    pub free: f64,
+    pub locked: f64,
+    pub borrowed: f64,
+    // accrued and not yet repaid
+    pub interest: f64,
+    pub net_asset: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MarginLoan {
+    pub id: String,
+    // the isolated pair, empty for cross margin
+    pub symbol: String,
+    pub asset: String,
+    pub principal: f64,
+    pub interest: f64,
+    pub timestamp: u64,
+    // as the exchange reports it
+    pub status: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MarginInterest {
+    pub symbol: String,
+    pub asset: String,
+    pub interest: f64,
+    // per day
+    pub rate: f64,
+    pub timestamp: u64,
+}
+
+// what the exchange accepts for one asset on one network, amounts are in the asset
+#[derive(Debug, Clone, Default)]
+pub struct WalletNetwork {
+    pub asset: String,
+    pub network: String,
+    // used when no network is given
+    pub is_default: bool,
+    pub deposit_enabled: bool,
+    pub withdraw_enabled: bool,
+    pub withdraw_fee: f64,
+    pub withdraw_min: f64,
+    // 0 where the exchange sets no limit
+    pub withdraw_max: f64,
+    pub deposit_min: f64,
+    // blocks before a deposit is credited
+    pub confirmations: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DepositAddress {
+    pub asset: String,
+    pub network: String,
+    pub address: String,
+    // memo or tag, empty where the network has none
+    pub tag: String,
+}
+
+// a deposit or withdrawal, status is one of TRANSFER_STATUS_*
+#[derive(Debug, Clone, Default)]
+pub struct WalletTransfer {
+    pub id: String,
+    pub asset: String,
+    pub network: String,
+    pub address: String,
+    pub tag: String,
+    pub amount: f64,
+    pub fee: f64,
+    // empty until the transaction is broadcast
+    pub tx_id: String,
+    pub timestamp: u64,
+    pub status: u8,
+}
+
+// the only destinations withdraw sends to, nothing goes out while it is empty
+#[derive(Debug, Clone, Default)]
+pub struct WithdrawWhitelist {
+    // asset, network, address and tag
+    entries: HashSet<(String, String, String, String)>,
+}
+
+// an address is only allowed on the network it was added for, the same address may not receive
+// on another chain. A None network is the exchange's default network of the asset
+impl WithdrawWhitelist {
+    pub fn allow(&mut self, asset: &str, network: Option<&str>, address: &str, tag: Option<&str>) {
+        self.entries.insert(Self::key(asset, network, address, tag));
+    }
+
+    pub fn remove(&mut self, asset: &str, network: Option<&str>, address: &str, tag: Option<&str>) {
+        self.entries.remove(&Self::key(asset, network, address, tag));
+    }
+
+    pub fn check(&self, asset: &str, network: Option<&str>, address: &str, tag: Option<&str>) -> APIResult<()> {
+        if self.entries.contains(&Self::key(asset, network, address, tag)) {
+            Ok(())
+        } else {
+            Err(Box::new(ExError::ApiError(format!(
+                "withdraw refused, {} {} on {} is not whitelisted",
+                asset,
+                address,
+                network.unwrap_or("the default network")
+            ))))
+        }
+    }
+
+    // addresses are matched as given, some networks are case sensitive
+    fn key(asset: &str, network: Option<&str>, address: &str, tag: Option<&str>) -> (String, String, String, String) {
+        (
+            asset.to_uppercase(),
+            network.unwrap_or("").to_uppercase(),
+            address.to_string(),
+            tag.unwrap_or("").to_string(),
+        )
+    }
 }
 
 // for futures
@@ -103,3 +327,30 @@ pub struct Position {
     pub price: f64,
     pub pos_type: PositionType,
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_withdraw_whitelist() {
+        let tron = Some("TRX");
+        let mut whitelist = WithdrawWhitelist::default();
+        assert!(whitelist.check("USDT", tron, "TXLAQ63Xg1NAzckPwKHvzw7CSEmLMEqcdj", None).is_err());
+        whitelist.allow("usdt", tron, "TXLAQ63Xg1NAzckPwKHvzw7CSEmLMEqcdj", None);
+        whitelist.allow("XRP", None, "rEb8TK3gBgk5auZkwc6sHnwrGVJH8DuaLh", Some("103"));
+        assert!(whitelist.check("USDT", Some("trx"), "TXLAQ63Xg1NAzckPwKHvzw7CSEmLMEqcdj", None).is_ok());
+        assert!(whitelist.check("USDT", tron, "txlaq63xg1nazckpwkhvzw7csemlmeqcdj", None).is_err());
+        assert!(whitelist.check("XRP", None, "rEb8TK3gBgk5auZkwc6sHnwrGVJH8DuaLh", None).is_err());
+        assert!(whitelist.check("XRP", None, "rEb8TK3gBgk5auZkwc6sHnwrGVJH8DuaLh", Some("103")).is_ok());
+        whitelist.remove("USDT", tron, "TXLAQ63Xg1NAzckPwKHvzw7CSEmLMEqcdj", None);
+        assert!(whitelist.check("USDT", tron, "TXLAQ63Xg1NAzckPwKHvzw7CSEmLMEqcdj", None).is_err());
+
+        // the same evm address on another chain, or on the default network, is not allowed
+        let evm = "0x94df8b352de7f46f64b01d3666bf6e936e44ce60";
+        whitelist.allow("USDT", Some("ETH"), evm, None);
+        assert!(whitelist.check("USDT", Some("ETH"), evm, None).is_ok());
+        assert!(whitelist.check("USDT", Some("BSC"), evm, None).is_err());
+        assert!(whitelist.check("USDT", None, evm, None).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use crate::errors::*;
+
+use chrono::Utc;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// where and how often recordings rotate, a new file is also started at every utc day
+#[derive(Debug, Clone)]
+pub struct RecorderConfig {
+    pub dir: PathBuf,
+    // uncompressed bytes per file
+    pub max_bytes: Option<u64>,
+    pub max_age: Option<Duration>,
+    pub compress: bool,
+}
+
+impl RecorderConfig {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        RecorderConfig {
+            dir: dir.as_ref().to_path_buf(),
+            max_bytes: Some(256 * 1024 * 1024),
+            max_age: Some(Duration::from_secs(3600)),
+            compress: true,
+        }
+    }
+}
+
+// writes raw frames as they arrived, one "<receive time in us> <frame>" line each, to
+// <dir>/<exchange>/<stream>/<yyyy-mm-dd>.<nnnn>.log[.gz]
+// clones share the open files, so one recorder can sit behind several connections
+#[derive(Clone)]
+pub struct Recorder {
+    config: RecorderConfig,
+    state: Arc<Mutex<RecorderState>>,
+}
+
+struct RecorderState {
+    files: HashMap<(String, String), RecordFile>,
+    // receive times are kept strictly increasing, so streams merge back in recording order
+    last_received: u64,
+}
+
+impl fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Recorder").field("config", &self.config).finish()
+    }
+}
+
+struct RecordFile {
+    date: String,
+    opened: Instant,
+    written: u64,
+    out: Box<dyn Write + Send>,
+}
+
+impl Recorder {
+    pub fn new(config: RecorderConfig) -> Self {
+        Recorder {
+            config,
+            state: Arc::new(Mutex::new(RecorderState {
+                files: HashMap::new(),
+                last_received: 0,
+            })),
+        }
+    }
+
+    pub fn record(&self, exchange: &str, stream: &str, frame: &str) -> APIResult<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_micros() as u64;
+        let date = Utc::now().format("%Y-%m-%d").to_string();
+        let mut state = self.state.lock().unwrap();
+        let received = now.max(state.last_received + 1);
+        state.last_received = received;
+        let files = &mut state.files;
+        let key = (exchange.to_string(), file_name(stream));
+        let rotate = match files.get(&key) {
+            Some(file) => self.should_rotate(file, &date),
+            None => true,
+        };
+        if rotate {
+            // flushes and finishes the gzip stream of the old file
+            files.remove(&key);
+            let file = self.open(&key.0, &key.1, &date)?;
+            files.insert(key.clone(), file);
+        }
+        let file = files.get_mut(&key).unwrap();
+        let line = format!("{} {}\n", received, frame);
+        file.out.write_all(line.as_bytes())?;
+        file.written += line.len() as u64;
+        Ok(())
+    }
+
+    pub fn flush(&self) -> APIResult<()> {
+        for file in self.state.lock().unwrap().files.values_mut() {
+            file.out.flush()?;
+        }
+        Ok(())
+    }
+
+    // closes every file, the next frame of a stream starts a new one
+    pub fn close(&self) {
+        self.state.lock().unwrap().files.clear();
+    }
+
+    fn should_rotate(&self, file: &RecordFile, date: &str) -> bool {
+        file.date != date
+            || self.config.max_bytes.map_or(false, |max_bytes| file.written >= max_bytes)
+            || self.config.max_age.map_or(false, |max_age| file.opened.elapsed() >= max_age)
+    }
+
+    fn open(&self, exchange: &str, stream: &str, date: &str) -> APIResult<RecordFile> {
+        let dir = self.config.dir.join(exchange).join(stream);
+        fs::create_dir_all(&dir)?;
+        // never append to a file of an earlier run, a gzip stream can not be reopened
+        let path = (0..)
+            .map(|index| dir.join(format!("{}.{:04}.log", date, index)))
+            .find(|path| !path.exists() && !path.with_extension("log.gz").exists())
+            .unwrap();
+        let out: Box<dyn Write + Send> = if self.config.compress {
+            let file = File::create(path.with_extension("log.gz"))?;
+            Box::new(BufWriter::new(GzEncoder::new(file, Compression::default())))
+        } else {
+            Box::new(BufWriter::new(File::create(path)?))
+        };
+        Ok(RecordFile {
+            date: date.to_string(),
+            opened: Instant::now(),
+            written: 0,
+            out,
+        })
+    }
+}
+
+// btcusdt@depth@100ms and market.btcusdt.depth.step0 are kept as they are
+fn file_name(stream: &str) -> String {
+    let name: String = stream
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || "@._-".contains(c) { c } else { '_' })
+        .collect();
+    if name.is_empty() || name.starts_with('.') {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_recorder_rotation() {
+        let dir = std::env::temp_dir().join(format!("rsex-recorder-{}", std::process::id()));
+        let mut config = RecorderConfig::new(&dir);
+        config.max_bytes = Some(40);
+        let recorder = Recorder::new(config);
+        recorder.record("binance", "btcusdt@depth@100ms", r#"{"u":1}"#).unwrap();
+        recorder.record("binance", "btcusdt@depth@100ms", r#"{"u":2}"#).unwrap();
+        recorder.record("binance", "btcusdt@depth@100ms", r#"{"u":3}"#).unwrap();
+        recorder.record("huobi", "market.btcusdt.depth.step0", r#"{"ch":1}"#).unwrap();
+        recorder.close();
+
+        let date = Utc::now().format("%Y-%m-%d").to_string();
+        let stream_dir = dir.join("binance").join("btcusdt@depth@100ms");
+        let mut files = fs::read_dir(&stream_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files, vec![format!("{}.0000.log.gz", date), format!("{}.0001.log.gz", date)]);
+
+        let mut content = String::new();
+        let file = File::open(stream_dir.join(&files[0])).unwrap();
+        GzDecoder::new(file).read_to_string(&mut content).unwrap();
+        let frames = content.lines().map(|line| line.split_once(' ').unwrap().1).collect::<Vec<_>>();
+        assert_eq!(frames, vec![r#"{"u":1}"#, r#"{"u":2}"#]);
+        assert!(dir.join("huobi").join("market.btcusdt.depth.step0").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+use crate::errors::*;
+
+use flate2::read::MultiGzDecoder;
+use log::warn;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// one line of a Recorder file
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedFrame {
+    // receive time, us since the epoch
+    pub received: u64,
+    pub stream: String,
+    pub frame: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaySpeed {
+    // sleeps the recorded gaps between frames
+    Original,
+    // recorded gaps divided by the factor
+    Accelerated(f64),
+    AsFastAsPossible,
+}
+
+// every stream recorded under <dir>/<exchange>, merged by receive time,
+// frames of one stream always keep their recorded order
+pub fn read_recording<P: AsRef<Path>>(dir: P, exchange: &str) -> APIResult<Vec<RecordedFrame>> {
+    let mut streams = vec![];
+    for entry in fs::read_dir(dir.as_ref().join(exchange))? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+        let stream = path.file_name().unwrap().to_string_lossy().to_string();
+        let mut files = fs::read_dir(&path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        // <yyyy-mm-dd>.<nnnn>.log sorts in recording order
+        files.sort();
+        let mut frames = vec![];
+        for file in files {
+            frames.extend(read_file(&file, &stream)?);
+        }
+        streams.push(frames);
+    }
+    Ok(merge(streams))
+}
+
+// a file of the recorder, a gzip stream cut short by a crash is read up to the cut
+pub fn read_file<P: AsRef<Path>>(path: P, stream: &str) -> APIResult<Vec<RecordedFrame>> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+    let reader: Box<dyn Read> = if path.extension().map_or(false, |extension| extension == "gz") {
+        Box::new(MultiGzDecoder::new(file))
+    } else {
+        Box::new(file)
+    };
+    let mut frames = vec![];
+    for line in BufReader::new(reader).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                warn!("stop reading {}: {}", path.display(), err);
+                break;
+            }
+        };
+        let frame = line
+            .split_once(' ')
+            .and_then(|(received, frame)| Some((received.parse::<u64>().ok()?, frame)));
+        match frame {
+            Some((received, frame)) => frames.push(RecordedFrame {
+                received,
+                stream: stream.to_string(),
+                frame: frame.to_string(),
+            }),
+            None => warn!("skip malformed line in {}", path.display()),
+        }
+    }
+    Ok(frames)
+}
+
+fn merge(streams: Vec<Vec<RecordedFrame>>) -> Vec<RecordedFrame> {
+    let mut streams = streams.into_iter().map(|frames| frames.into_iter().peekable()).collect::<Vec<_>>();
+    let mut heads = BinaryHeap::new();
+    for (index, frames) in streams.iter_mut().enumerate() {
+        if let Some(frame) = frames.peek() {
+            heads.push(Reverse((frame.received, index)));
+        }
+    }
+    let mut merged = vec![];
+    while let Some(Reverse((_, index))) = heads.pop() {
+        merged.extend(streams[index].next());
+        if let Some(frame) = streams[index].peek() {
+            heads.push(Reverse((frame.received, index)));
+        }
+    }
+    merged
+}
+
+// hands frames to `on_frame` paced by `speed`
+pub fn replay<'f, I, F>(frames: I, speed: ReplaySpeed, mut on_frame: F)
+where
+    I: IntoIterator<Item = &'f RecordedFrame>,
+    F: FnMut(&RecordedFrame),
+{
+    let factor = match speed {
+        ReplaySpeed::Original => 1.0,
+        ReplaySpeed::Accelerated(factor) => factor,
+        ReplaySpeed::AsFastAsPossible => 0.0,
+    };
+    let mut origin = None;
+    for frame in frames {
+        if factor > 0.0 {
+            let (start, first) = *origin.get_or_insert((Instant::now(), frame.received));
+            let offset = frame.received.saturating_sub(first) as f64 / factor;
+            let due = start + Duration::from_micros(offset as u64);
+            let now = Instant::now();
+            if due > now {
+                thread::sleep(due - now);
+            }
+        }
+        on_frame(frame);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::recorder::*;
+
+    #[test]
+    fn test_read_and_replay() {
+        let dir = std::env::temp_dir().join(format!("rsex-replay-{}", std::process::id()));
+        let recorder = Recorder::new(RecorderConfig::new(&dir));
+        recorder.record("binance", "btcusdt@depth", "a1").unwrap();
+        recorder.record("binance", "ethusdt@depth", "b1").unwrap();
+        recorder.record("binance", "btcusdt@depth", "a2").unwrap();
+        recorder.close();
+
+        let frames = read_recording(&dir, "binance").unwrap();
+        assert_eq!(frames[1].stream, "ethusdt@depth");
+        let recorded = frames.iter().map(|frame| frame.frame.as_str()).collect::<Vec<_>>();
+        assert_eq!(recorded, vec!["a1", "b1", "a2"]);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let frames = vec![
+            RecordedFrame {
+                received: 0,
+                stream: "s".into(),
+                frame: "1".into(),
+            },
+            RecordedFrame {
+                received: 200_000,
+                stream: "s".into(),
+                frame: "2".into(),
+            },
+        ];
+        let start = Instant::now();
+        let mut seen = vec![];
+        replay(&frames, ReplaySpeed::Accelerated(2.0), |frame| seen.push(frame.frame.clone()));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(seen, vec!["1", "2"]);
+    }
+}
@@ -1,9 +1,14 @@
 use crate::errors::*;
 use crate::models::*;
+use crate::websocket::Ack;
 
 use log::{ error};
+use std::collections::HashMap;
 
 
+// not every exchange serves every call, these fail on huobi:
+// - get_avg_price returns Err, huobi has no average price endpoint
+// - get_history_orders is not implemented and panics
 pub trait SpotRest {
     fn get_symbol(&self, base_currency: &str, trade_currency: &str) -> String;
     fn get_symbols(&self) -> APIResult<Vec<SymbolInfo>>;
@@ -114,12 +119,60 @@ pub trait SpotRest {
 
     fn get_orderbook(&self, symbol: &str, depth: u32) -> APIResult<Orderbook>;
     fn get_ticker(&self, symbol: &str) -> APIResult<Ticker>;
+    // one request for every market, keyed by the exchange's symbol
+    fn get_all_book_tickers(&self) -> APIResult<HashMap<String, Ticker>>;
+    fn get_all_prices(&self) -> APIResult<HashMap<String, f64>>;
+    fn get_24h_stats(&self, symbol: &str) -> APIResult<DayStats>;
+    fn get_all_24h_stats(&self) -> APIResult<Vec<DayStats>>;
+    // binance averages the last 5 minutes, Err on huobi
+    fn get_avg_price(&self, symbol: &str) -> APIResult<f64>;
+    // the latest public trades, oldest first
+    fn get_recent_trades(&self, symbol: &str, limit: u16) -> APIResult<Vec<Trade>>;
     fn get_kline(&self, symbol: &str, period: &str, limit: u16) -> APIResult<Vec<Kline>>;
+    // every candle opening in [start, end] (ms since the epoch), paged as needed,
+    // sorted by timestamp without duplicates
+    fn get_kline_range(&self, symbol: &str, interval: KlineInterval, start: u64, end: u64) -> APIResult<Vec<Kline>>;
 
     fn query_buy_price(&self, symbol:&str, amount:f64) -> (f64, bool);
     fn query_sell_price(&self, symbol:&str, amount:f64) -> (f64, bool);
 }
 
+// `isolated_symbol` picks the isolated margin of a pair, None the cross margin account
+pub trait MarginRest {
+    // returns the exchange's id of the loan
+    fn margin_borrow(&self, asset: &str, amount: f64, isolated_symbol: Option<&str>) -> APIResult<String>;
+    fn margin_repay(&self, asset: &str, amount: f64, isolated_symbol: Option<&str>) -> APIResult<String>;
+    fn max_borrowable(&self, asset: &str, isolated_symbol: Option<&str>) -> APIResult<f64>;
+    fn get_margin_account(&self, isolated_symbol: Option<&str>) -> APIResult<MarginAccount>;
+    // newest first
+    fn get_loan_history(&self, asset: &str, isolated_symbol: Option<&str>) -> APIResult<Vec<MarginLoan>>;
+    // binance lists every interest charge, huobi has no such history and gives one entry
+    // per loan with the interest accrued on it so far
+    fn get_interest_history(&self, asset: &str, isolated_symbol: Option<&str>) -> APIResult<Vec<MarginInterest>>;
+}
+
+// withdraw only sends to destinations on the client's WithdrawWhitelist
+pub trait Wallet {
+    fn get_networks(&self, asset: &str) -> APIResult<Vec<WalletNetwork>>;
+    // the default network when `network` is None
+    fn get_deposit_address(&self, asset: &str, network: Option<&str>) -> APIResult<DepositAddress>;
+    // newest first
+    fn get_deposit_history(&self, asset: &str) -> APIResult<Vec<WalletTransfer>>;
+    fn get_withdraw_history(&self, asset: &str) -> APIResult<Vec<WalletTransfer>>;
+    // returns the id of the withdrawal
+    fn withdraw(
+        &self,
+        asset: &str,
+        network: Option<&str>,
+        address: &str,
+        tag: Option<&str>,
+        amount: f64,
+    ) -> APIResult<String>;
+    // binance has no api to cancel a withdrawal and always returns Err
+    fn cancel_withdraw(&self, id: &str) -> APIResult<bool>;
+    fn get_withdraw(&self, asset: &str, id: &str) -> APIResult<WalletTransfer>;
+}
+
 pub trait FutureRest {
 
     fn get_balance(&self, asset: &str) -> APIResult<Balance>;
@@ -142,20 +195,22 @@ pub trait FutureRest {
     fn get_kline(&self, symbol: &str, period: &str, limit: u16) -> APIResult<Vec<Kline>>;
 }
 
+// the Ack of a sub_* call resolves when the exchange confirms the subscription, called
+// before connect it is confirmed with the subscriptions sent on connect
 pub trait SpotWs {
-    fn sub_orderbook(&mut self, symbol: &str);
-    fn sub_kline(&mut self, symbol: &str, period: &str);
-    fn sub_ticker(&mut self, symbol: &str);
-    fn sub_trade(&mut self, symbol: &str);
+    fn sub_orderbook(&mut self, symbol: &str) -> Ack<()>;
+    fn sub_kline(&mut self, symbol: &str, period: &str) -> Ack<()>;
+    fn sub_ticker(&mut self, symbol: &str) -> Ack<()>;
+    fn sub_trade(&mut self, symbol: &str) -> Ack<()>;
 
-    fn sub_order_update(&mut self, symbol: &str);
+    fn sub_order_update(&mut self, symbol: &str) -> Ack<()>;
 }
 
 pub trait FutureWs {
-    fn sub_orderbook(&self, symbol: &str);
-    fn sub_kline(&self, symbol: &str, period: &str);
-    fn sub_ticker(&self, symbol: &str);
-    fn sub_trade(&self, symbol: &str);
+    fn sub_orderbook(&self, symbol: &str) -> Ack<()>;
+    fn sub_kline(&self, symbol: &str, period: &str) -> Ack<()>;
+    fn sub_ticker(&self, symbol: &str) -> Ack<()>;
+    fn sub_trade(&self, symbol: &str) -> Ack<()>;
 
-    fn sub_order_update(&self, symbol: &str);
+    fn sub_order_update(&self, symbol: &str) -> Ack<()>;
 }